@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tower_lsp::lsp_types::{CodeActionKind, DiagnosticSeverity, DiagnosticTag};
+
+use crate::textlint::{Dispatch, OffsetBasis};
+
+/// `workspace/didChangeConfiguration` で設定がネストされるセクション名。
+pub const SECTION: &str = "ichigyo";
+
+/// 設定で指定する診断の重大度。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    #[serde(alias = "info")]
+    Information,
+    Hint,
+}
+
+impl From<Severity> for DiagnosticSeverity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Information => DiagnosticSeverity::INFORMATION,
+            Severity::Hint => DiagnosticSeverity::HINT,
+        }
+    }
+}
+
+/// 設定で指定する診断のタグ。エディタは取り消し線や薄い表示で描画する。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tag {
+    Unnecessary,
+    Deprecated,
+}
+
+impl From<Tag> for DiagnosticTag {
+    fn from(tag: Tag) -> Self {
+        match tag {
+            Tag::Unnecessary => DiagnosticTag::UNNECESSARY,
+            Tag::Deprecated => DiagnosticTag::DEPRECATED,
+        }
+    }
+}
+
+/// glob に一致するドキュメントで使う textlint の設定ファイル。
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScopedConfig {
+    /// ワークスペースルートからの相対パスに対する glob (例: `docs/ja/**`)。
+    pub glob: String,
+    /// `--config` で渡す設定ファイル。相対パスはルート基準。
+    pub config_path: PathBuf,
+}
+
+/// サーバーの設定。`initializationOptions` と `workspace/didChangeConfiguration` の
+/// どちらからも [`Config::from_value`] で読み込む。省略したキーはデフォルト値になる。
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Config {
+    /// code action のタイトルに含めるメッセージの最大文字数。
+    pub title_max_length: usize,
+    /// hover でメッセージを折り返す文字数。code action のタイトルもこの文字数で切り詰める。
+    pub message_wrap_width: Option<usize>,
+    /// textlint プロセスの起動方法。
+    pub dispatch: Dispatch,
+    /// ルールやプラグインを解決する `node_modules` ディレクトリ。相対パスはルート基準。
+    pub modules_dir: Option<PathBuf>,
+    /// ルール ID → プリセット名。診断の source を `textlint:<preset>` にするために使う。
+    pub rule_preset_map: HashMap<String, String>,
+    /// ルールのカテゴリ (`typo` / `style` など) → 診断の重大度。ルール自身の severity より優先する。
+    pub category_severity_map: HashMap<String, Severity>,
+    /// ルール ID → 診断の重大度。`category_severity_map` とルール自身の severity より優先する。
+    /// プリセット内のルールは `<preset>/` を除いた名前でも引く。
+    pub rule_severity_overrides: HashMap<String, Severity>,
+    /// ルール ID → 診断に付けるタグ。プリセット内のルールは `<preset>/` を除いた名前でも引く。
+    pub rule_tags: HashMap<String, Tag>,
+    /// 返す code action の kind。`None` ならサポートしている全 kind。
+    pub code_action_kinds: Option<Vec<CodeActionKind>>,
+    /// fixAndFormat で textlint の fix 後に実行するフォーマッタの argv。空なら実行しない。
+    pub formatter: Vec<String>,
+    /// initialized 後に textlint を空打ちしてキャッシュを温めるか。
+    pub warmup: bool,
+    /// textlint が報告する fix.range の単位。
+    pub fix_offset_basis: OffsetBasis,
+    /// プロジェクトの `.textlintrc` を無視し、`rules` で指定したルールだけで lint するか。
+    pub no_textlintrc: bool,
+    /// textlint に `--rule` で渡すルール名。
+    pub rules: Vec<String>,
+    /// textlint に `--rulesdir` で渡すルールのディレクトリ。相対パスはワークスペースルート基準。
+    pub rules_dir: Option<PathBuf>,
+    /// textlint に `--preset` で渡すプリセット名。
+    pub preset: Option<String>,
+    /// `.txt` のファイルを lint するとき textlint に `--plugin @textlint/textlint-plugin-text` を渡すか。
+    pub force_plaintext_plugin: bool,
+    /// lint コマンドの前に付けるランチャーの argv。空なら直接起動する。
+    pub wrapper: Vec<String>,
+    /// lint コマンドをシェル経由で起動するときのシェルの argv (例: `["bash", "-lc"]`)。
+    pub shell: Vec<String>,
+    /// textlint に `--config` で渡す設定ファイル。相対パスはワークスペースルート基準。
+    /// `None` なら textlint 自身の設定ファイル探索に任せる。
+    pub textlint_config: Option<PathBuf>,
+    /// glob ごとの textlint の設定ファイル。最初に一致したものを使い、どれにも一致しなければ
+    /// `textlint_config` (それもなければ textlint 自身の設定ファイル探索) に任せる。
+    pub scoped_configs: Vec<ScopedConfig>,
+    /// 言語 ID → textlint の代わりに実行するコマンドの argv。`{file}` はファイルパスに置き換える。
+    pub runners: HashMap<String, Vec<String>>,
+    /// initialized 後、この時間 (ミリ秒) に開かれたドキュメントはまとめて lint する。0 なら無効。
+    pub startup_grace_period_ms: u64,
+    /// didChange から lint を始めるまでの待ち時間 (ミリ秒)。0 なら変更のたびにすぐ lint する。
+    pub lint_debounce_ms: u64,
+    /// 内容が同じでも lint し直すまでの時間 (ミリ秒)。`None` なら内容が変わるまで前回の結果を使う。
+    pub cache_ttl_ms: Option<u64>,
+    /// 診断を publish するたびに、重大度ごとの問題の数を `ichigyo/status` で知らせるか。
+    pub status_notifications: bool,
+    /// 問題があったドキュメントの問題が 0 件になったとき `window/showMessage` で知らせるか。
+    pub notify_on_clean: bool,
+    /// textlint がインストールされていないとき、導入方法を案内する診断を出すか。
+    pub notify_missing_install: bool,
+    /// 開いているファイルがディスク上で外部から変更されたとき lint し直すか。
+    pub relint_on_external_change: bool,
+    /// `ichigyo/didFocus` で通知されたドキュメントだけを lint し、ほかの診断は消すか。
+    pub active_document_only: bool,
+    /// fix の適用に確認が必要なルール。code action を明示的に呼び出したときだけ適用でき、
+    /// fix-all には含めない。
+    pub confirm_fix_rules: Vec<String>,
+    /// lint するファイルの拡張子 (`.` なし)。大文字小文字は区別しない。
+    /// ほかの拡張子のファイルは開いても保存しても textlint を起動しない。
+    pub target_extensions: Vec<String>,
+    /// textlint の実行ファイルのパス。相対パスはワークスペースルート基準。
+    pub textlint_path: Option<PathBuf>,
+    /// pull 型の診断 (`workspace/diagnostic` / `textDocument/diagnostic`) を提供するか。
+    /// initialize 時の値だけが有効。
+    pub workspace_diagnostics: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            title_max_length: 40,
+            message_wrap_width: None,
+            dispatch: Dispatch::default(),
+            modules_dir: None,
+            rule_preset_map: HashMap::new(),
+            category_severity_map: HashMap::new(),
+            rule_severity_overrides: HashMap::new(),
+            rule_tags: default_rule_tags(),
+            code_action_kinds: None,
+            formatter: Vec::new(),
+            warmup: true,
+            fix_offset_basis: OffsetBasis::default(),
+            no_textlintrc: false,
+            rules: Vec::new(),
+            rules_dir: None,
+            preset: None,
+            force_plaintext_plugin: false,
+            wrapper: Vec::new(),
+            shell: Vec::new(),
+            textlint_config: None,
+            scoped_configs: Vec::new(),
+            runners: HashMap::new(),
+            startup_grace_period_ms: 0,
+            lint_debounce_ms: 500,
+            cache_ttl_ms: None,
+            status_notifications: false,
+            notify_on_clean: false,
+            notify_missing_install: true,
+            relint_on_external_change: false,
+            active_document_only: false,
+            confirm_fix_rules: Vec::new(),
+            target_extensions: vec!["md".into(), "markdown".into(), "txt".into()],
+            textlint_path: None,
+            workspace_diagnostics: false,
+        }
+    }
+}
+
+/// 冗長な表現や使われなくなった表現を報告するルールのタグ。
+fn default_rule_tags() -> HashMap<String, Tag> {
+    [
+        ("ja-no-redundant-expression", Tag::Unnecessary),
+        ("ja-no-successive-word", Tag::Unnecessary),
+        ("no-doubled-conjunction", Tag::Unnecessary),
+        ("no-hankaku-kana", Tag::Deprecated),
+    ]
+    .into_iter()
+    .map(|(rule, tag)| (rule.to_string(), tag))
+    .collect()
+}
+
+/// `{"ichigyo": {...}}` のようにセクションでネストされた設定値なら中身を取り出す。
+pub fn unwrap_section(value: Value) -> Value {
+    match value {
+        Value::Object(mut map) if map.get(SECTION).is_some_and(Value::is_object) => {
+            map.remove(SECTION).unwrap_or_default()
+        }
+        value => value,
+    }
+}
+
+/// 受け取った設定値 `update` を、これまでの設定値 `settings` に重ねる。
+/// `update` にないキーは元の値を残し、`null` のキーは取り除いてデフォルト値に戻す。
+pub fn merge_settings(settings: &mut Map<String, Value>, update: Map<String, Value>) {
+    for (key, value) in update {
+        if value.is_null() {
+            settings.remove(&key);
+        } else {
+            settings.insert(key, value);
+        }
+    }
+}
+
+impl Config {
+    /// JSON の設定値を読み込む。`{"ichigyo": {...}}` のようにセクションで
+    /// ネストされていても受け付ける。値が不正な項目はデフォルト値になり、他の項目は活かす。
+    pub fn from_value(value: Value) -> Self {
+        Self::from_value_with_errors(value).0
+    }
+
+    /// [`Config::from_value`] と同じように読み込み、読み込めなかった項目のエラーも返す。
+    /// オブジェクトでない値は丸ごとデフォルト設定にする。
+    pub fn from_value_with_errors(value: Value) -> (Self, Vec<String>) {
+        let value = match unwrap_section(value) {
+            Value::Null => return (Self::default(), Vec::new()),
+            value => value,
+        };
+        let Value::Object(map) = value else {
+            return (
+                Self::default(),
+                vec![format!("expected an object: {value}")],
+            );
+        };
+
+        // 項目ごとに読んでみて、読めない項目だけを捨てる
+        let mut errors = Vec::new();
+        let mut valid = serde_json::Map::new();
+        for (key, field) in map {
+            let single = Value::Object([(key.clone(), field.clone())].into_iter().collect());
+            match serde_json::from_value::<Self>(single) {
+                Ok(_) => {
+                    valid.insert(key, field);
+                }
+                Err(e) => errors.push(format!("{key}: {e}")),
+            }
+        }
+        let config = serde_json::from_value(Value::Object(valid)).unwrap_or_default();
+        (config, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn category_severity_map_is_parsed() {
+        let config = Config::from_value(serde_json::json!({
+            "categorySeverityMap": { "typo": "error", "style": "hint" }
+        }));
+        assert_eq!(config.category_severity_map["typo"], Severity::Error);
+
+        assert_eq!(
+            DiagnosticSeverity::from(config.category_severity_map["style"]),
+            DiagnosticSeverity::HINT
+        );
+    }
+
+    #[test]
+    fn rule_severity_overrides_accept_info_alias() {
+        let config = Config::from_value(json!({
+            "ruleSeverityOverrides": { "prh": "info", "no-todo": "information" }
+        }));
+        assert_eq!(config.rule_severity_overrides["prh"], Severity::Information);
+        assert_eq!(
+            config.rule_severity_overrides["no-todo"],
+            Severity::Information
+        );
+    }
+
+    #[test]
+    fn rule_tags_are_parsed() {
+        let config = Config::from_value(json!({
+            "ruleTags": { "no-todo": "deprecated" }
+        }));
+        assert_eq!(config.rule_tags.len(), 1);
+        assert_eq!(
+            DiagnosticTag::from(config.rule_tags["no-todo"]),
+            DiagnosticTag::DEPRECATED
+        );
+    }
+
+    #[test]
+    fn default_config() {
+        let config = Config::default();
+        assert_eq!(config.title_max_length, 40);
+        assert_eq!(config.dispatch, Dispatch::Async);
+        assert!(config.modules_dir.is_none());
+        assert!(config.rule_preset_map.is_empty());
+        assert_eq!(
+            config.rule_tags.get("ja-no-redundant-expression"),
+            Some(&Tag::Unnecessary)
+        );
+        assert!(config.code_action_kinds.is_none());
+        assert!(config.formatter.is_empty());
+        assert!(config.warmup);
+        assert_eq!(config.lint_debounce_ms, 500);
+        assert!(config.cache_ttl_ms.is_none());
+        assert_eq!(config.fix_offset_basis, OffsetBasis::Utf16);
+        assert!(config.notify_missing_install);
+        assert!(!config.relint_on_external_change);
+        assert!(!config.active_document_only);
+        assert!(!config.workspace_diagnostics);
+        assert!(!config.status_notifications);
+        assert_eq!(config.target_extensions, ["md", "markdown", "txt"]);
+
+        assert_eq!(Config::from_value(Value::Null), config);
+        assert_eq!(Config::from_value(json!({})), config);
+    }
+
+    #[test]
+    fn partial_override_keeps_other_defaults() {
+        let config = Config::from_value(json!({
+            "titleMaxLength": 10,
+            "dispatch": "blocking",
+            "warmup": false,
+        }));
+        assert_eq!(config.title_max_length, 10);
+        assert_eq!(config.dispatch, Dispatch::Blocking);
+        assert!(!config.warmup);
+        assert!(config.formatter.is_empty());
+        assert!(config.code_action_kinds.is_none());
+    }
+
+    #[test]
+    fn section_nested_settings_are_unwrapped() {
+        let config = Config::from_value(json!({
+            "ichigyo": {
+                "codeActionKinds": ["quickfix"],
+                "rulePresetMap": { "no-doubled-joshi": "ja-technical-writing" },
+            }
+        }));
+        assert_eq!(
+            config.code_action_kinds,
+            Some(vec![CodeActionKind::QUICKFIX])
+        );
+        assert_eq!(
+            config
+                .rule_preset_map
+                .get("no-doubled-joshi")
+                .map(String::as_str),
+            Some("ja-technical-writing")
+        );
+    }
+
+    #[test]
+    fn invalid_value_falls_back_to_default() {
+        let config = Config::from_value(json!({ "titleMaxLength": "long" }));
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn merge_settings_keeps_missing_keys_and_removes_null() {
+        let mut settings = Map::new();
+        merge_settings(
+            &mut settings,
+            json!({ "titleMaxLength": 10, "lintDebounceMs": 0 })
+                .as_object()
+                .unwrap()
+                .clone(),
+        );
+        merge_settings(
+            &mut settings,
+            json!({ "titleMaxLength": 20, "lintDebounceMs": null })
+                .as_object()
+                .unwrap()
+                .clone(),
+        );
+        assert_eq!(Value::Object(settings), json!({ "titleMaxLength": 20 }));
+    }
+
+    #[test]
+    fn invalid_value_keeps_other_settings() {
+        let (config, errors) = Config::from_value_with_errors(json!({
+            "titleMaxLength": "long",
+            "lintDebounceMs": 0,
+        }));
+        assert_eq!(config.title_max_length, Config::default().title_max_length);
+        assert_eq!(config.lint_debounce_ms, 0);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("titleMaxLength: "), "{errors:?}");
+
+        let (config, errors) = Config::from_value_with_errors(json!("text"));
+        assert_eq!(config, Config::default());
+        assert_eq!(errors.len(), 1);
+    }
+}
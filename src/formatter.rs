@@ -0,0 +1,99 @@
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::Context;
+use tokio::io::AsyncWriteExt;
+
+/// フォーマッタのコマンド引数中でドキュメントのパスに置き換えるプレースホルダ。
+pub const FILE_PLACEHOLDER: &str = "{file}";
+
+/// 外部フォーマッタを実行し、整形後のテキストを返す。
+///
+/// `command` は `["prettier", "--stdin-filepath", "{file}"]` のような argv で、
+/// `{file}` は `file_path` に置き換える。テキストは stdin で渡し、stdout を結果とする。
+pub async fn run(
+    command: &[String],
+    text: &str,
+    file_path: &Path,
+    work_dir: &Path,
+) -> anyhow::Result<String> {
+    let (program, args) = command
+        .split_first()
+        .context("formatter command is empty")?;
+    let file = file_path.to_string_lossy();
+    let args = args.iter().map(|arg| arg.replace(FILE_PLACEHOLDER, &file));
+
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .current_dir(work_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to spawn formatter `{program}`"))?;
+
+    let mut stdin = child.stdin.take().context("formatter stdin is not piped")?;
+    // 大きなテキストでは stdin を書き終える前に stdout が詰まるので、並行して読む
+    let write = async move {
+        let result = stdin.write_all(text.as_bytes()).await;
+        drop(stdin);
+        result
+    };
+    let (written, output) = tokio::join!(write, child.wait_with_output());
+    // stdin を読まずに終了するコマンドもあるので BrokenPipe は無視する
+    if let Err(e) = written {
+        if e.kind() != std::io::ErrorKind::BrokenPipe {
+            return Err(e.into());
+        }
+    }
+
+    let output = output?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("formatter `{program}` failed: {}", stderr.trim());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_pipes_text_through_command() {
+        let command = vec!["tr".to_string(), "a-z".to_string(), "A-Z".to_string()];
+        let formatted = run(&command, "# title\n", Path::new("a.md"), Path::new("."))
+            .await
+            .unwrap();
+        assert_eq!(formatted, "# TITLE\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_substitutes_file_placeholder() {
+        let command = vec!["echo".to_string(), "path={file}".to_string()];
+        let output = run(&command, "", Path::new("/docs/a.md"), Path::new("."))
+            .await
+            .unwrap();
+        assert_eq!(output, "path=/docs/a.md\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_handles_output_larger_than_pipe_buffer() {
+        let text = "a".repeat(1 << 20);
+        let command = vec!["cat".to_string()];
+        let formatted = run(&command, &text, Path::new("a.md"), Path::new("."))
+            .await
+            .unwrap();
+        assert_eq!(formatted.len(), text.len());
+    }
+
+    #[tokio::test]
+    async fn run_rejects_empty_command() {
+        assert!(run(&[], "text", Path::new("a.md"), Path::new("."))
+            .await
+            .is_err());
+    }
+}
@@ -1,2 +1,4 @@
+pub mod config;
+pub mod formatter;
 pub mod server;
 pub mod textlint;
@@ -1,17 +1,14 @@
-mod server;
-mod textlint;
+use tower_lsp::Server;
 
-use tower_lsp::{LspService, Server};
-
-use server::Backend;
-use textlint::CommandRunner;
+use ichigyo_ls::server::Backend;
+use ichigyo_ls::textlint::CommandRunner;
 
 #[tokio::main]
 async fn main() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::new(|client| Backend::new(client, CommandRunner));
+    let (service, socket) = Backend::service(CommandRunner);
 
     Server::new(stdin, stdout, socket).serve(service).await;
 }
@@ -1,42 +1,331 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
-use dashmap::DashMap;
-use tower_lsp::jsonrpc::Result;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use dashmap::{DashMap, DashSet};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tower_lsp::jsonrpc::{Error, Result};
 use tower_lsp::lsp_types::*;
-use tower_lsp::{Client, LanguageServer};
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService};
 
-use crate::textlint::{self, PositionEncoding, TextlintMessage, TextlintRunner};
+use crate::config::{self, Config};
+use crate::formatter;
+use crate::textlint::{
+    self, InvalidFixRange, PositionEncoding, RunOptions, TextlintMessage, TextlintRunner,
+};
+
+/// 指定位置の fix を適用するコマンド。引数は `[uri, position, ruleId]`。
+const APPLY_FIX_AT_COMMAND: &str = "ichigyo.applyFixAt";
+
+/// 現在の問題をベースラインとして記録するコマンド。引数は `[uri]` (省略時は全ドキュメント)。
+const SNAPSHOT_BASELINE_COMMAND: &str = "ichigyo.snapshotBaseline";
+
+/// textlint の fix をすべて適用し、設定があればフォーマッタも実行するコマンド。引数は `[uri]`。
+const FIX_AND_FORMAT_COMMAND: &str = "ichigyo.fixAndFormat";
+
+/// 保存しているメッセージを textlint の JSON 形式で返すコマンド。引数は `[uri]`。
+const EXPORT_TEXTLINT_JSON_COMMAND: &str = "ichigyo.exportTextlintJson";
+
+/// lint 済みのドキュメントをすべて lint し直すコマンド。引数なし。
+const RELINT_ALL_COMMAND: &str = "ichigyo.relintAll";
+
+/// 診断用に textlint のバージョンやサーバーの状態を返すコマンド。引数なし。
+const DOCTOR_COMMAND: &str = "ichigyo.doctor";
+
+/// ルール ID を `window/showMessage` で表示し、結果としても返すコマンド。引数は `[ruleId]`。
+const COPY_RULE_ID_COMMAND: &str = "ichigyo.copyRuleId";
+
+/// 自動修正できる問題をまとめて `workspace/applyEdit` で適用するコマンド。引数は `[uri]`。
+const FIX_ALL_COMMAND: &str = "ichigyo.fixAll";
+
+/// 待ち時間なしでドキュメントをすぐに lint し直すコマンド。引数は `[uri]`。
+const LINT_NOW_COMMAND: &str = "ichigyo.lintNow";
+
+/// ドキュメントにフォーカスが戻ったことを伝えるカスタム通知。受け取ると lint し直す。
+const DID_FOCUS_METHOD: &str = "ichigyo/didFocus";
+
+/// publish した診断の数を知らせるカスタム通知。`statusNotifications` が有効なときだけ送る。
+const STATUS_METHOD: &str = "ichigyo/status";
+
+/// 変更を監視する textlint 設定ファイルの glob。
+const TEXTLINT_CONFIG_GLOB: &str = "**/.textlintrc*";
+
+/// ファイル監視の動的登録の ID。設定が変わったら同じ ID で登録し直す。
+const WATCHERS_REGISTRATION_ID: &str = "ichigyo-ls/watched-files";
+
+/// textlint が見つからないときに出す案内。
+const MISSING_INSTALL_MESSAGE: &str = "textlint が見つかりません。`npm install --save-dev textlint` でワークスペースにインストールするか、PATH の通った場所に textlint を用意してください。";
+
+/// 複数のドキュメントをまとめて lint するときの同時実行数。
+const LINT_CONCURRENCY: usize = 4;
+
+/// lint がこれより長くかかったときだけ進捗を表示する。
+const LINT_PROGRESS_DELAY: Duration = Duration::from_millis(200);
+
+/// lint の失敗を `window/showMessage` で知らせる最短の間隔。
+const FAILURE_NOTICE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// このサーバーが返しうる code action の kind。空の kind は「Copy rule ID」に使う。
+const SUPPORTED_CODE_ACTION_KINDS: &[CodeActionKind] = &[
+    CodeActionKind::QUICKFIX,
+    CodeActionKind::SOURCE_FIX_ALL,
+    CodeActionKind::EMPTY,
+];
+
+/// lint 後に編集され、fix を当てられないときの理由。
+const STALE_FIX_REASON: &str = "document changed since last lint; waiting for re-lint";
+
+/// ドキュメント全体の fix をまとめて適用する code action のタイトル。
+const FIX_ALL_TITLE: &str = "Fix all auto-fixable textlint problems";
 
 pub struct Backend<R: TextlintRunner> {
+    inner: Arc<Inner<R>>,
+}
+
+impl<R: TextlintRunner> Clone for Backend<R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<R: TextlintRunner> std::ops::Deref for Backend<R> {
+    type Target = Inner<R>;
+
+    fn deref(&self) -> &Inner<R> {
+        &self.inner
+    }
+}
+
+/// サーバーの状態。ハンドラの外で動くタスクからも使えるよう、[`Backend`] が Arc で共有する。
+pub struct Inner<R: TextlintRunner> {
     client: Client,
-    runner: R,
+    runner: Arc<R>,
     root_dir: OnceLock<PathBuf>,
     position_encoding: OnceLock<PositionEncoding>,
-    /// URI → (ファイル内容, Vec<TextlintMessage>) を保持。
-    /// code_action で fix 情報を参照するために使う。
-    state: DashMap<Url, (String, Vec<TextlintMessage>)>,
+    client_capabilities: OnceLock<ClientCapabilities>,
+    config: ArcSwap<Config>,
+    /// これまでに受け取った設定値。一部のキーだけを送ってきても、ほかのキーの値を残すために使う。
+    settings: std::sync::Mutex<serde_json::Map<String, Value>>,
+    /// URI → ドキュメントの状態。code_action で fix 情報を参照するために使う。
+    state: DashMap<Url, DocumentState>,
+    /// URI → ベースラインとして記録した既存の問題。これらは publish しない。
+    baseline: DashMap<Url, HashSet<BaselineKey>>,
+    /// URI → lint の排他ロック。同じドキュメントの lint を直列化し、publish の順序を保つ。
+    lint_locks: DashMap<Url, Arc<tokio::sync::Mutex<()>>>,
+    /// URI → lint を始めるたびに増やす世代。新しい lint が始まった古い lint は publish しない。
+    lint_generations: DashMap<Url, u64>,
+    /// textlint が見つからず lint できなかったドキュメント。案内の診断を出し続ける。
+    missing_install: DashSet<Url>,
+    /// URI → エディタ上の最新の内容。lint 後に編集された fix を検出するために使う。
+    buffers: DashMap<Url, Buffer>,
+    /// JSON の textlint 設定ファイル → その内容。publish のたびにディスクを読まないよう覚えておき、
+    /// すべて lint し直すときに読み直す。
+    rule_config_texts: DashMap<PathBuf, String>,
+    /// initialized を受け取った時刻。起動直後の猶予期間の判定に使う。
+    initialized_at: OnceLock<Instant>,
+    /// 猶予期間中に開かれたドキュメント (URI → 言語 ID)。猶予期間の終わりにまとめて lint する。
+    startup_opens: DashMap<Url, String>,
+    /// 猶予期間の終わりに startup_opens を lint する処理が予約済みか。
+    startup_flush_scheduled: AtomicBool,
+    /// URI → didChange による lint を始める時刻。lint を待っているドキュメントだけが入る。
+    pending_lints: DashMap<Url, tokio::time::Instant>,
+    /// 最後に lint の失敗を知らせた時刻。
+    last_failure_notice: std::sync::Mutex<Option<Instant>>,
+    /// `activeDocumentOnly` で lint の対象にしているドキュメント。
+    active_document: ArcSwapOption<Url>,
+    /// `textlint --version` の結果。初回の問い合わせ時に一度だけ調べる。
+    textlint_version: tokio::sync::OnceCell<Option<String>>,
 }
 
-fn negotiate_encoding(params: &InitializeParams) -> (PositionEncoding, PositionEncodingKind) {
-    if let Some(ref general) = params.capabilities.general {
-        if let Some(ref encodings) = general.position_encodings {
-            // UTF-16 が LSP デフォルト。クライアントがサポートしていれば優先する。
-            for enc in encodings {
-                if *enc == PositionEncodingKind::UTF16 {
-                    return (PositionEncoding::Utf16, PositionEncodingKind::UTF16);
-                }
+/// ベースライン比較用のキー。行番号ではなく行の内容で位置を正規化し、
+/// 上に行が増減しても同じ問題として扱えるようにする。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BaselineKey {
+    rule_id: String,
+    message: String,
+    line_text: String,
+}
+
+impl BaselineKey {
+    fn new(text: &str, msg: &TextlintMessage) -> Self {
+        let line_text = text
+            .lines()
+            .nth(msg.line.saturating_sub(1) as usize)
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        Self {
+            rule_id: msg.rule_id.clone(),
+            message: msg.message.clone(),
+            line_text,
+        }
+    }
+}
+
+/// lint の仕方。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LintMode {
+    /// 最後に lint した内容と同じなら textlint を実行しない。
+    Cached,
+    /// 内容が変わっていなくても textlint を実行する。
+    Forced,
+    /// textlint を実行して状態を更新するだけで、publish しない。
+    /// pull 型の診断で結果をレスポンスとして返すときに使う。
+    Report,
+}
+
+/// lint 済みドキュメントの状態。
+struct DocumentState {
+    /// textlint が解析したファイル内容。
+    text: String,
+    messages: Vec<TextlintMessage>,
+    /// didOpen で通知された言語 ID。開かれずに保存されたファイルは拡張子から推測する。
+    language_id: String,
+    /// lint を始めた時点のエディタ上のバージョン。開かれていないファイルは `None`。
+    version: Option<i32>,
+    /// 最後に publish した診断。同じ診断を publish し直さないために使う。
+    published: Option<Vec<Diagnostic>>,
+    /// lint 後の編集に合わせて動かした各メッセージの範囲 (`messages` と同じ順)。
+    /// 編集で書き換えられた問題は `None`。lint 後に編集されていなければ `None`。
+    shifted: Option<Vec<Option<Range>>>,
+    /// lint した時刻。`cacheTtlMs` を過ぎた結果は内容が同じでも使わない。
+    linted_at: tokio::time::Instant,
+}
+
+impl DocumentState {
+    /// 編集 `change` に合わせて問題の範囲を動かし、範囲が編集と重なる問題を除く。
+    /// 除いた問題があれば `true`。
+    fn apply_change(
+        &mut self,
+        change: &TextDocumentContentChangeEvent,
+        encoding: PositionEncoding,
+    ) -> bool {
+        let text = &self.text;
+        let shifted = self.shifted.get_or_insert_with(|| {
+            self.messages
+                .iter()
+                .map(|msg| Some(lsp_range(textlint::message_range(text, msg, encoding))))
+                .collect()
+        });
+        let mut dropped = false;
+        for slot in shifted.iter_mut() {
+            let Some(range) = *slot else {
+                continue;
+            };
+            let Some(edited) = change.range else {
+                // 全文の置き換えでは位置を追えない。
+                *slot = None;
+                dropped = true;
+                continue;
+            };
+            if range.end < edited.start {
+                continue;
             }
-            for enc in encodings {
-                if *enc == PositionEncodingKind::UTF32 {
-                    return (PositionEncoding::Utf32, PositionEncodingKind::UTF32);
-                }
+            if range.start <= edited.end {
+                *slot = None;
+                dropped = true;
+                continue;
             }
-            for enc in encodings {
-                if *enc == PositionEncodingKind::UTF8 {
-                    return (PositionEncoding::Utf8, PositionEncodingKind::UTF8);
-                }
+            *slot = Some(Range::new(
+                shift_position(range.start, edited, &change.text, encoding),
+                shift_position(range.end, edited, &change.text, encoding),
+            ));
+        }
+        dropped
+    }
+}
+
+/// `edited` を `new_text` で置き換えたあとの `position` の位置。
+/// `position` は `edited` より後ろにあること。
+fn shift_position(
+    position: Position,
+    edited: Range,
+    new_text: &str,
+    encoding: PositionEncoding,
+) -> Position {
+    let inserted_lines = new_text.matches('\n').count() as u32;
+    let last_line = new_text.rsplit('\n').next().unwrap_or_default();
+    let last_width = textlint::end_position(last_line, encoding).character;
+    if position.line == edited.end.line {
+        let start = if inserted_lines == 0 {
+            edited.start.character + last_width
+        } else {
+            last_width
+        };
+        Position::new(
+            edited.start.line + inserted_lines,
+            start + position.character - edited.end.character,
+        )
+    } else {
+        Position::new(
+            position.line - (edited.end.line - edited.start.line) + inserted_lines,
+            position.character,
+        )
+    }
+}
+
+/// `ichigyo/didFocus` 通知のパラメータ。
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidFocusParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// エディタ上のドキュメントの最新の内容。didOpen / didChange で更新する。
+struct Buffer {
+    version: i32,
+    text: String,
+}
+
+/// didChange の変更を 1 件 `text` に適用する。`range` がなければ全文の置き換え。
+/// `range` は `encoding` の単位で、本文の範囲外はクランプする。
+fn apply_content_change(
+    text: &mut String,
+    change: TextDocumentContentChangeEvent,
+    encoding: PositionEncoding,
+) {
+    let Some(range) = change.range else {
+        *text = change.text;
+        return;
+    };
+    let offset = |pos: Position| {
+        textlint::position_to_byte_offset(
+            text,
+            textlint::Position {
+                line: pos.line,
+                character: pos.character,
+            },
+            encoding,
+        )
+    };
+    let start = offset(range.start);
+    let end = offset(range.end).max(start);
+    text.replace_range(start..end, &change.text);
+}
+
+fn negotiate_encoding(params: &InitializeParams) -> (PositionEncoding, PositionEncodingKind) {
+    // textlint の出力から変換しやすい順に UTF-8 > UTF-32 > UTF-16 で選ぶ
+    const PREFERENCE: [(PositionEncoding, PositionEncodingKind); 3] = [
+        (PositionEncoding::Utf8, PositionEncodingKind::UTF8),
+        (PositionEncoding::Utf32, PositionEncodingKind::UTF32),
+        (PositionEncoding::Utf16, PositionEncodingKind::UTF16),
+    ];
+    if let Some(encodings) = params
+        .capabilities
+        .general
+        .as_ref()
+        .and_then(|general| general.position_encodings.as_ref())
+    {
+        for (encoding, kind) in PREFERENCE {
+            if encodings.contains(&kind) {
+                return (encoding, kind);
             }
         }
     }
@@ -44,327 +333,6964 @@ fn negotiate_encoding(params: &InitializeParams) -> (PositionEncoding, PositionE
     (PositionEncoding::Utf16, PositionEncodingKind::UTF16)
 }
 
-impl<R: TextlintRunner> Backend<R> {
-    pub fn new(client: Client, runner: R) -> Self {
-        Self {
-            client,
-            runner,
-            root_dir: OnceLock::new(),
-            position_encoding: OnceLock::new(),
-            state: DashMap::new(),
-        }
+/// 拡張子から LSP の言語 ID を推測する。不明な拡張子は `plaintext` とみなす。
+fn language_id_from_path(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match ext.as_deref() {
+        Some("md" | "markdown") => "markdown",
+        Some("rst") => "restructuredtext",
+        Some("adoc" | "asciidoc") => "asciidoc",
+        Some("tex") => "latex",
+        Some("html" | "htm") => "html",
+        _ => "plaintext",
     }
+}
 
-    fn encoding(&self) -> PositionEncoding {
-        self.position_encoding.get().copied().unwrap_or_default()
-    }
+/// ルール ID をキーにした設定の値。プリセット内のルールは最後の `/` 以降の名前でも引く。
+fn lookup_rule<'a, V>(map: &'a HashMap<String, V>, rule_id: &str) -> Option<&'a V> {
+    map.get(rule_id).or_else(|| {
+        let (_, rule) = rule_id.rsplit_once('/')?;
+        map.get(rule)
+    })
+}
 
-    async fn lint_and_publish(&self, uri: &Url) {
-        let path = match uri.to_file_path() {
-            Ok(p) => p,
-            Err(()) => return,
-        };
+/// `uri` が [`TEXTLINT_CONFIG_GLOB`] に一致する textlint の設定ファイルか。
+fn is_textlint_config(uri: &Url) -> bool {
+    uri.to_file_path().is_ok_and(|path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(".textlintrc"))
+    })
+}
 
-        let work_dir = match self.root_dir.get() {
-            Some(d) => d.clone(),
-            None => match path.parent() {
-                Some(p) => p.to_path_buf(),
-                None => return,
-            },
-        };
+/// ルール ID を `ichigyo.copyRuleId` で返す code action。問題を直すものではないので
+/// quickfix にはせず、kind を空にする。
+fn copy_rule_id_action(rule_id: &str) -> CodeAction {
+    CodeAction {
+        title: format!("Copy rule ID: {rule_id}"),
+        kind: Some(CodeActionKind::EMPTY),
+        command: Some(Command {
+            title: format!("Copy rule ID: {rule_id}"),
+            command: COPY_RULE_ID_COMMAND.to_string(),
+            arguments: Some(vec![Value::String(rule_id.to_string())]),
+        }),
+        ..Default::default()
+    }
+}
 
-        let results = match self.runner.run(&path, &work_dir).await {
-            Ok(r) => r,
-            Err(_) => return,
-        };
+/// `kind` が `requested` と同じか、その下位の kind (`requested.xxx`) であるか。
+fn kind_matches(requested: &CodeActionKind, kind: &CodeActionKind) -> bool {
+    let requested = requested.as_str();
+    let kind = kind.as_str();
+    kind == requested
+        || kind
+            .strip_prefix(requested)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
 
-        // textlint が解析したファイルと同じ内容を読み込む。
-        // バッファテキスト (did_change) ではなくディスクのファイルを使うことで
-        // fix.range オフセットとの整合性を保証する。
-        let text = match tokio::fs::read_to_string(&path).await {
-            Ok(t) => t,
-            Err(_) => return,
-        };
+/// よく使われるルール → カテゴリの対応表。プリセット経由のルール ID は最後の要素で引く。
+const RULE_CATEGORIES: &[(&str, &str)] = &[
+    ("prh", "terminology"),
+    ("ja-no-abusage", "terminology"),
+    ("no-dropping-the-ra", "grammar"),
+    ("no-double-negative-ja", "grammar"),
+    ("no-doubled-joshi", "style"),
+    ("no-doubled-conjunction", "style"),
+    ("no-doubled-conjunctive-particle-ga", "style"),
+    ("no-mix-dearu-desumasu", "style"),
+    ("ja-no-mixed-period", "style"),
+    ("ja-no-redundant-expression", "style"),
+    ("ja-no-weak-phrase", "style"),
+    ("no-exclamation-question-mark", "style"),
+    ("sentence-length", "style"),
+    ("max-ten", "style"),
+    ("max-kanji-continuous-len", "style"),
+    ("ja-unnatural-alphabet", "typo"),
+    ("no-hankaku-kana", "typo"),
+    ("no-nfd", "typo"),
+    ("no-invalid-control-character", "typo"),
+    ("no-zero-width-spaces", "typo"),
+];
 
-        let messages: Vec<TextlintMessage> = results.into_iter().flat_map(|r| r.messages).collect();
-        let encoding = self.encoding();
+/// ルールのカテゴリ。対応表にないルールは `None`。
+fn rule_category(rule_id: &str) -> Option<&'static str> {
+    let name = rule_id.rsplit('/').next().unwrap_or(rule_id);
+    RULE_CATEGORIES
+        .iter()
+        .find(|(rule, _)| *rule == name)
+        .map(|(_, category)| *category)
+}
 
-        let diagnostics: Vec<Diagnostic> = messages
-            .iter()
-            .map(|msg| {
-                let line = msg.line.saturating_sub(1);
-                let col = textlint::textlint_column_to_character(&text, line, msg.column, encoding);
-                Diagnostic {
-                    range: Range {
-                        start: Position::new(line, col),
-                        end: Position::new(line, col),
-                    },
-                    severity: Some(match msg.severity {
-                        1 => DiagnosticSeverity::WARNING,
-                        _ => DiagnosticSeverity::ERROR,
-                    }),
-                    source: Some("textlint".to_string()),
-                    code: Some(NumberOrString::String(msg.rule_id.clone())),
-                    message: msg.message.clone(),
-                    ..Default::default()
-                }
-            })
-            .collect();
+/// `textlint-rule-<name>` 以外の名前で npm に公開されているルールのパッケージ名。
+const RULE_PACKAGES: &[(&str, &str)] = &[
+    (
+        "no-unmatched-pair",
+        "@textlint-rule/textlint-rule-no-unmatched-pair",
+    ),
+    (
+        "no-invalid-control-character",
+        "@textlint-rule/textlint-rule-no-invalid-control-character",
+    ),
+];
 
-        self.state.insert(uri.clone(), (text, messages));
-        self.client
-            .publish_diagnostics(uri.clone(), diagnostics, None)
-            .await;
+/// ルールのドキュメントの URL。npm のパッケージのページとみなす。
+///
+/// パッケージ名は対応表にあればそれを、なければ `textlint-rule-<name>` とする。
+/// `@scope/name` のルールは `@scope/textlint-rule-name`、プリセットのルール
+/// (`<preset>/<name>`) は `<name>` で探す。URL に使えない文字を含むルール ID は `None`。
+fn rule_doc_url(rule_id: &str) -> Option<Url> {
+    let package = match rule_id.strip_prefix('@').and_then(|id| id.split_once('/')) {
+        Some((scope, name)) => {
+            if !is_package_name(scope) {
+                return None;
+            }
+            format!("@{scope}/textlint-rule-{name}")
+        }
+        None => {
+            let name = rule_id.rsplit('/').next().unwrap_or(rule_id);
+            match RULE_PACKAGES.iter().find(|(rule, _)| *rule == name) {
+                Some((_, package)) => package.to_string(),
+                None => format!("textlint-rule-{name}"),
+            }
+        }
+    };
+    let name = package.rsplit('/').next().unwrap_or(&package);
+    if !is_package_name(name.trim_start_matches("textlint-rule-")) {
+        return None;
     }
+    Url::parse(&format!("https://www.npmjs.com/package/{package}")).ok()
 }
 
-#[tower_lsp::async_trait]
-impl<R: TextlintRunner> LanguageServer for Backend<R> {
-    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
-        let (encoding, encoding_kind) = negotiate_encoding(&params);
-        let _ = self.position_encoding.set(encoding);
+/// npm のパッケージ名 (スコープを除く) として URL にそのまま使える文字列か。
+fn is_package_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
 
-        if let Some(root_uri) = params.root_uri {
-            if let Ok(path) = root_uri.to_file_path() {
-                let _ = self.root_dir.set(path);
+/// [`STATUS_METHOD`] の通知。
+enum StatusNotification {}
+
+impl notification::Notification for StatusNotification {
+    type Params = StatusParams;
+    const METHOD: &'static str = STATUS_METHOD;
+}
+
+/// ドキュメントに publish した診断の重大度ごとの数。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusParams {
+    uri: Url,
+    errors: usize,
+    warnings: usize,
+    information: usize,
+    hints: usize,
+}
+
+impl StatusParams {
+    fn new(uri: Url, diagnostics: &[Diagnostic]) -> Self {
+        let mut status = Self {
+            uri,
+            errors: 0,
+            warnings: 0,
+            information: 0,
+            hints: 0,
+        };
+        for diagnostic in diagnostics {
+            match diagnostic.severity {
+                Some(DiagnosticSeverity::ERROR) => status.errors += 1,
+                Some(DiagnosticSeverity::WARNING) => status.warnings += 1,
+                Some(DiagnosticSeverity::INFORMATION) => status.information += 1,
+                Some(DiagnosticSeverity::HINT) => status.hints += 1,
+                _ => {}
             }
         }
+        status
+    }
+}
+
+/// 診断の `data` に載せる情報。クライアントが追加のリクエストなしに詳細を表示できるようにする。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DiagnosticData {
+    rule_id: String,
+    /// textlint の fix があるか。
+    fixable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    doc_url: Option<Url>,
+}
+
+impl DiagnosticData {
+    fn new(msg: &TextlintMessage) -> Self {
+        Self {
+            rule_id: msg.rule_id.clone(),
+            fixable: msg.fix.is_some(),
+            category: rule_category(&msg.rule_id).map(str::to_string),
+            doc_url: rule_doc_url(&msg.rule_id),
+        }
+    }
+}
 
-        Ok(InitializeResult {
-            capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
-                )),
-                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
-                position_encoding: Some(encoding_kind),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
+/// textlint の severity を LSP の DiagnosticSeverity に変換する。
+///
+/// 0: hint (一部のプリセットで情報扱い)、1: warning、2: error、3: information
+/// (プラグインが使う)。それ以外の値は見落とさないよう error として扱う。
+fn diagnostic_severity(severity: u32) -> DiagnosticSeverity {
+    match severity {
+        0 => DiagnosticSeverity::HINT,
+        1 => DiagnosticSeverity::WARNING,
+        3 => DiagnosticSeverity::INFORMATION,
+        _ => DiagnosticSeverity::ERROR,
     }
+}
 
-    async fn shutdown(&self) -> Result<()> {
-        Ok(())
+/// textlint モジュールの Range を LSP の Range に変換する。
+fn lsp_range(range: textlint::Range) -> Range {
+    Range {
+        start: Position::new(range.start.line, range.start.character),
+        end: Position::new(range.end.line, range.end.character),
     }
+}
 
-    async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let uri = params.text_document.uri;
-        self.lint_and_publish(&uri).await;
+/// textlint の結果を `path` のドキュメントの問題と、ほかのファイルの問題に分ける。
+///
+/// 結果が 1 つならすべて `path` のものとする (stdin の結果の `filePath` は当てにならない)。
+/// 複数あれば `filePath` を `work_dir` 基準で解決し、`path` と一致するか空なら `path` のもの、
+/// それ以外はファイルごとにまとめて返す。
+fn split_results(
+    path: &Path,
+    work_dir: &Path,
+    results: Vec<textlint::TextlintResult>,
+) -> (Vec<TextlintMessage>, Vec<(PathBuf, Vec<TextlintMessage>)>) {
+    if results.len() <= 1 {
+        let messages = results.into_iter().flat_map(|r| r.messages).collect();
+        return (messages, Vec::new());
     }
 
-    async fn did_change(&self, _params: DidChangeTextDocumentParams) {
-        // state のテキストは lint_and_publish でディスクから読むため、
-        // ここでは更新しない。did_change でテキストを上書きすると
-        // textlint の fix.range オフセットとの不整合が発生する。
+    let mut own = Vec::new();
+    let mut others: Vec<(PathBuf, Vec<TextlintMessage>)> = Vec::new();
+    for result in results {
+        let file_path = work_dir.join(&result.file_path);
+        if result.file_path.is_empty() || file_path == path {
+            own.extend(result.messages);
+        } else if let Some((_, messages)) = others.iter_mut().find(|(p, _)| *p == file_path) {
+            messages.extend(result.messages);
+        } else {
+            others.push((file_path, result.messages));
+        }
     }
+    (own, others)
+}
 
-    async fn did_save(&self, params: DidSaveTextDocumentParams) {
-        let uri = params.text_document.uri;
-        self.lint_and_publish(&uri).await;
+/// 修正後のテキスト `fixed` の末尾の改行の有無を `original` に合わせる。
+/// textlint の出力で末尾の改行が増減しても、フォーマットで余計な差分を出さないため。
+fn preserve_trailing_newline(original: &str, mut fixed: String) -> String {
+    match (original.ends_with('\n'), fixed.ends_with('\n')) {
+        (true, false) => fixed.push('\n'),
+        (false, true) => {
+            fixed.pop();
+            if fixed.ends_with('\r') {
+                fixed.pop();
+            }
+        }
+        _ => {}
     }
+    fixed
+}
 
-    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
-        let uri = &params.text_document.uri;
-        let entry = match self.state.get(uri) {
-            Some(e) => e,
-            None => return Ok(None),
-        };
-        let (text, messages) = entry.value();
-        let request_range = params.range;
+/// フォーマット要求の `insertFinalNewline` と `trimFinalNewlines` を `text` に適用する。
+/// 足す改行は本文で使われている改行コードに合わせる。
+fn apply_final_newline_options(mut text: String, options: &FormattingOptions) -> String {
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    if options.trim_final_newlines == Some(true) {
+        let trimmed = text.trim_end_matches(['\r', '\n']).len();
+        if trimmed < text.len() {
+            text.truncate(trimmed);
+            text.push_str(newline);
+        }
+    }
+    if options.insert_final_newline == Some(true) && !text.is_empty() && !text.ends_with('\n') {
+        text.push_str(newline);
+    }
+    text
+}
 
-        let mut actions = Vec::new();
+/// メッセージを最大 `max_chars` 文字に切り詰める。超えた場合は末尾を `…` にする。
+fn truncate_message(message: &str, max_chars: usize) -> String {
+    if message.chars().count() <= max_chars {
+        return message.to_string();
+    }
+    let mut truncated: String = message.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
 
-        for msg in messages {
-            let fix = match &msg.fix {
-                Some(f) => f,
-                None => continue,
-            };
+/// メッセージを `width` 文字ごとに改行する。Markdown の改行として行末に空白 2 つを付ける。
+fn wrap_message(message: &str, width: usize) -> String {
+    if width == 0 {
+        return message.to_string();
+    }
+    message
+        .lines()
+        .map(|line| {
+            let chars: Vec<char> = line.chars().collect();
+            chars
+                .chunks(width)
+                .map(|chunk| chunk.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("  \n")
+        })
+        .collect::<Vec<_>>()
+        .join("  \n")
+}
 
-            let msg_line = msg.line.saturating_sub(1);
-            if msg_line < request_range.start.line || msg_line > request_range.end.line {
-                continue;
-            }
+/// `<!-- textlint-disable a, b -->` のような `directive` のコメント行なら、指定されたルールを返す。
+/// ルールを指定していなければ空。
+fn directive_rules<'a>(line: &'a str, directive: &str) -> Option<Vec<&'a str>> {
+    let body = line
+        .trim()
+        .strip_prefix("<!--")?
+        .strip_suffix("-->")?
+        .trim()
+        .strip_prefix(directive)?;
+    if !body.is_empty() && !body.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(
+        body.split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .collect(),
+    )
+}
 
-            let encoding = self.encoding();
-            let start = textlint::offset_to_position(text, fix.range[0], encoding);
-            let end = textlint::offset_to_position(text, fix.range[1], encoding);
+/// `lines` の `line` 行目 (0-based) の、改行を除いた範囲。
+fn line_range(lines: &[&str], line: usize, encoding: PositionEncoding) -> Range {
+    let content = lines[line].strip_suffix('\r').unwrap_or(lines[line]);
+    let end = textlint::end_position(content, encoding);
+    Range::new(
+        Position::new(line as u32, 0),
+        Position::new(line as u32, end.character),
+    )
+}
 
-            let edit_range = Range {
-                start: Position::new(start.line, start.character),
-                end: Position::new(end.line, end.character),
-            };
+/// 拡張子の一覧に一致するファイルの glob。一覧が空なら `None`。
+fn extensions_glob(extensions: &[String]) -> Option<String> {
+    let extensions: Vec<&str> = extensions
+        .iter()
+        .map(|ext| ext.trim_start_matches('.'))
+        .collect();
+    match extensions.as_slice() {
+        [] => None,
+        [ext] => Some(format!("**/*.{ext}")),
+        _ => Some(format!("**/*.{{{}}}", extensions.join(","))),
+    }
+}
 
-            let mut changes = HashMap::new();
-            changes.insert(
-                uri.clone(),
-                vec![TextEdit {
-                    range: edit_range,
-                    new_text: fix.text.clone(),
-                }],
-            );
+/// `pos` が `range` に含まれるか。幅 0 の範囲は同じ行にあれば含むとみなす。
+fn range_contains(range: &Range, pos: Position) -> bool {
+    if range.start == range.end {
+        return range.start.line == pos.line;
+    }
+    let key = |p: &Position| (p.line, p.character);
+    key(&range.start) <= key(&pos) && key(&pos) <= key(&range.end)
+}
 
-            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
-                title: format!("Fix: {} ({})", msg.message, msg.rule_id),
-                kind: Some(CodeActionKind::QUICKFIX),
-                edit: Some(WorkspaceEdit {
-                    changes: Some(changes),
-                    ..Default::default()
-                }),
-                ..Default::default()
-            }));
+impl<R: TextlintRunner> Backend<R> {
+    pub fn new(client: Client, runner: R) -> Self {
+        let inner = Inner {
+            client,
+            runner: Arc::new(runner),
+            root_dir: OnceLock::new(),
+            position_encoding: OnceLock::new(),
+            client_capabilities: OnceLock::new(),
+            config: ArcSwap::from_pointee(Config::default()),
+            settings: std::sync::Mutex::new(serde_json::Map::new()),
+            state: DashMap::new(),
+            baseline: DashMap::new(),
+            lint_locks: DashMap::new(),
+            lint_generations: DashMap::new(),
+            missing_install: DashSet::new(),
+            buffers: DashMap::new(),
+            rule_config_texts: DashMap::new(),
+            initialized_at: OnceLock::new(),
+            startup_opens: DashMap::new(),
+            startup_flush_scheduled: AtomicBool::new(false),
+            pending_lints: DashMap::new(),
+            last_failure_notice: std::sync::Mutex::new(None),
+            active_document: ArcSwapOption::empty(),
+            textlint_version: tokio::sync::OnceCell::new(),
+        };
+        Self {
+            inner: Arc::new(inner),
         }
+    }
 
-        if actions.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(actions))
+    /// カスタム通知のハンドラを登録したサービスを作る。
+    pub fn service(runner: R) -> (LspService<Self>, ClientSocket) {
+        LspService::build(|client| Self::new(client, runner))
+            .custom_method(DID_FOCUS_METHOD, Self::did_focus)
+            .finish()
+    }
+
+    /// フォーカスが戻ったドキュメントを lint し直す。追跡していない URI は無視する。
+    /// `activeDocumentOnly` が有効なら、それまで対象だったドキュメントの診断を消す。
+    pub async fn did_focus(&self, params: DidFocusParams) {
+        let uri = params.text_document.uri;
+        if !self.state.contains_key(&uri) && !self.buffers.contains_key(&uri) {
+            return;
+        }
+        if self.config().active_document_only {
+            let previous = self.active_document.swap(Some(Arc::new(uri.clone())));
+            if let Some(previous) = previous.filter(|previous| **previous != uri) {
+                self.state.remove(&*previous);
+                self.client
+                    .publish_diagnostics((*previous).clone(), Vec::new(), None)
+                    .await;
+            }
         }
+        // フォーカスが外れている間に変わった辞書やルールを反映するため、内容が同じでも実行する
+        self.relint_and_publish(&uri).await;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::textlint::{FixCommand, TextlintResult};
-    use std::path::Path;
-    use std::sync::Mutex;
-    use tower_lsp::LspService;
+    /// `uri` の拡張子が `targetExtensions` に含まれるか (大文字小文字は区別しない)。
+    fn is_target_extension(&self, uri: &Url) -> bool {
+        let Ok(path) = uri.to_file_path() else {
+            return false;
+        };
+        let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+            return false;
+        };
+        self.config()
+            .target_extensions
+            .iter()
+            .any(|target| target.trim_start_matches('.').eq_ignore_ascii_case(ext))
+    }
 
-    struct MockRunner {
-        results: Mutex<Vec<TextlintResult>>,
+    /// `activeDocumentOnly` のとき、`uri` を lint してよいか。対象がまだなければ `uri` を対象にする。
+    fn is_active_document(&self, uri: &Url) -> bool {
+        if !self.config().active_document_only {
+            return true;
+        }
+        self.active_document
+            .compare_and_swap(&None::<Arc<Url>>, Some(Arc::new(uri.clone())));
+        self.active_document
+            .load()
+            .as_deref()
+            .is_some_and(|active| active == uri)
     }
 
-    impl MockRunner {
-        fn new(results: Vec<TextlintResult>) -> Self {
-            Self {
-                results: Mutex::new(results),
-            }
+    fn encoding(&self) -> PositionEncoding {
+        self.position_encoding.get().copied().unwrap_or_default()
+    }
+
+    /// `path` の textlint を実行するディレクトリ。ワークスペースルートがなければ
+    /// `path` の親ディレクトリ。どちらもなければ `None`。
+    fn resolve_work_dir(&self, path: &Path) -> Option<PathBuf> {
+        match self.root_dir.get() {
+            Some(root) => Some(root.clone()),
+            None => path.parent().map(Path::to_path_buf),
         }
     }
 
-    #[async_trait::async_trait]
-    impl TextlintRunner for MockRunner {
-        async fn run(
-            &self,
-            _file_path: &Path,
-            _work_dir: &Path,
-        ) -> anyhow::Result<Vec<TextlintResult>> {
-            let results = self.results.lock().unwrap().clone();
-            Ok(results)
+    /// 相対パスをワークスペースルート基準で解決する。ルートが未設定ならそのまま返す。
+    fn resolve_path(&self, path: &Path) -> PathBuf {
+        match self.root_dir.get() {
+            Some(root) if path.is_relative() => root.join(path),
+            _ => path.to_path_buf(),
         }
     }
 
-    #[tokio::test]
-    async fn initialize_returns_expected_capabilities() {
-        let runner = MockRunner::new(vec![]);
-        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+    fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
 
-        let params = InitializeParams::default();
-        let result = service.inner().initialize(params).await.unwrap();
+    /// 設定から textlint の実行オプションを作る。
+    fn run_options(&self) -> RunOptions {
+        let config = self.config();
+        RunOptions {
+            dispatch: config.dispatch,
+            // 存在しないディレクトリは textlint に渡しても意味がないので無視する
+            modules_dir: config
+                .modules_dir
+                .as_deref()
+                .map(|dir| self.resolve_path(dir))
+                .filter(|dir| dir.is_dir()),
+            command: None,
+            config_path: config
+                .textlint_config
+                .as_deref()
+                .map(|path| self.resolve_path(path)),
+            no_textlintrc: config.no_textlintrc,
+            rules: config.rules.clone(),
+            rules_dir: config
+                .rules_dir
+                .as_deref()
+                .map(|dir| self.resolve_path(dir)),
+            preset: config.preset.clone(),
+            force_plaintext_plugin: config.force_plaintext_plugin,
+            wrapper: config.wrapper.clone(),
+            shell: config.shell.clone(),
+            binary: self.textlint_binary(),
+        }
+    }
 
-        assert!(result.capabilities.code_action_provider.is_some());
-        assert!(result.capabilities.text_document_sync.is_some());
+    /// 起動する textlint。`textlintPath` が設定されていればそれを、なければワークスペースの
+    /// `node_modules/.bin/textlint` を使う。どちらもなければ `None` (`PATH` から探す)。
+    fn textlint_binary(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.config().textlint_path {
+            return Some(self.resolve_path(path));
+        }
+        textlint::local_binary(self.root_dir.get()?)
     }
 
-    #[tokio::test]
-    async fn code_action_returns_quickfix_for_fixable_message() {
-        let results = vec![TextlintResult {
-            file_path: "./test.md".to_string(),
-            messages: vec![TextlintMessage {
-                rule_id: "no-doubled-joshi".to_string(),
-                message: "助詞の重複".to_string(),
-                line: 1,
-                column: 5,
-                severity: 2,
-                fix: Some(FixCommand {
-                    range: [6, 7],
-                    text: "けれど".to_string(),
+    /// `path` (言語 ID は `language_id`) のドキュメントを lint するときの RunOptions。
+    /// `runners` に対応するコマンドがあれば textlint の代わりにそれを使い、
+    /// `scopedConfigs` に一致する glob があれば `textlintConfig` より優先してその設定ファイルを使う。
+    fn run_options_for(&self, path: &Path, language_id: &str) -> RunOptions {
+        let config = self.config();
+        let base = self.run_options();
+        RunOptions {
+            command: config
+                .runners
+                .get(language_id)
+                .filter(|command| !command.is_empty())
+                .cloned(),
+            config_path: self
+                .scoped_config_path(path)
+                .or_else(|| base.config_path.clone()),
+            ..base
+        }
+    }
+
+    /// `path` に一致する最初の `scopedConfigs` の設定ファイル。不正な glob は無視する。
+    fn scoped_config_path(&self, path: &Path) -> Option<PathBuf> {
+        let config = self.config();
+        let relative = match self.root_dir.get() {
+            Some(root) => path.strip_prefix(root).unwrap_or(path),
+            None => path,
+        };
+        config
+            .scoped_configs
+            .iter()
+            .find(|scope| {
+                globset::Glob::new(&scope.glob)
+                    .map(|glob| glob.compile_matcher().is_match(relative))
+                    .unwrap_or(false)
+            })
+            .map(|scope| self.resolve_path(&scope.config_path))
+    }
+
+    /// code action のタイトルに含めるメッセージの最大文字数。
+    /// `messageWrapWidth` が設定されていれば、タイトルは折り返せないのでその幅でも切り詰める。
+    fn title_length(&self) -> usize {
+        let config = self.config();
+        match config.message_wrap_width {
+            Some(width) if width > 0 => config.title_max_length.min(width),
+            _ => config.title_max_length,
+        }
+    }
+
+    /// 設定で `kind` の code action を返すことが許可されているか。
+    fn offers_kind(&self, kind: &CodeActionKind) -> bool {
+        let config = self.config();
+        config
+            .code_action_kinds
+            .as_deref()
+            .unwrap_or(SUPPORTED_CODE_ACTION_KINDS)
+            .iter()
+            .any(|requested| kind_matches(requested, kind))
+    }
+
+    /// 診断の `source`。ルールがプリセットに対応付けられていれば `textlint:<preset>` にする。
+    fn diagnostic_source(&self, rule_id: &str) -> String {
+        match self.config().rule_preset_map.get(rule_id) {
+            Some(preset) => format!("textlint:{preset}"),
+            None => "textlint".to_string(),
+        }
+    }
+
+    /// メッセージの診断の重大度。ルールが `ruleSeverityOverrides` にあればそれを、
+    /// ルールのカテゴリが `categorySeverityMap` にあればそれを、なければ textlint の severity を使う。
+    fn severity(&self, msg: &TextlintMessage) -> DiagnosticSeverity {
+        let config = self.config();
+        lookup_rule(&config.rule_severity_overrides, &msg.rule_id)
+            .or_else(|| {
+                rule_category(&msg.rule_id)
+                    .and_then(|category| config.category_severity_map.get(category))
+            })
+            .map_or_else(
+                || diagnostic_severity(msg.severity),
+                |severity| (*severity).into(),
+            )
+    }
+
+    /// `ruleTags` で指定した診断のタグ。
+    fn tags(&self, rule_id: &str) -> Option<Vec<DiagnosticTag>> {
+        let tag = *lookup_rule(&self.config().rule_tags, rule_id)?;
+        Some(vec![tag.into()])
+    }
+
+    /// textlint のメッセージを LSP の Diagnostic に変換する。
+    fn diagnostic(&self, text: &str, msg: &TextlintMessage) -> Diagnostic {
+        Diagnostic {
+            range: lsp_range(textlint::message_range(text, msg, self.encoding())),
+            severity: Some(self.severity(msg)),
+            source: Some(self.diagnostic_source(&msg.rule_id)),
+            code: Some(NumberOrString::String(msg.rule_id.clone())),
+            code_description: rule_doc_url(&msg.rule_id).map(|href| CodeDescription { href }),
+            message: msg.message.clone(),
+            data: serde_json::to_value(DiagnosticData::new(msg)).ok(),
+            tags: self.tags(&msg.rule_id),
+            ..Default::default()
+        }
+    }
+
+    /// textlint のインストール方法を案内する診断。
+    fn missing_install_diagnostic() -> Diagnostic {
+        Diagnostic {
+            range: Range::default(),
+            severity: Some(DiagnosticSeverity::INFORMATION),
+            source: Some("ichigyo-ls".to_string()),
+            message: MISSING_INSTALL_MESSAGE.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// `uri` の状態から publish する診断を作る。ベースラインにある問題は除く。
+    fn document_diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        if self.missing_install.contains(uri) {
+            return vec![Self::missing_install_diagnostic()];
+        }
+        let Some(doc) = self.state.get(uri) else {
+            return Vec::new();
+        };
+        let baseline = self.baseline.get(uri);
+        let rule_config = if doc.messages.is_empty() {
+            None
+        } else {
+            self.rule_config(uri)
+        };
+
+        doc.messages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, msg)| match &doc.shifted {
+                // lint 後に編集された問題は編集に合わせた位置で出し、書き換えられたものは出さない。
+                Some(shifted) => shifted[i].map(|range| (msg, Some(range))),
+                None => Some((msg, None)),
+            })
+            .filter(|(msg, _)| {
+                baseline
+                    .as_ref()
+                    .is_none_or(|keys| !keys.contains(&BaselineKey::new(&doc.text, msg)))
+            })
+            .map(|(msg, shifted)| {
+                let mut diagnostic = self.diagnostic(&doc.text, msg);
+                if let Some(range) = shifted {
+                    diagnostic.range = range;
+                }
+                let related: Vec<DiagnosticRelatedInformation> = rule_config
+                    .as_ref()
+                    .and_then(|(config_uri, config)| {
+                        self.rule_config_location(config_uri, config, &msg.rule_id)
+                    })
+                    .into_iter()
+                    .chain(self.fix_target_location(uri, &doc.text, msg))
+                    .collect();
+                diagnostic.related_information = (!related.is_empty()).then_some(related);
+                diagnostic
+            })
+            .collect()
+    }
+
+    /// `uri` の lint に使われる JSON の textlint 設定ファイル。
+    /// `scopedConfigs` / `textlintConfig` の指定がなければワークスペースルートの
+    /// `.textlintrc.json` / `.textlintrc` を探す。`noTextlintrc` が有効なら `None`。
+    fn rule_config_path(&self, uri: &Url) -> Option<PathBuf> {
+        if self.config().no_textlintrc {
+            return None;
+        }
+        let path = uri.to_file_path().ok()?;
+        self.scoped_config_path(&path)
+            .or_else(|| self.run_options().config_path)
+            .or_else(|| textlint::find_json_config(self.root_dir.get()?))
+    }
+
+    /// `uri` の lint に使われる JSON の textlint 設定ファイルと、その内容。
+    /// 内容は [`Self::load_rule_config`] で読み込んだものを使い、ここではディスクを読まない。
+    fn rule_config(&self, uri: &Url) -> Option<(Url, String)> {
+        let config_path = self.rule_config_path(uri)?;
+        let text = self.rule_config_texts.get(&config_path)?.clone();
+        Some((Url::from_file_path(&config_path).ok()?, text))
+    }
+
+    /// `uri` の lint に使われる JSON の textlint 設定ファイルをまだ読んでいなければ読み込む。
+    async fn load_rule_config(&self, uri: &Url) {
+        let Some(config_path) = self.rule_config_path(uri) else {
+            return;
+        };
+        if self.rule_config_texts.contains_key(&config_path) {
+            return;
+        }
+        if let Ok(text) = tokio::fs::read_to_string(&config_path).await {
+            self.rule_config_texts.insert(config_path, text);
+        }
+    }
+
+    /// 設定ファイル `config` で `rule_id` を設定しているキーを指す関連情報。
+    fn rule_config_location(
+        &self,
+        config_uri: &Url,
+        config: &str,
+        rule_id: &str,
+    ) -> Option<DiagnosticRelatedInformation> {
+        let [start, end] = textlint::rule_config_key_range(config, rule_id)?;
+        let encoding = self.encoding();
+        Some(DiagnosticRelatedInformation {
+            location: Location {
+                uri: config_uri.clone(),
+                range: lsp_range(textlint::Range {
+                    start: textlint::offset_to_position(config, start, encoding),
+                    end: textlint::offset_to_position(config, end, encoding),
                 }),
-            }],
+            },
+            message: format!("{rule_id} is configured here"),
+        })
+    }
+
+    /// メッセージの fix が置き換える範囲。fix がないか範囲が不正なら `None`。
+    fn fix_target_location(
+        &self,
+        uri: &Url,
+        text: &str,
+        msg: &TextlintMessage,
+    ) -> Option<DiagnosticRelatedInformation> {
+        let edit = self.fix_edit(text, msg).ok().flatten()?;
+        Some(DiagnosticRelatedInformation {
+            location: Location {
+                uri: uri.clone(),
+                range: edit.range,
+            },
+            message: format!("will be replaced with '{}'", edit.new_text),
+        })
+    }
+
+    /// 現在の状態から診断を publish する。`statusNotifications` が有効なら問題の数も知らせる。
+    /// 前回 publish した診断とまったく同じなら、エディタの表示がちらつかないよう送らない。
+    /// バージョンには lint したバージョン (lint 後の編集に合わせて動かした診断なら
+    /// エディタ上の最新のバージョン) を付ける。
+    async fn publish(&self, uri: &Url) {
+        self.load_rule_config(uri).await;
+        let diagnostics = self.document_diagnostics(uri);
+        let mut version = None;
+        if let Some(mut doc) = self.state.get_mut(uri) {
+            if doc.published.as_ref() == Some(&diagnostics) {
+                return;
+            }
+            doc.published = Some(diagnostics.clone());
+            version = match doc.shifted {
+                Some(_) => self.buffers.get(uri).map(|buffer| buffer.version),
+                None => doc.version,
+            };
+        }
+        let status = self
+            .config()
+            .status_notifications
+            .then(|| StatusParams::new(uri.clone(), &diagnostics));
+        self.client
+            .publish_diagnostics(uri.clone(), diagnostics, version)
+            .await;
+        if let Some(status) = status {
+            self.client
+                .send_notification::<StatusNotification>(status)
+                .await;
+        }
+    }
+
+    /// `uri` に保存しているメッセージを `textlint --format json` と同じ形式で返す。
+    /// lint していないドキュメントなら `None`。
+    fn export_textlint_json(&self, uri: &Url) -> Option<Value> {
+        let doc = self.state.get(uri)?;
+        let file_path = match uri.to_file_path() {
+            Ok(path) => path.display().to_string(),
+            Err(()) => uri.to_string(),
+        };
+        let results = [textlint::TextlintResult {
+            file_path,
+            messages: doc.messages.clone(),
         }];
+        serde_json::to_value(results).ok()
+    }
+
+    /// クライアントが `window/workDoneProgress` をサポートしていれば進捗表示を始め、トークンを返す。
+    async fn begin_progress(&self, token: &str, title: &str) -> Option<ProgressToken> {
+        let supported = self
+            .client_capabilities
+            .get()
+            .and_then(|c| c.window.as_ref())
+            .and_then(|w| w.work_done_progress)
+            == Some(true);
+        if !supported {
+            return None;
+        }
+
+        let token = ProgressToken::String(token.to_string());
+        self.client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.clone(),
+            })
+            .await
+            .ok()?;
+        self.send_progress(
+            &token,
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.to_string(),
+                percentage: Some(0),
+                ..Default::default()
+            }),
+        )
+        .await;
+        Some(token)
+    }
+
+    /// `future` を実行し、`LINT_PROGRESS_DELAY` より長くかかるときだけ進捗を表示する。
+    async fn with_progress<T>(
+        &self,
+        token: &str,
+        future: impl std::future::Future<Output = T>,
+    ) -> T {
+        tokio::pin!(future);
+        tokio::select! {
+            output = &mut future => return output,
+            () = tokio::time::sleep(LINT_PROGRESS_DELAY) => {}
+        }
+        // 進捗の作成をクライアントが受け付けるのを待つ間も実行は続ける
+        let (output, progress) = tokio::join!(future, self.begin_progress(token, "textlint"));
+        if let Some(token) = progress {
+            let end = WorkDoneProgressEnd { message: None };
+            self.send_progress(&token, WorkDoneProgress::End(end)).await;
+        }
+        output
+    }
+
+    async fn send_progress(&self, token: &ProgressToken, progress: WorkDoneProgress) {
+        self.client
+            .send_notification::<notification::Progress>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(progress),
+            })
+            .await;
+    }
+
+    /// lint 済みのドキュメントをすべて lint し直し、ドキュメント数と問題の数を返す。
+    async fn relint_all(&self) -> Value {
+        // 設定ファイルが変わったかもしれないので読み直す
+        self.rule_config_texts.clear();
+        let uris: Vec<Url> = self.state.iter().map(|entry| entry.key().clone()).collect();
+        let total = uris.len();
+        let progress = self.begin_progress("ichigyo/relintAll", "textlint").await;
+
+        let done = AtomicUsize::new(0);
+        futures::stream::iter(&uris)
+            .for_each_concurrent(LINT_CONCURRENCY, |uri| {
+                let (done, progress) = (&done, &progress);
+                async move {
+                    self.relint_and_publish(uri).await;
+                    let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+                    if let Some(token) = progress {
+                        let report = WorkDoneProgressReport {
+                            message: Some(format!("{done}/{total}")),
+                            percentage: Some((done * 100 / total) as u32),
+                            ..Default::default()
+                        };
+                        self.send_progress(token, WorkDoneProgress::Report(report))
+                            .await;
+                    }
+                }
+            })
+            .await;
+
+        let problems: usize = uris
+            .iter()
+            .map(|uri| self.document_diagnostics(uri).len())
+            .sum();
+        if let Some(token) = progress {
+            let end = WorkDoneProgressEnd {
+                message: Some(format!("{total} documents, {problems} problems")),
+            };
+            self.send_progress(&token, WorkDoneProgress::End(end)).await;
+        }
+        serde_json::json!({ "documents": total, "problems": problems })
+    }
+
+    /// 状態を持っている全ドキュメントを lint し直し、ドキュメントごとの診断を返す。
+    /// ファイルシステムは探さず、サーバーが知っているドキュメントだけを対象にする。
+    /// 結果はレスポンスで返すので、publishDiagnostics では送らない。
+    async fn workspace_report(&self) -> WorkspaceDiagnosticReport {
+        let uris: Vec<Url> = self.state.iter().map(|entry| entry.key().clone()).collect();
+        futures::stream::iter(&uris)
+            .for_each_concurrent(LINT_CONCURRENCY, |uri| {
+                self.lint_document(uri, None, LintMode::Report)
+            })
+            .await;
+
+        let items = uris
+            .into_iter()
+            .map(|uri| {
+                let version = self
+                    .state
+                    .get(&uri)
+                    .and_then(|doc| doc.version)
+                    .map(i64::from);
+                let items = self.document_diagnostics(&uri);
+                // レスポンスで返した診断を publishDiagnostics で送り直さない
+                if let Some(mut doc) = self.state.get_mut(&uri) {
+                    doc.published = Some(items.clone());
+                }
+                WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                    uri,
+                    version,
+                    full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                        result_id: None,
+                        items,
+                    },
+                })
+            })
+            .collect();
+        WorkspaceDiagnosticReport { items }
+    }
+
+    /// textlint のバージョンを返す。調べられなければ `None`。結果はキャッシュする。
+    async fn textlint_version(&self) -> Option<String> {
+        self.textlint_version
+            .get_or_init(|| async {
+                let work_dir = self
+                    .root_dir
+                    .get()
+                    .cloned()
+                    .unwrap_or_else(|| PathBuf::from("."));
+                match self.runner.version(&work_dir, &self.run_options()).await {
+                    Ok(version) => Some(version),
+                    Err(e) => {
+                        self.client
+                            .log_message(
+                                MessageType::WARNING,
+                                format!("failed to get textlint version: {e}"),
+                            )
+                            .await;
+                        None
+                    }
+                }
+            })
+            .await
+            .clone()
+    }
+
+    /// 不具合の調査に使うサーバーの状態を返す。
+    async fn doctor(&self) -> Value {
+        let encoding = match self.encoding() {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        };
+        serde_json::json!({
+            "textlintVersion": self.textlint_version().await,
+            "rootDir": self.root_dir.get(),
+            "positionEncoding": encoding,
+            "documents": self.state.len(),
+        })
+    }
+
+    /// `uri` (省略時は全ドキュメント) の現在の問題をベースラインとして記録し、再 publish する。
+    /// 記録した問題の数を返す。
+    async fn snapshot_baseline(&self, uri: Option<Url>) -> usize {
+        let uris: Vec<Url> = match uri {
+            Some(uri) => vec![uri],
+            None => self.state.iter().map(|entry| entry.key().clone()).collect(),
+        };
+
+        let mut count = 0;
+        for uri in &uris {
+            let Some(doc) = self.state.get(uri) else {
+                continue;
+            };
+            let keys: HashSet<BaselineKey> = doc
+                .messages
+                .iter()
+                .map(|msg| BaselineKey::new(&doc.text, msg))
+                .collect();
+            drop(doc);
+            count += keys.len();
+            self.baseline.insert(uri.clone(), keys);
+        }
+
+        for uri in &uris {
+            self.publish(uri).await;
+        }
+        count
+    }
+
+    /// lint 後にドキュメントが編集され、`msg` の fix が最新の内容に当てはまらなくなったか。
+    /// 編集されていても fix.range の部分が lint 時と同じなら適用できるとみなす。
+    fn fix_is_stale(&self, uri: &Url, doc: &DocumentState, msg: &TextlintMessage) -> bool {
+        let Some(fix) = msg.fix.as_ref() else {
+            return false;
+        };
+        let Some(buffer) = self.buffers.get(uri) else {
+            return false;
+        };
+        if doc.version == Some(buffer.version) {
+            return false;
+        }
+        textlint::fix_range_text(&buffer.text, fix.range)
+            != textlint::fix_range_text(&doc.text, fix.range)
+    }
+
+    /// `confirmFixRules` に含まれ、fix の適用に確認が必要なルールか。
+    fn requires_confirmation(&self, rule_id: &str) -> bool {
+        self.config()
+            .confirm_fix_rules
+            .iter()
+            .any(|rule| rule == rule_id)
+    }
+
+    /// クライアントが無効化された code action (`disabled`) を表示できるか。
+    fn supports_disabled_code_actions(&self) -> bool {
+        self.client_capabilities
+            .get()
+            .and_then(|c| c.text_document.as_ref())
+            .and_then(|t| t.code_action.as_ref())
+            .and_then(|c| c.disabled_support)
+            == Some(true)
+    }
+
+    /// メッセージの fix を TextEdit に変換する。fix がなければ `Ok(None)`、
+    /// fix.range が本文に対して不正なら `Err` を返す。
+    fn fix_edit(
+        &self,
+        text: &str,
+        msg: &TextlintMessage,
+    ) -> std::result::Result<Option<TextEdit>, InvalidFixRange> {
+        let Some(fix) = msg.fix.as_ref() else {
+            return Ok(None);
+        };
+        textlint::check_fix_range(text, fix.range)?;
+
+        let encoding = self.encoding();
+        let range = textlint::Range {
+            start: textlint::offset_to_position(text, fix.range[0], encoding),
+            end: textlint::offset_to_position(text, fix.range[1], encoding),
+        };
+
+        Ok(Some(TextEdit {
+            range: lsp_range(range),
+            new_text: fix.text.clone(),
+        }))
+    }
+
+    /// エディタ上の最新の行数。バッファがなければ lint した内容の行数。
+    fn current_line_count(&self, uri: &Url, linted: &str) -> u32 {
+        self.buffers.get(uri).map_or_else(
+            || textlint::line_count(linted),
+            |b| textlint::line_count(&b.text),
+        )
+    }
+
+    /// `line` 行目 (0-based) だけ `rule_id` を無効にするコメントで挟む code action。
+    ///
+    /// textlint-filter-rule-comments の `<!-- textlint-disable <ruleId> -->` /
+    /// `<!-- textlint-enable <ruleId> -->` を使うので、HTML コメントを書ける Markdown だけが対象。
+    /// 別のルールを無効にするコメントですでに挟まれていれば、そのコメントにルールを書き足す。
+    /// lint 後に行の内容が変わっている場合や、すでに直前の行で無効にしている場合は `None`。
+    fn disable_line_action(
+        &self,
+        uri: &Url,
+        doc: &DocumentState,
+        rule_id: &str,
+        line: u32,
+    ) -> Option<CodeAction> {
+        if doc.language_id != "markdown" {
+            return None;
+        }
+        let buffer = self.buffers.get(uri);
+        let text = buffer
+            .as_ref()
+            .map_or(doc.text.as_str(), |b| b.text.as_str());
+        let lines: Vec<&str> = text.split('\n').collect();
+        let index = line as usize;
+        if lines.get(index) != doc.text.split('\n').nth(index).as_ref() {
+            return None;
+        }
+
+        let disabled = index
+            .checked_sub(1)
+            .and_then(|above| directive_rules(lines[above], "textlint-disable"));
+        // ルールを指定しないコメントはすべてのルールを無効にする
+        if disabled
+            .as_ref()
+            .is_some_and(|rules| rules.is_empty() || rules.contains(&rule_id))
+        {
+            return None;
+        }
+        let enabled = lines
+            .get(index + 1)
+            .and_then(|below| directive_rules(below, "textlint-enable"));
+        let edits = match (disabled, enabled) {
+            (Some(disabled), Some(enabled)) if disabled == enabled => {
+                let rules = [disabled, vec![rule_id]].concat().join(", ");
+                let encoding = self.encoding();
+                vec![
+                    TextEdit {
+                        range: line_range(&lines, index - 1, encoding),
+                        new_text: format!("<!-- textlint-disable {rules} -->"),
+                    },
+                    TextEdit {
+                        range: line_range(&lines, index + 1, encoding),
+                        new_text: format!("<!-- textlint-enable {rules} -->"),
+                    },
+                ]
+            }
+            _ => self.wrap_line_edits(text, &lines, line, rule_id),
+        };
+
+        Some(CodeAction {
+            title: format!("Disable {rule_id} for this line"),
+            kind: Some(CodeActionKind::QUICKFIX),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), edits)])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// `line` 行目 (0-based) を `rule_id` の disable / enable コメントで挟む TextEdit。
+    fn wrap_line_edits(
+        &self,
+        text: &str,
+        lines: &[&str],
+        line: u32,
+        rule_id: &str,
+    ) -> Vec<TextEdit> {
+        let index = line as usize;
+        let disable = format!("<!-- textlint-disable {rule_id} -->");
+        let enable = format!("<!-- textlint-enable {rule_id} -->");
+        // 足す改行は本文で使われている改行コードに合わせる
+        let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+
+        // 最終行なら行末に改行を足してから閉じる
+        let after = if index + 1 < lines.len() {
+            TextEdit {
+                range: Range::new(Position::new(line + 1, 0), Position::new(line + 1, 0)),
+                new_text: format!("{enable}{newline}"),
+            }
+        } else {
+            let end = textlint::end_position(text, self.encoding());
+            let end = Position::new(end.line, end.character);
+            TextEdit {
+                range: Range::new(end, end),
+                new_text: format!("{newline}{enable}"),
+            }
+        };
+        let before = TextEdit {
+            range: Range::new(Position::new(line, 0), Position::new(line, 0)),
+            new_text: format!("{disable}{newline}"),
+        };
+        vec![before, after]
+    }
+
+    /// ドキュメントの fix をまとめて適用する TextEdit。重なる fix は先に始まるものだけを使い、
+    /// 古くなった fix と確認が必要なルールの fix は含めない。
+    /// 同じ範囲を別のテキストに置き換える fix を捨てたときは `window/logMessage` に残す。
+    async fn fix_all_edits(&self, uri: &Url) -> Vec<TextEdit> {
+        let (edits, conflicts) = {
+            let Some(doc) = self.state.get(uri) else {
+                return Vec::new();
+            };
+            let current_lines = self.current_line_count(uri, &doc.text);
+            let fixes = textlint::non_overlapping_fixes(&doc.text, &doc.messages);
+            let conflicts: Vec<String> = textlint::same_range_conflicts(&fixes, &doc.messages)
+                .into_iter()
+                .map(|(kept, dropped)| {
+                    format!(
+                        "conflicting fix for {} at {:?}: kept fix from {}",
+                        dropped.rule_id,
+                        dropped.fix.as_ref().map(|f| f.range).unwrap_or_default(),
+                        kept.rule_id
+                    )
+                })
+                .collect();
+            let edits: Vec<TextEdit> = fixes
+                .into_iter()
+                .filter(|msg| msg.line <= current_lines && !self.fix_is_stale(uri, &doc, msg))
+                .filter(|msg| !self.requires_confirmation(&msg.rule_id))
+                .filter_map(|msg| self.fix_edit(&doc.text, msg).ok().flatten())
+                .collect();
+            (edits, conflicts)
+        };
+        for message in conflicts {
+            self.client.log_message(MessageType::LOG, message).await;
+        }
+        edits
+    }
+
+    /// [`Self::fix_all_edits`] を `workspace/applyEdit` で適用し、適用した fix の数を返す。
+    /// 結果は `window/showMessage` でも知らせる。拒否されたら 0。
+    async fn fix_all(&self, uri: &Url) -> usize {
+        let edits = self.fix_all_edits(uri).await;
+        let count = edits.len();
+        let (kind, message) = if count == 0 {
+            (
+                MessageType::INFO,
+                "no auto-fixable textlint problems".to_string(),
+            )
+        } else if self.apply_edits(uri, edits).await {
+            (MessageType::INFO, format!("applied {count} textlint fixes"))
+        } else {
+            (
+                MessageType::WARNING,
+                "textlint fixes were not applied".to_string(),
+            )
+        };
+        self.client.show_message(kind, message).await;
+        if kind == MessageType::WARNING {
+            0
+        } else {
+            count
+        }
+    }
+
+    /// ドキュメントの fix をすべて適用する code action。重なる fix は先に始まるものだけを使い、
+    /// 確認が必要なルールの fix は含めない。適用できる fix がなければ `None`。
+    async fn fix_all_action(&self, uri: &Url) -> Option<CodeAction> {
+        let edits = self.fix_all_edits(uri).await;
+        if edits.is_empty() {
+            return None;
+        }
+
+        Some(CodeAction {
+            title: FIX_ALL_TITLE.to_string(),
+            kind: Some(CodeActionKind::SOURCE_FIX_ALL),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), edits)])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// `position` の行にある `rule_id` の fix を `workspace/applyEdit` で適用する。
+    /// 同じ行に複数あれば、置換範囲が `position` を含むものを優先する。
+    /// lint 後の編集で fix が古くなっていれば適用せず、再 lint を待つよう知らせる。
+    /// 適用できたら `true` を返す。
+    async fn apply_fix_at(&self, uri: &Url, position: Position, rule_id: &str) -> bool {
+        let edit = {
+            let entry = match self.state.get(uri) {
+                Some(e) => e,
+                None => return false,
+            };
+            let doc = entry.value();
+            let DocumentState { text, messages, .. } = doc;
+
+            let candidates: Vec<(&TextlintMessage, TextEdit)> = messages
+                .iter()
+                .filter(|msg| msg.rule_id == rule_id && msg.line.saturating_sub(1) == position.line)
+                .filter_map(|msg| Some((msg, self.fix_edit(text, msg).ok().flatten()?)))
+                .collect();
+            let found = candidates
+                .iter()
+                .position(|(_, e)| e.range.start <= position && position <= e.range.end)
+                .unwrap_or(0);
+            match candidates.into_iter().nth(found) {
+                Some((msg, e)) => (!self.fix_is_stale(uri, doc, msg)).then_some(e),
+                None => return false,
+            }
+        };
+        let Some(edit) = edit else {
+            self.client
+                .show_message(MessageType::WARNING, STALE_FIX_REASON)
+                .await;
+            return false;
+        };
+
+        self.apply_edits(uri, vec![edit]).await
+    }
+
+    /// `edits` を `uri` に対する WorkspaceEdit として適用する。適用されたら `true`。
+    async fn apply_edits(&self, uri: &Url, edits: Vec<TextEdit>) -> bool {
+        let mut changes = HashMap::new();
+        changes.insert(uri.clone(), edits);
+        let edit = WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        };
+
+        match self.client.apply_edit(edit).await {
+            Ok(response) => response.applied,
+            Err(_) => false,
+        }
+    }
+
+    /// textlint の fix をすべて適用し、フォーマッタが設定されていればその結果も適用する。
+    /// 適用する fix は [`Self::fix_all_edits`] と同じで、フォーマッタには fix 後のエディタ上の
+    /// 内容を渡す。lint 後に編集されていれば何もしない。
+    /// どちらかの適用が拒否されたら `false` を返して中断する。
+    async fn fix_and_format(&self, uri: &Url) -> bool {
+        let text = {
+            let Some(doc) = self.state.get(uri) else {
+                return false;
+            };
+            match self.buffers.get(uri) {
+                // fix の位置は lint した内容に対するものなので、編集後の内容には当てられない
+                Some(buffer) if doc.version != Some(buffer.version) => None,
+                Some(buffer) => Some(buffer.text.clone()),
+                None => Some(doc.text.clone()),
+            }
+        };
+        let Some(text) = text else {
+            self.client
+                .show_message(MessageType::WARNING, STALE_FIX_REASON)
+                .await;
+            return false;
+        };
+
+        let edits = self.fix_all_edits(uri).await;
+        // 後ろの fix から当てれば、前の fix の位置はずれない
+        let mut fixed = text;
+        for edit in edits.iter().rev() {
+            let change = TextDocumentContentChangeEvent {
+                range: Some(edit.range),
+                range_length: None,
+                text: edit.new_text.clone(),
+            };
+            apply_content_change(&mut fixed, change, self.encoding());
+        }
+
+        if !edits.is_empty() && !self.apply_edits(uri, edits).await {
+            return false;
+        }
+
+        let config = self.config();
+        if config.formatter.is_empty() {
+            return true;
+        }
+        let Ok(path) = uri.to_file_path() else {
+            return true;
+        };
+        let Some(work_dir) = self.resolve_work_dir(&path) else {
+            return true;
+        };
+
+        let formatted = match formatter::run(&config.formatter, &fixed, &path, &work_dir).await {
+            Ok(t) => t,
+            Err(e) => {
+                self.client
+                    .show_message(MessageType::ERROR, e.to_string())
+                    .await;
+                return false;
+            }
+        };
+        if formatted == fixed {
+            return true;
+        }
+
+        let end = textlint::end_position(&fixed, self.encoding());
+        let edit = TextEdit {
+            range: Range {
+                start: Position::new(0, 0),
+                end: Position::new(end.line, end.character),
+            },
+            new_text: formatted,
+        };
+        self.apply_edits(uri, vec![edit]).await
+    }
+
+    /// `textlint --fix` でドキュメント全体を修正し、全体を置き換える TextEdit を返す。
+    /// エディタ上の内容 (なければ最後に lint した内容) を stdin で渡す。末尾の改行は元の内容に
+    /// 合わせたうえで、`options` の `insertFinalNewline` と `trimFinalNewlines` に従う。
+    /// 変更がない場合や、`runners` のコマンドで lint する言語では `None`。
+    async fn format_document(&self, uri: &Url, options: &FormattingOptions) -> Option<TextEdit> {
+        let path = uri.to_file_path().ok()?;
+        let work_dir = self.resolve_work_dir(&path)?;
+        let text = match self.buffers.get(uri) {
+            Some(buffer) => buffer.text.clone(),
+            None => self.state.get(uri)?.text.clone(),
+        };
+        let language_id = self
+            .state
+            .get(uri)
+            .map(|doc| doc.language_id.clone())
+            .unwrap_or_else(|| language_id_from_path(&path).to_string());
+        let run_options = self.run_options_for(&path, &language_id);
+        if run_options.command.is_some() {
+            return None;
+        }
+
+        let fixed = match self
+            .runner
+            .fix_stdin(&text, &path, &work_dir, &run_options)
+            .await
+        {
+            Ok(fixed) => {
+                apply_final_newline_options(preserve_trailing_newline(&text, fixed), options)
+            }
+            Err(e) => {
+                self.client
+                    .log_message(MessageType::ERROR, format!("textlint --fix failed: {e:#}"))
+                    .await;
+                return None;
+            }
+        };
+        if fixed == text {
+            return None;
+        }
+
+        let end = textlint::end_position(&text, self.encoding());
+        Some(TextEdit {
+            range: Range {
+                start: Position::new(0, 0),
+                end: Position::new(end.line, end.character),
+            },
+            new_text: fixed,
+        })
+    }
+
+    /// クライアントが `workspace/configuration` に対応しているか。
+    fn supports_configuration(&self) -> bool {
+        self.client_capabilities
+            .get()
+            .and_then(|c| c.workspace.as_ref())
+            .and_then(|w| w.configuration)
+            == Some(true)
+    }
+
+    /// `workspace/configuration` で `ichigyo` セクションを取得し、設定を置き換える。
+    /// クライアントが値を持っていなければ (`null` や空のオブジェクト) initializationOptions の
+    /// 設定を維持する。
+    async fn pull_configuration(&self) {
+        let item = ConfigurationItem {
+            scope_uri: None,
+            section: Some(config::SECTION.to_string()),
+        };
+        let Ok(values) = self.client.configuration(vec![item]).await else {
+            return;
+        };
+        let value = values
+            .into_iter()
+            .next()
+            .filter(|value| value.as_object().is_some_and(|map| !map.is_empty()));
+        if let Some(value) = value {
+            self.store_config(value).await;
+        }
+    }
+
+    /// 設定値をこれまでの設定値に重ねて読み込み、設定を置き換える。送られなかったキーは
+    /// 今の値を残す。読み込めなかった項目は `window/logMessage` で知らせる。
+    async fn store_config(&self, value: Value) {
+        let update = match config::unwrap_section(value) {
+            Value::Object(update) => update,
+            Value::Null => return,
+            value => {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("invalid settings, expected an object: {value}"),
+                    )
+                    .await;
+                return;
+            }
+        };
+        let (config, errors) = {
+            let mut settings = self.settings.lock().unwrap();
+            config::merge_settings(&mut settings, update);
+            Config::from_value_with_errors(Value::Object(settings.clone()))
+        };
+        self.config.store(Arc::new(config));
+        for error in errors {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("invalid setting, using the default: {error}"),
+                )
+                .await;
+        }
+    }
+
+    /// textlint 設定ファイルの監視を動的登録する。`relintOnExternalChange` が有効なら
+    /// 開いているファイルの変更も監視する。
+    async fn register_watchers(&self) {
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: self.file_watchers(),
+        };
+        let registration = Registration {
+            id: WATCHERS_REGISTRATION_ID.to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(options).ok(),
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("failed to register watchers: {e}"),
+                )
+                .await;
+        }
+    }
+
+    /// 監視するファイルの一覧。`relintOnExternalChange` が有効なら lint 対象の拡張子のファイルも監視する。
+    fn file_watchers(&self) -> Vec<FileSystemWatcher> {
+        let mut watchers = vec![FileSystemWatcher {
+            glob_pattern: GlobPattern::String(TEXTLINT_CONFIG_GLOB.to_string()),
+            kind: None,
+        }];
+        let config = self.config();
+        if config.relint_on_external_change {
+            if let Some(glob) = extensions_glob(&config.target_extensions) {
+                watchers.push(FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(glob),
+                    kind: Some(WatchKind::Change),
+                });
+            }
+        }
+        watchers
+    }
+
+    /// クライアントが `workspace/didChangeWatchedFiles` の動的登録に対応しているか。
+    fn supports_watched_files(&self) -> bool {
+        self.client_capabilities
+            .get()
+            .and_then(|c| c.workspace.as_ref())
+            .and_then(|w| w.did_change_watched_files.as_ref())
+            .and_then(|w| w.dynamic_registration)
+            == Some(true)
+    }
+
+    /// 登録済みのファイル監視を外し、今の設定で登録し直す。
+    async fn reregister_watchers(&self) {
+        let unregistration = Unregistration {
+            id: WATCHERS_REGISTRATION_ID.to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+        };
+        if let Err(e) = self
+            .client
+            .unregister_capability(vec![unregistration])
+            .await
+        {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("failed to unregister watchers: {e}"),
+                )
+                .await;
+        }
+        self.register_watchers().await;
+    }
+
+    /// ディスク上の内容がエディタ上の内容と異なる、開いているドキュメントを返す。
+    /// エディタ上のバッファには触れない。
+    async fn externally_changed(&self, changes: &[FileEvent]) -> Vec<Url> {
+        let mut changed = Vec::new();
+        for change in changes {
+            if change.typ != FileChangeType::CHANGED || !self.buffers.contains_key(&change.uri) {
+                continue;
+            }
+            let Ok(path) = change.uri.to_file_path() else {
+                continue;
+            };
+            let Ok(on_disk) = tokio::fs::read_to_string(path).await else {
+                continue;
+            };
+            if self
+                .buffers
+                .get(&change.uri)
+                .is_some_and(|buffer| buffer.text != on_disk)
+            {
+                changed.push(change.uri.clone());
+            }
+        }
+        changed
+    }
+
+    /// 起動直後の猶予期間の残り時間。猶予期間外なら `None`。
+    fn startup_grace_remaining(&self) -> Option<Duration> {
+        let grace = Duration::from_millis(self.config().startup_grace_period_ms);
+        let elapsed = self.initialized_at.get()?.elapsed();
+        grace.checked_sub(elapsed).filter(|d| !d.is_zero())
+    }
+
+    /// 猶予期間中の didOpen をキューに積む。最初に積んだ呼び出しだけが猶予期間の終わりに
+    /// lint するタスクを起動し、重複を除いたドキュメントを同時実行数を絞って lint する。
+    fn queue_startup_open(&self, uri: Url, language_id: String, remaining: Duration) {
+        self.startup_opens.insert(uri, language_id);
+        if self.startup_flush_scheduled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(remaining).await;
+            let this = &this;
+            loop {
+                // 集めてから消すまでの間に開かれたドキュメントを落とさないよう、1 件ずつ取り出す
+                let uris: Vec<Url> = this
+                    .startup_opens
+                    .iter()
+                    .map(|entry| entry.key().clone())
+                    .collect();
+                let opens: Vec<(Url, String)> = uris
+                    .into_iter()
+                    .filter_map(|uri| this.startup_opens.remove(&uri))
+                    .collect();
+                futures::stream::iter(opens)
+                    .for_each_concurrent(LINT_CONCURRENCY, |(uri, language_id)| async move {
+                        this.lint_and_publish(&uri, Some(language_id)).await;
+                    })
+                    .await;
+
+                // 予約を解いたあとにも残っていれば、新しい予約がない限りここで続けて lint する
+                this.startup_flush_scheduled.store(false, Ordering::SeqCst);
+                if this.startup_opens.is_empty()
+                    || this.startup_flush_scheduled.swap(true, Ordering::SeqCst)
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// lint の失敗をユーザーに知らせる。入力のたびに出ないよう、一定時間に 1 回に抑える。
+    async fn report_lint_failure(&self, err: &anyhow::Error) {
+        {
+            let mut last = self.last_failure_notice.lock().unwrap();
+            if last.is_some_and(|last| last.elapsed() < FAILURE_NOTICE_INTERVAL) {
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+
+        let message = if textlint::is_missing_binary(err) {
+            format!("textlint not found: {err}")
+        } else if err.downcast_ref::<serde_json::Error>().is_some() {
+            format!("textlint ran but its output is not valid JSON: {err:#}")
+        } else {
+            format!("textlint failed: {err:#}")
+        };
+        self.client.show_message(MessageType::ERROR, message).await;
+    }
+
+    /// lint 結果を `uri` の状態として保存する。重複を除き、fix のオフセットを正規化する。
+    fn store_messages(
+        &self,
+        uri: &Url,
+        text: String,
+        mut messages: Vec<TextlintMessage>,
+        language_id: String,
+        version: Option<i32>,
+    ) {
+        textlint::dedup_messages(&mut messages);
+        textlint::normalize_fix_offsets(&text, &mut messages, self.config().fix_offset_basis);
+        let published = self.state.get(uri).and_then(|doc| doc.published.clone());
+        self.state.insert(
+            uri.clone(),
+            DocumentState {
+                text,
+                messages,
+                language_id,
+                version,
+                published,
+                shifted: None,
+                linted_at: tokio::time::Instant::now(),
+            },
+        );
+    }
+
+    /// lint したドキュメント以外のファイル `path` について報告された問題を publish する。
+    /// 位置の変換に使う内容は、開いていればエディタ上の内容、なければディスクから読む。
+    async fn publish_other_file(&self, path: &Path, messages: Vec<TextlintMessage>) {
+        let Ok(uri) = Url::from_file_path(path) else {
+            return;
+        };
+        if !self.is_active_document(&uri) {
+            return;
+        }
+        let buffer = self
+            .buffers
+            .get(&uri)
+            .map(|buffer| (buffer.version, buffer.text.clone()));
+        let (version, text) = match buffer {
+            Some((version, text)) => (Some(version), text),
+            None => match tokio::fs::read_to_string(path).await {
+                Ok(text) => (None, text),
+                Err(_) => return,
+            },
+        };
+        let language_id = self
+            .state
+            .get(&uri)
+            .map(|doc| doc.language_id.clone())
+            .unwrap_or_else(|| language_id_from_path(path).to_string());
+        self.store_messages(&uri, text, messages, language_id, version);
+        self.publish(&uri).await;
+    }
+
+    /// `generation` が `uri` の最新の lint か。ドキュメントが閉じられていれば `false`。
+    fn is_latest_lint(&self, uri: &Url, generation: u64) -> bool {
+        self.lint_generations
+            .get(uri)
+            .is_some_and(|latest| *latest == generation)
+    }
+
+    /// 変更が落ち着いてから lint するタスクを起動する。待っている間に次の変更が来たら待ち直し、
+    /// 連続した変更は 1 回の lint にまとめる。タスクを起動するのは最初の変更だけで、
+    /// `pending_lints` から URI が消えたら lint せずに終わる。
+    /// 待ち時間 (`lintDebounceMs`) が 0 なら変更のたびにすぐ lint する。
+    fn schedule_lint(&self, uri: Url) {
+        let debounce = Duration::from_millis(self.config().lint_debounce_ms);
+        let this = self.clone();
+        if debounce.is_zero() {
+            self.pending_lints.remove(&uri);
+            tokio::spawn(async move { this.lint_and_publish(&uri, None).await });
+            return;
+        }
+        let deadline = tokio::time::Instant::now() + debounce;
+        if self.pending_lints.insert(uri.clone(), deadline).is_some() {
+            return;
+        }
+
+        tokio::spawn(async move {
+            loop {
+                let Some(deadline) = this.pending_lints.get(&uri).map(|d| *d) else {
+                    return;
+                };
+                tokio::time::sleep_until(deadline).await;
+                // 待っている間に期限が延びていなければ lint する
+                let now = tokio::time::Instant::now();
+                if this
+                    .pending_lints
+                    .remove_if(&uri, |_, d| *d <= now)
+                    .is_some()
+                {
+                    break;
+                }
+            }
+            this.lint_and_publish(&uri, None).await;
+        });
+    }
+
+    /// textlint を実行して診断を publish する。
+    /// `language_id` が `None` なら既存の状態か拡張子から推測する。
+    /// 最後に lint した内容と同じなら textlint を実行せず、保存している結果を publish し直す。
+    async fn lint_and_publish(&self, uri: &Url, language_id: Option<String>) {
+        self.lint_document(uri, language_id, LintMode::Cached).await;
+    }
+
+    /// 内容が変わっていなくても textlint を実行して診断を publish する。
+    /// ルールや設定を変えた後に使う。
+    async fn relint_and_publish(&self, uri: &Url) {
+        self.lint_document(uri, None, LintMode::Forced).await;
+    }
+
+    async fn lint_document(&self, uri: &Url, language_id: Option<String>, mode: LintMode) {
+        let publish = mode != LintMode::Report;
+        let path = match uri.to_file_path() {
+            Ok(p) => p,
+            Err(()) => return,
+        };
+
+        // pull の lint は世代を進めない。実行中の publish する lint を古いとみなして
+        // 結果を捨てると、どちらの経路からも診断が送られなくなる
+        let generation = {
+            let mut generation = self.lint_generations.entry(uri.clone()).or_insert(0);
+            if publish {
+                *generation += 1;
+            }
+            *generation
+        };
+
+        // 同じドキュメントの lint が実行中なら、それが終わるまで待つ。
+        // 別のドキュメントは並行して lint できる。
+        let lock = self.lint_locks.entry(uri.clone()).or_default().clone();
+        let _guard = lock.lock().await;
+        // 待っている間に新しい lint が始まっていれば、そちらに任せる
+        if !self.is_latest_lint(uri, generation) {
+            return;
+        }
+
+        let Some(work_dir) = self.resolve_work_dir(&path) else {
+            return;
+        };
+
+        let language_id = language_id
+            .or_else(|| self.state.get(uri).map(|doc| doc.language_id.clone()))
+            .unwrap_or_else(|| language_id_from_path(&path).to_string());
+
+        // 開いているドキュメントは保存前の内容を stdin で lint する。
+        // 外部コマンドはファイルパスしか受け取らないので、ディスクの内容を使う
+        let options = self.run_options_for(&path, &language_id);
+        let buffer = self
+            .buffers
+            .get(uri)
+            .map(|buffer| (buffer.version, buffer.text.clone()));
+        let version = buffer.as_ref().map(|(version, _)| *version);
+        let buffer_text = buffer
+            .map(|(_, text)| text)
+            .filter(|_| options.command.is_none());
+        let disk_text = match buffer_text {
+            Some(_) => None,
+            None => tokio::fs::read_to_string(&path).await.ok(),
+        };
+
+        // 自動保存や何もしないフォーマッタで、同じ内容の lint が繰り返されるのを避ける。
+        // 辞書などを外から変えた場合に備え、cacheTtlMs を過ぎた結果は使わない
+        if mode == LintMode::Cached && !self.missing_install.contains(uri) {
+            let linted_text = buffer_text.as_ref().or(disk_text.as_ref());
+            let ttl = self.config().cache_ttl_ms.map(Duration::from_millis);
+            let cached = self.state.get_mut(uri).is_some_and(|mut doc| {
+                let hit = doc.language_id == language_id
+                    && Some(&doc.text) == linted_text
+                    && ttl.is_none_or(|ttl| doc.linted_at.elapsed() < ttl);
+                if hit {
+                    doc.version = version;
+                }
+                hit
+            });
+            if cached {
+                if publish {
+                    self.publish(uri).await;
+                }
+                return;
+            }
+        }
+
+        let run = async {
+            match &buffer_text {
+                Some(text) => {
+                    self.runner
+                        .run_stdin(text, &path, &work_dir, &options)
+                        .await
+                }
+                None => self.runner.run(&path, &work_dir, &options).await,
+            }
+        };
+        let run = self
+            .with_progress(&format!("ichigyo/lint/{uri}#{generation}"), run)
+            .await;
+        // 実行中に新しい lint が始まった結果は古いので publish しない
+        if !self.is_latest_lint(uri, generation) {
+            return;
+        }
+        let results = match run {
+            Ok(r) => r,
+            Err(e) => {
+                if self.config().notify_missing_install
+                    && options.command.is_none()
+                    && textlint::is_missing_binary(&e)
+                    && !textlint::has_local_install(&work_dir)
+                {
+                    self.missing_install.insert(uri.clone());
+                    if publish {
+                        self.publish(uri).await;
+                    }
+                }
+                self.report_lint_failure(&e).await;
+                return;
+            }
+        };
+        self.missing_install.remove(uri);
+
+        // textlint が解析した内容と同じテキストを保持して、fix.range オフセットとの
+        // 整合性を保証する。ディスクを lint した場合は実行前に読み込んだ内容を使う。
+        let Some(text) = buffer_text.or(disk_text) else {
+            return;
+        };
+
+        let (messages, others) = split_results(&path, &work_dir, results);
+        if publish {
+            for (other, messages) in others {
+                self.publish_other_file(&other, messages).await;
+            }
+        }
+
+        self.load_rule_config(uri).await;
+        let had_problems = !self.document_diagnostics(uri).is_empty();
+        self.store_messages(uri, text, messages, language_id, version);
+        if publish {
+            self.publish(uri).await;
+        }
+
+        // 問題があったドキュメントが 0 件になったときだけ知らせる。
+        // pull の lint は診断をクライアントに送らないので知らせない
+        if publish
+            && had_problems
+            && self.config().notify_on_clean
+            && self.document_diagnostics(uri).is_empty()
+        {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| uri.to_string());
+            self.client
+                .show_message(
+                    MessageType::INFO,
+                    format!("{name}: no textlint problems 🎉"),
+                )
+                .await;
+        }
+    }
+}
+
+#[tower_lsp::async_trait]
+impl<R: TextlintRunner> LanguageServer for Backend<R> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let (encoding, encoding_kind) = negotiate_encoding(&params);
+        let _ = self.position_encoding.set(encoding);
+
+        if let Some(root_uri) = params.root_uri {
+            match root_uri.to_file_path() {
+                // 存在しないパスを cwd にすると textlint の起動がすべて失敗するので、
+                // ルートなしとしてファイルごとの親ディレクトリで実行する
+                Ok(path) if path.is_dir() => {
+                    let _ = self.root_dir.set(path);
+                }
+                _ => {
+                    self.client
+                        .log_message(
+                            MessageType::WARNING,
+                            format!("ignoring rootUri that is not a directory: {root_uri}"),
+                        )
+                        .await;
+                }
+            }
+        }
+
+        let _ = self.client_capabilities.set(params.capabilities.clone());
+
+        if let Some(options) = params.initialization_options {
+            self.store_config(options).await;
+        }
+
+        // 設定された kind のうち実際に返しうるものだけを広告する
+        let advertised_kinds: Vec<CodeActionKind> = SUPPORTED_CODE_ACTION_KINDS
+            .iter()
+            .filter(|kind| self.offers_kind(kind))
+            .cloned()
+            .collect();
+
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::INCREMENTAL,
+                )),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(advertised_kinds),
+                        ..Default::default()
+                    },
+                )),
+                position_encoding: Some(encoding_kind),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                diagnostic_provider: self.config().workspace_diagnostics.then(|| {
+                    DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                        identifier: Some("textlint".to_string()),
+                        inter_file_dependencies: false,
+                        workspace_diagnostics: true,
+                        ..Default::default()
+                    })
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        APPLY_FIX_AT_COMMAND.to_string(),
+                        SNAPSHOT_BASELINE_COMMAND.to_string(),
+                        FIX_AND_FORMAT_COMMAND.to_string(),
+                        EXPORT_TEXTLINT_JSON_COMMAND.to_string(),
+                        RELINT_ALL_COMMAND.to_string(),
+                        DOCTOR_COMMAND.to_string(),
+                        LINT_NOW_COMMAND.to_string(),
+                        FIX_ALL_COMMAND.to_string(),
+                        COPY_RULE_ID_COMMAND.to_string(),
+                    ],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    async fn initialized(&self, _params: InitializedParams) {
+        let _ = self.initialized_at.set(Instant::now());
+
+        if self.supports_configuration() {
+            self.pull_configuration().await;
+        }
+
+        if self.supports_watched_files() {
+            self.register_watchers().await;
+        }
+
+        if !self.config().warmup {
+            return;
+        }
+        let Some(work_dir) = self.root_dir.get().cloned() else {
+            return;
+        };
+
+        // 初回 lint の待ち時間を減らすため、バックグラウンドで textlint を起動しておく
+        let runner = Arc::clone(&self.runner);
+        let client = self.client.clone();
+        let options = self.run_options();
+        tokio::spawn(async move {
+            if let Err(e) = runner.warmup(&work_dir, &options).await {
+                client
+                    .log_message(MessageType::WARNING, e.to_string())
+                    .await;
+            }
+        });
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let uri = params.text_document.uri;
+        if !self.is_target_extension(&uri) {
+            return;
+        }
+        let language_id = params.text_document.language_id;
+        self.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: params.text_document.version,
+                text: params.text_document.text,
+            },
+        );
+        if !self.is_active_document(&uri) {
+            return;
+        }
+        if let Some(remaining) = self.startup_grace_remaining() {
+            // セッション復元などで大量に開かれたときに textlint を一斉に起動しない
+            self.queue_startup_open(uri, language_id, remaining);
+            return;
+        }
+        self.lint_and_publish(&uri, Some(language_id)).await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        // state のテキストは lint_and_publish で lint した内容にそろえるため、
+        // ここでは更新しない。did_change でテキストを上書きすると
+        // textlint の fix.range オフセットとの不整合が発生する。
+        // 最新の内容は buffers に保持し、次の lint と古くなった fix の検出に使う。
+        if params.content_changes.is_empty() {
+            return;
+        }
+        let uri = params.text_document.uri;
+        if !self.is_target_extension(&uri) {
+            return;
+        }
+        let (mut text, version) = match self.buffers.get(&uri) {
+            Some(buffer) => (buffer.text.clone(), Some(buffer.version)),
+            None => (
+                self.state
+                    .get(&uri)
+                    .map(|doc| doc.text.clone())
+                    .unwrap_or_default(),
+                None,
+            ),
+        };
+        let encoding = self.encoding();
+        // lint した内容からの編集を追えるときは、書き換えられた問題を次の lint を待たずに消す。
+        let mut doc = self
+            .state
+            .get_mut(&uri)
+            .filter(|doc| doc.shifted.is_some() || doc.version == version);
+        let mut dropped = false;
+        for change in params.content_changes {
+            if let Some(doc) = doc.as_mut() {
+                dropped |= doc.apply_change(&change, encoding);
+            }
+            apply_content_change(&mut text, change, encoding);
+        }
+        drop(doc);
+        self.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: params.text_document.version,
+                text,
+            },
+        );
+        if dropped {
+            self.publish(&uri).await;
+        }
+        if self.is_active_document(&uri) {
+            self.schedule_lint(uri);
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        // 閉じたドキュメントの状態を残すと長いセッションでメモリを使い続ける。
+        // ベースラインはユーザーが記録したものなので、開き直しても使えるよう残す
+        let uri = params.text_document.uri;
+        self.state.remove(&uri);
+        self.buffers.remove(&uri);
+        self.pending_lints.remove(&uri);
+        self.missing_install.remove(&uri);
+        self.lint_locks.remove(&uri);
+        self.lint_generations.remove(&uri);
+        self.active_document
+            .rcu(|active| active.clone().filter(|active| **active != uri));
+        self.client.publish_diagnostics(uri, Vec::new(), None).await;
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        // ichigyo のセクションを送ってこないクライアントには workspace/configuration で問い合わせる。
+        // 空のオブジェクトや他の拡張の設定で initializationOptions を上書きしない
+        let watchers = self.file_watchers();
+        let section = params
+            .settings
+            .get(config::SECTION)
+            .filter(|section| section.is_object());
+        if let Some(section) = section {
+            self.store_config(section.clone()).await;
+        } else if self.supports_configuration() {
+            self.pull_configuration().await;
+        } else {
+            return;
+        }
+        // 監視する拡張子が変わっていれば登録し直す。
+        // ルールや textlint の実行方法が変わっているかもしれないので、すべて lint し直す
+        let reregister = self.supports_watched_files() && self.file_watchers() != watchers;
+        let this = self.clone();
+        tokio::spawn(async move {
+            if reregister {
+                this.reregister_watchers().await;
+            }
+            this.relint_all().await
+        });
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        // ルールが変わったかもしれないので、lint 済みのドキュメントをすべて lint し直す
+        if params
+            .changes
+            .iter()
+            .any(|change| is_textlint_config(&change.uri))
+        {
+            self.relint_all().await;
+            return;
+        }
+        if !self.config().relint_on_external_change {
+            return;
+        }
+        // フォーマッタや git 操作などでディスク上の内容が変わったドキュメントを lint し直す。
+        // 診断はエディタ上の内容に対して出すので、バッファの内容のまま textlint を実行し直す
+        for uri in self.externally_changed(&params.changes).await {
+            self.relint_and_publish(&uri).await;
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        // didOpen されていないファイルでも状態を作り、以降の code_action で使えるようにする
+        let uri = params.text_document.uri;
+        if !self.is_target_extension(&uri) || !self.is_active_document(&uri) {
+            return;
+        }
+        self.lint_and_publish(&uri, None).await;
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let Some(doc) = self.state.get(uri) else {
+            return Ok(None);
+        };
+
+        let width = self.config().message_wrap_width.unwrap_or(0);
+        let sections: Vec<String> = doc
+            .messages
+            .iter()
+            .filter(|msg| {
+                let range = lsp_range(textlint::message_range(&doc.text, msg, self.encoding()));
+                range_contains(&range, position)
+            })
+            .map(|msg| {
+                format!(
+                    "**{}**  \n{}",
+                    msg.rule_id,
+                    wrap_message(&msg.message, width)
+                )
+            })
+            .collect();
+        if sections.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: sections.join("\n\n---\n\n"),
+            }),
+            range: None,
+        }))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        // 設定で提供していて、かつクライアントが `only` で要求した kind だけを返す
+        let only = params.context.only.as_deref();
+        let wants = |kind: &CodeActionKind| {
+            self.offers_kind(kind)
+                && only
+                    .is_none_or(|only| only.iter().any(|requested| kind_matches(requested, kind)))
+        };
+        // 要求された kind をどれも返せないなら、メッセージを走査せずに終える
+        if !SUPPORTED_CODE_ACTION_KINDS.iter().any(wants) {
+            return Ok(None);
+        }
+
+        let uri = &params.text_document.uri;
+        // end が start より前の範囲を送るクライアントもあるので、向きをそろえる
+        let request_range = if params.range.end < params.range.start {
+            Range::new(params.range.end, params.range.start)
+        } else {
+            params.range
+        };
+        let invoked = params.context.trigger_kind == Some(CodeActionTriggerKind::INVOKED);
+
+        let mut actions = Vec::new();
+        let mut skipped = Vec::new();
+
+        let wants_quickfix = wants(&CodeActionKind::QUICKFIX);
+        let wants_copy = wants(&CodeActionKind::EMPTY);
+        if wants_quickfix || wants_copy {
+            let entry = match self.state.get(uri) {
+                Some(e) => e,
+                None => return Ok(None),
+            };
+            let doc = entry.value();
+            let DocumentState { text, messages, .. } = doc;
+
+            // クライアントが渡した診断に対応するものを先に、次にエラーを警告より先に、
+            // 同じ重大度なら位置順に並べる
+            let requested = &params.context.diagnostics;
+            let mut candidates: Vec<(&TextlintMessage, textlint::Range)> = messages
+                .iter()
+                .map(|msg| (msg, textlint::message_range(text, msg, self.encoding())))
+                .collect();
+            candidates.sort_by_key(|(msg, range)| {
+                let range = lsp_range(*range);
+                (
+                    !requested.iter().any(|diagnostic| {
+                        diagnostic.range == range
+                            && diagnostic.code.as_ref()
+                                == Some(&NumberOrString::String(msg.rule_id.clone()))
+                    }),
+                    self.severity(msg) != DiagnosticSeverity::ERROR,
+                    range.start.line,
+                    range.start.character,
+                    msg.rule_id.clone(),
+                )
+            });
+
+            // lint 後にドキュメントが短くなっていれば、はみ出した行の問題は古いので扱わない
+            let current_lines = self.current_line_count(uri, text);
+            let mut disable_actions = Vec::new();
+            let mut disabled_lines = HashSet::new();
+            let mut copy_actions = Vec::new();
+            let mut copied_rules = HashSet::new();
+
+            for (msg, msg_range) in candidates {
+                if msg.line > current_lines {
+                    continue;
+                }
+                if msg_range.end.line < request_range.start.line
+                    || msg_range.start.line > request_range.end.line
+                {
+                    continue;
+                }
+
+                if wants_copy && copied_rules.insert(msg.rule_id.as_str()) {
+                    copy_actions.push(copy_rule_id_action(&msg.rule_id));
+                }
+                if !wants_quickfix {
+                    continue;
+                }
+
+                // 同じ行の同じルールには 1 つだけ出す
+                if disabled_lines.insert((msg.rule_id.as_str(), msg_range.start.line)) {
+                    disable_actions.extend(self.disable_line_action(
+                        uri,
+                        doc,
+                        &msg.rule_id,
+                        msg_range.start.line,
+                    ));
+                }
+
+                let title = format!(
+                    "Fix: {} ({})",
+                    truncate_message(&msg.message, self.title_length()),
+                    msg.rule_id
+                );
+
+                // 古い内容に対する fix を適用するとテキストを壊すので、再 lint を促す。
+                // 確認が必要なルールの fix は、明示的に code action を呼び出したときだけ適用できる
+                let disabled_reason = if self.fix_is_stale(uri, doc, msg) {
+                    Some(STALE_FIX_REASON)
+                } else if msg.fix.is_some() && self.requires_confirmation(&msg.rule_id) && !invoked
+                {
+                    Some("fix requires confirmation; invoke code actions explicitly to apply")
+                } else {
+                    None
+                };
+                if let Some(reason) = disabled_reason {
+                    if self.supports_disabled_code_actions() {
+                        actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                            title,
+                            kind: Some(CodeActionKind::QUICKFIX),
+                            disabled: Some(CodeActionDisabled {
+                                reason: reason.to_string(),
+                            }),
+                            ..Default::default()
+                        }));
+                    }
+                    continue;
+                }
+
+                let edit = match self.fix_edit(text, msg) {
+                    Ok(Some(e)) => e,
+                    Ok(None) => continue,
+                    Err(reason) => {
+                        let range = msg.fix.as_ref().map(|f| f.range).unwrap_or_default();
+                        skipped.push(format!(
+                            "skipped fix for {} at {:?}: {reason}",
+                            msg.rule_id, range
+                        ));
+                        continue;
+                    }
+                };
+
+                // メッセージに別の候補が挙がっていれば、textlint の fix を優先として並べる
+                let alternatives = textlint::alternative_replacements(msg);
+                let preferred = (!alternatives.is_empty()).then_some(true);
+                let alternative_actions: Vec<CodeAction> = alternatives
+                    .into_iter()
+                    .map(|candidate| CodeAction {
+                        title: format!("Replace with \"{candidate}\" ({})", msg.rule_id),
+                        kind: Some(CodeActionKind::QUICKFIX),
+                        edit: Some(WorkspaceEdit {
+                            changes: Some(HashMap::from([(
+                                uri.clone(),
+                                vec![TextEdit {
+                                    range: edit.range,
+                                    new_text: candidate,
+                                }],
+                            )])),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    })
+                    .collect();
+
+                let mut changes = HashMap::new();
+                changes.insert(uri.clone(), vec![edit]);
+
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title,
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(changes),
+                        ..Default::default()
+                    }),
+                    is_preferred: preferred,
+                    ..Default::default()
+                }));
+                actions.extend(
+                    alternative_actions
+                        .into_iter()
+                        .map(CodeActionOrCommand::CodeAction),
+                );
+            }
+
+            actions.extend(
+                disable_actions
+                    .into_iter()
+                    .chain(copy_actions)
+                    .map(CodeActionOrCommand::CodeAction),
+            );
+        }
+
+        if wants(&CodeActionKind::SOURCE_FIX_ALL) {
+            if let Some(action) = self.fix_all_action(uri).await {
+                actions.push(CodeActionOrCommand::CodeAction(action));
+            }
+        }
+
+        for message in skipped {
+            self.client.log_message(MessageType::LOG, message).await;
+        }
+
+        if actions.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(actions))
+        }
+    }
+
+    async fn diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult> {
+        let uri = &params.text_document.uri;
+        self.load_rule_config(uri).await;
+        let items = self.document_diagnostics(uri);
+        Ok(DocumentDiagnosticReportResult::Report(
+            DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: None,
+                    items,
+                },
+            }),
+        ))
+    }
+
+    async fn workspace_diagnostic(
+        &self,
+        _params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult> {
+        Ok(WorkspaceDiagnosticReportResult::Report(
+            self.workspace_report().await,
+        ))
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        Ok(self
+            .format_document(&uri, &params.options)
+            .await
+            .map(|edit| vec![edit]))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        match params.command.as_str() {
+            APPLY_FIX_AT_COMMAND => {
+                let (uri, position, rule_id): (Url, Position, String) =
+                    serde_json::from_value(Value::Array(params.arguments)).map_err(|e| {
+                        Error::invalid_params(format!("expected [uri, position, ruleId]: {e}"))
+                    })?;
+                let applied = self.apply_fix_at(&uri, position, &rule_id).await;
+                Ok(Some(Value::Bool(applied)))
+            }
+            SNAPSHOT_BASELINE_COMMAND => {
+                let uri = match params.arguments.into_iter().next() {
+                    Some(v) => Some(
+                        serde_json::from_value::<Url>(v)
+                            .map_err(|e| Error::invalid_params(format!("expected [uri]: {e}")))?,
+                    ),
+                    None => None,
+                };
+                let count = self.snapshot_baseline(uri).await;
+                Ok(Some(Value::from(count)))
+            }
+            FIX_AND_FORMAT_COMMAND => {
+                let (uri,): (Url,) = serde_json::from_value(Value::Array(params.arguments))
+                    .map_err(|e| Error::invalid_params(format!("expected [uri]: {e}")))?;
+                let completed = self.fix_and_format(&uri).await;
+                Ok(Some(Value::Bool(completed)))
+            }
+            EXPORT_TEXTLINT_JSON_COMMAND => {
+                let (uri,): (Url,) = serde_json::from_value(Value::Array(params.arguments))
+                    .map_err(|e| Error::invalid_params(format!("expected [uri]: {e}")))?;
+                Ok(self.export_textlint_json(&uri))
+            }
+            RELINT_ALL_COMMAND => Ok(Some(self.relint_all().await)),
+            DOCTOR_COMMAND => Ok(Some(self.doctor().await)),
+            COPY_RULE_ID_COMMAND => {
+                let (rule_id,): (String,) = serde_json::from_value(Value::Array(params.arguments))
+                    .map_err(|e| Error::invalid_params(format!("expected [ruleId]: {e}")))?;
+                self.client
+                    .show_message(MessageType::INFO, format!("textlint rule ID: {rule_id}"))
+                    .await;
+                Ok(Some(Value::String(rule_id)))
+            }
+            FIX_ALL_COMMAND => {
+                let (uri,): (Url,) = serde_json::from_value(Value::Array(params.arguments))
+                    .map_err(|e| Error::invalid_params(format!("expected [uri]: {e}")))?;
+                Ok(Some(Value::from(self.fix_all(&uri).await)))
+            }
+            LINT_NOW_COMMAND => {
+                let (uri,): (Url,) = serde_json::from_value(Value::Array(params.arguments))
+                    .map_err(|e| Error::invalid_params(format!("expected [uri]: {e}")))?;
+                // 待っている didChange の lint は不要になるので取り消す
+                self.pending_lints.remove(&uri);
+                self.relint_and_publish(&uri).await;
+                Ok(None)
+            }
+            _ => Err(Error::invalid_params(format!(
+                "unknown command: {}",
+                params.command
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textlint::{FixCommand, TextlintResult};
+    use futures::{SinkExt, StreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+    use tower::{Service, ServiceExt};
+    use tower_lsp::jsonrpc::{Request, Response};
+
+    struct MockRunner {
+        results: Mutex<Vec<TextlintResult>>,
+        warmed_up: Notify,
+        /// run の所要時間。並行実行の検証に使う。
+        delay: Duration,
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+        /// 設定されていれば run がこの種類の I/O エラーで失敗する。
+        failure: Mutex<Option<std::io::ErrorKind>>,
+        /// run に渡された `RunOptions::command` の履歴。
+        commands: Mutex<Vec<Option<Vec<String>>>>,
+        /// run に渡されたファイルの履歴。
+        files: Mutex<Vec<PathBuf>>,
+        /// run に渡された `RunOptions::binary` の履歴。
+        binaries: Mutex<Vec<Option<PathBuf>>>,
+        /// version が呼ばれた回数。
+        version_probes: AtomicUsize,
+        /// run_stdin に渡されたテキストの履歴。
+        texts: Mutex<Vec<String>>,
+        /// fix_stdin が返すテキスト。`None` なら入力をそのまま返す。
+        fixed: Mutex<Option<String>>,
+    }
+
+    impl MockRunner {
+        fn new(results: Vec<TextlintResult>) -> Self {
+            Self {
+                results: Mutex::new(results),
+                warmed_up: Notify::new(),
+                delay: Duration::ZERO,
+                running: AtomicUsize::new(0),
+                max_running: AtomicUsize::new(0),
+                failure: Mutex::new(None),
+                commands: Mutex::new(Vec::new()),
+                files: Mutex::new(Vec::new()),
+                binaries: Mutex::new(Vec::new()),
+                version_probes: AtomicUsize::new(0),
+                texts: Mutex::new(Vec::new()),
+                fixed: Mutex::new(None),
+            }
+        }
+
+        fn with_delay(results: Vec<TextlintResult>, delay: Duration) -> Self {
+            Self {
+                delay,
+                ..Self::new(results)
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl TextlintRunner for MockRunner {
+        async fn run(
+            &self,
+            file_path: &Path,
+            _work_dir: &Path,
+            options: &RunOptions,
+        ) -> anyhow::Result<Vec<TextlintResult>> {
+            self.files.lock().unwrap().push(file_path.to_path_buf());
+            self.commands.lock().unwrap().push(options.command.clone());
+            self.binaries.lock().unwrap().push(options.binary.clone());
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            if !self.delay.is_zero() {
+                tokio::time::sleep(self.delay).await;
+            }
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            if let Some(kind) = *self.failure.lock().unwrap() {
+                return Err(std::io::Error::from(kind).into());
+            }
+            let results = self.results.lock().unwrap().clone();
+            Ok(results)
+        }
+
+        async fn run_stdin(
+            &self,
+            text: &str,
+            file_path: &Path,
+            work_dir: &Path,
+            options: &RunOptions,
+        ) -> anyhow::Result<Vec<TextlintResult>> {
+            self.texts.lock().unwrap().push(text.to_string());
+            self.run(file_path, work_dir, options).await
+        }
+
+        async fn fix_stdin(
+            &self,
+            text: &str,
+            _file_path: &Path,
+            _work_dir: &Path,
+            _options: &RunOptions,
+        ) -> anyhow::Result<String> {
+            let fixed = self.fixed.lock().unwrap().clone();
+            Ok(fixed.unwrap_or_else(|| text.to_string()))
+        }
+
+        async fn warmup(&self, _work_dir: &Path, _options: &RunOptions) -> anyhow::Result<()> {
+            self.warmed_up.notify_one();
+            Ok(())
+        }
+
+        async fn version(&self, _work_dir: &Path, _options: &RunOptions) -> anyhow::Result<String> {
+            self.version_probes.fetch_add(1, Ordering::SeqCst);
+            Ok("14.0.4".to_string())
+        }
+    }
+
+    /// LspService 経由で initialize を送り、クライアントへ通知・リクエストを送れる状態にする。
+    async fn initialized(
+        runner: MockRunner,
+        options: Value,
+    ) -> (LspService<Backend<MockRunner>>, ClientSocket) {
+        initialized_with(runner, serde_json::json!({}), options).await
+    }
+
+    async fn initialized_with(
+        runner: MockRunner,
+        capabilities: Value,
+        options: Value,
+    ) -> (LspService<Backend<MockRunner>>, ClientSocket) {
+        let (mut service, socket) = Backend::service(runner);
+        let request = Request::build("initialize")
+            .params(serde_json::json!({
+                "capabilities": capabilities,
+                "initializationOptions": options,
+            }))
+            .id(1)
+            .finish();
+        service.ready().await.unwrap().call(request).await.unwrap();
+        (service, socket)
+    }
+
+    #[tokio::test]
+    async fn initialize_returns_expected_capabilities() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+
+        let params = InitializeParams::default();
+        let result = service.inner().initialize(params).await.unwrap();
+
+        assert!(result.capabilities.code_action_provider.is_some());
+        assert!(result.capabilities.text_document_sync.is_some());
+        assert_eq!(
+            result.capabilities.position_encoding,
+            Some(PositionEncodingKind::UTF16)
+        );
+        assert_eq!(service.inner().encoding(), PositionEncoding::Utf16);
+    }
+
+    fn initialize_params_with_encodings(encodings: Vec<PositionEncodingKind>) -> InitializeParams {
+        InitializeParams {
+            capabilities: ClientCapabilities {
+                general: Some(GeneralClientCapabilities {
+                    position_encodings: Some(encodings),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn initialize_echoes_utf8_when_client_offers_only_utf8() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+
+        let params = initialize_params_with_encodings(vec![PositionEncodingKind::UTF8]);
+        let result = service.inner().initialize(params).await.unwrap();
+
+        assert_eq!(
+            result.capabilities.position_encoding,
+            Some(PositionEncodingKind::UTF8)
+        );
+        assert_eq!(service.inner().encoding(), PositionEncoding::Utf8);
+    }
+
+    #[test]
+    fn negotiate_encoding_prefers_utf8_then_utf32() {
+        let all = initialize_params_with_encodings(vec![
+            PositionEncodingKind::UTF16,
+            PositionEncodingKind::UTF32,
+            PositionEncodingKind::UTF8,
+        ]);
+        assert_eq!(negotiate_encoding(&all).0, PositionEncoding::Utf8);
+
+        let no_utf8 = initialize_params_with_encodings(vec![
+            PositionEncodingKind::UTF16,
+            PositionEncodingKind::UTF32,
+        ]);
+        assert_eq!(negotiate_encoding(&no_utf8).0, PositionEncoding::Utf32);
+
+        let unknown = initialize_params_with_encodings(vec![PositionEncodingKind::new("ucs-2")]);
+        assert_eq!(negotiate_encoding(&unknown).0, PositionEncoding::Utf16);
+    }
+
+    #[tokio::test]
+    async fn code_action_returns_quickfix_for_fixable_message() {
+        let results = vec![TextlintResult {
+            file_path: "./test.md".to_string(),
+            messages: vec![TextlintMessage {
+                rule_id: "no-doubled-joshi".to_string(),
+                message: "助詞の重複".to_string(),
+                line: 1,
+                column: 5,
+                index: None,
+                loc: None,
+                severity: 2,
+                fix: Some(FixCommand {
+                    range: [6, 7],
+                    text: "けれど".to_string(),
+                }),
+            }],
+        }];
+
+        let runner = MockRunner::new(results);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        // simulate lint state
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let text = "test がが error";
+        backend.state.insert(
+            uri.clone(),
+            document(
+                text,
+                vec![TextlintMessage {
+                    rule_id: "no-doubled-joshi".to_string(),
+                    message: "助詞の重複".to_string(),
+                    line: 1,
+                    column: 5,
+                    index: None,
+                    loc: None,
+                    severity: 2,
+                    fix: Some(FixCommand {
+                        range: [6, 7],
+                        text: "けれど".to_string(),
+                    }),
+                }],
+            ),
+        );
+
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            range: Range {
+                start: Position::new(0, 0),
+                end: Position::new(0, 10),
+            },
+            context: CodeActionContext::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+
+        let result = backend.code_action(params).await.unwrap();
+        let actions = result.unwrap();
+        assert_eq!(actions.len(), 4);
+
+        if let CodeActionOrCommand::CodeAction(action) = &actions[0] {
+            assert_eq!(action.kind, Some(CodeActionKind::QUICKFIX));
+            assert!(action.title.contains("no-doubled-joshi"));
+        } else {
+            panic!("expected CodeAction");
+        }
+        if let CodeActionOrCommand::CodeAction(action) = &actions[1] {
+            assert_eq!(action.title, "Disable no-doubled-joshi for this line");
+        } else {
+            panic!("expected CodeAction");
+        }
+        if let CodeActionOrCommand::CodeAction(action) = &actions[2] {
+            assert_eq!(action.title, "Copy rule ID: no-doubled-joshi");
+            assert_eq!(action.kind, Some(CodeActionKind::EMPTY));
+        } else {
+            panic!("expected CodeAction");
+        }
+        if let CodeActionOrCommand::CodeAction(action) = &actions[3] {
+            assert_eq!(action.kind, Some(CodeActionKind::SOURCE_FIX_ALL));
+        } else {
+            panic!("expected CodeAction");
+        }
+    }
+
+    #[tokio::test]
+    async fn code_action_offers_only_disable_for_no_fix() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "text",
+                vec![TextlintMessage {
+                    rule_id: "max-ten".to_string(),
+                    message: "読点が多い".to_string(),
+                    line: 1,
+                    column: 1,
+                    index: None,
+                    loc: None,
+                    severity: 1,
+                    fix: None,
+                }],
+            ),
+        );
+
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            range: Range {
+                start: Position::new(0, 0),
+                end: Position::new(0, 5),
+            },
+            context: CodeActionContext::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+
+        let actions = backend.code_action(params).await.unwrap().unwrap();
+        assert_eq!(actions.len(), 2);
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected CodeAction");
+        };
+        assert_eq!(action.title, "Disable max-ten for this line");
+        assert_eq!(action.kind, Some(CodeActionKind::QUICKFIX));
+        // 最終行なので、閉じるコメントは行末に改行を足して入れる
+        let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+        assert_eq!(
+            edits,
+            &vec![
+                TextEdit {
+                    range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+                    new_text: "<!-- textlint-disable max-ten -->\n".to_string(),
+                },
+                TextEdit {
+                    range: Range::new(Position::new(0, 4), Position::new(0, 4)),
+                    new_text: "\n<!-- textlint-enable max-ten -->".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn code_action_disable_line_extends_existing_comments() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let on_line = |rule_id: &str, line| TextlintMessage {
+            rule_id: rule_id.to_string(),
+            line,
+            fix: None,
+            ..futatsu_message()
+        };
+        let target = &uri;
+        let disable = |text: String, rule_id: &'static str, line: u32| async move {
+            let params = code_action_params(
+                target.clone(),
+                Range::new(Position::new(line, 0), Position::new(line, 0)),
+            );
+            let actions = backend.code_action(params).await.unwrap().unwrap();
+            let edits = actions
+                .into_iter()
+                .find_map(|action| match action {
+                    CodeActionOrCommand::CodeAction(a)
+                        if a.title == format!("Disable {rule_id} for this line") =>
+                    {
+                        a.edit.unwrap().changes.unwrap().remove(target)
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            let mut text = text;
+            for edit in edits.into_iter().rev() {
+                let change = TextDocumentContentChangeEvent {
+                    range: Some(edit.range),
+                    range_length: None,
+                    text: edit.new_text,
+                };
+                apply_content_change(&mut text, change, PositionEncoding::Utf16);
+            }
+            text
+        };
+
+        let text = "あ、い、う\n".to_string();
+        backend.state.insert(
+            uri.clone(),
+            document(&text, vec![on_line("max-ten", 1), on_line("prh", 1)]),
+        );
+        let text = disable(text, "max-ten", 0).await;
+        assert_eq!(
+            text,
+            "<!-- textlint-disable max-ten -->\nあ、い、う\n<!-- textlint-enable max-ten -->\n"
+        );
+
+        // lint し直すと max-ten の問題は消え、prh の問題だけが 2 行目に残る
+        backend
+            .state
+            .insert(uri.clone(), document(&text, vec![on_line("prh", 2)]));
+        let text = disable(text, "prh", 1).await;
+        assert_eq!(
+            text,
+            "<!-- textlint-disable max-ten, prh -->\nあ、い、う\n<!-- textlint-enable max-ten, prh -->\n"
+        );
+
+        // 書き足したルールには出さない
+        backend
+            .state
+            .insert(uri.clone(), document(&text, vec![on_line("prh", 2)]));
+        let params = code_action_params(
+            uri.clone(),
+            Range::new(Position::new(1, 0), Position::new(1, 0)),
+        );
+        let actions = backend.code_action(params).await.unwrap().unwrap();
+        assert!(actions.iter().all(|action| match action {
+            CodeActionOrCommand::CodeAction(a) => !a.title.starts_with("Disable"),
+            CodeActionOrCommand::Command(_) => true,
+        }));
+    }
+
+    #[test]
+    fn directive_rules_parses_rule_lists() {
+        assert_eq!(
+            directive_rules("<!-- textlint-disable a, b -->", "textlint-disable"),
+            Some(vec!["a", "b"])
+        );
+        assert_eq!(
+            directive_rules("<!-- textlint-disable -->", "textlint-disable"),
+            Some(vec![])
+        );
+        assert_eq!(
+            directive_rules("<!-- textlint-disable-next-line a -->", "textlint-disable"),
+            None
+        );
+        assert_eq!(directive_rules("本文", "textlint-disable"), None);
+    }
+
+    #[tokio::test]
+    async fn code_action_disable_line_wraps_the_message_line() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let max_ten = |line| TextlintMessage {
+            rule_id: "max-ten".to_string(),
+            message: "読点が多い".to_string(),
+            line,
+            column: 1,
+            index: None,
+            loc: None,
+            severity: 1,
+            fix: None,
+        };
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "# 見出し\nあ、い、う\n<!-- textlint-disable max-ten -->\nえ、お\n",
+                vec![max_ten(2), max_ten(4)],
+            ),
+        );
+
+        let code_actions = |line| {
+            let params = CodeActionParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+                range: Range::new(Position::new(line, 0), Position::new(line, 0)),
+                context: CodeActionContext::default(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            };
+            backend.code_action(params)
+        };
+
+        let actions = code_actions(1).await.unwrap().unwrap();
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected CodeAction");
+        };
+        let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+        assert_eq!(
+            edits,
+            &vec![
+                TextEdit {
+                    range: Range::new(Position::new(1, 0), Position::new(1, 0)),
+                    new_text: "<!-- textlint-disable max-ten -->\n".to_string(),
+                },
+                TextEdit {
+                    range: Range::new(Position::new(2, 0), Position::new(2, 0)),
+                    new_text: "<!-- textlint-enable max-ten -->\n".to_string(),
+                },
+            ]
+        );
+
+        // 直前の行ですでに無効にしていれば出さない
+        let actions = code_actions(3).await.unwrap().unwrap();
+        let titles: Vec<&str> = actions
+            .iter()
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(a) => a.title.as_str(),
+                CodeActionOrCommand::Command(c) => c.title.as_str(),
+            })
+            .collect();
+        assert_eq!(titles, ["Copy rule ID: max-ten"]);
+    }
+
+    #[tokio::test]
+    async fn code_action_disable_line_keeps_crlf_line_endings() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let max_ten = |line| TextlintMessage {
+            rule_id: "max-ten".to_string(),
+            message: "読点が多い".to_string(),
+            line,
+            column: 1,
+            index: None,
+            loc: None,
+            severity: 1,
+            fix: None,
+        };
+        backend.state.insert(
+            uri.clone(),
+            document("あ、い\r\nう、え", vec![max_ten(1), max_ten(2)]),
+        );
+
+        let disable_edits = |line| {
+            let params = CodeActionParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+                range: Range::new(Position::new(line, 0), Position::new(line, 0)),
+                context: CodeActionContext::default(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            };
+            let uri = uri.clone();
+            async move {
+                let actions = backend.code_action(params).await.unwrap().unwrap();
+                let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+                    panic!("expected CodeAction");
+                };
+                let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+                edits
+                    .iter()
+                    .map(|edit| edit.new_text.clone())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            disable_edits(0).await,
+            [
+                "<!-- textlint-disable max-ten -->\r\n",
+                "<!-- textlint-enable max-ten -->\r\n",
+            ]
+        );
+        // 最終行では行末に改行を足してから閉じる
+        assert_eq!(
+            disable_edits(1).await,
+            [
+                "<!-- textlint-disable max-ten -->\r\n",
+                "\r\n<!-- textlint-enable max-ten -->",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn code_action_title_truncates_long_message() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = InitializeParams {
+            initialization_options: Some(serde_json::json!({ "titleMaxLength": 10 })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let long_message = "一文に二回以上利用されている助詞 \"が\" がみつかりました。";
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "test がが error",
+                vec![TextlintMessage {
+                    rule_id: "no-doubled-joshi".to_string(),
+                    message: long_message.to_string(),
+                    line: 1,
+                    column: 5,
+                    index: None,
+                    loc: None,
+                    severity: 2,
+                    fix: Some(FixCommand {
+                        range: [6, 7],
+                        text: "けれど".to_string(),
+                    }),
+                }],
+            ),
+        );
+
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            range: Range {
+                start: Position::new(0, 0),
+                end: Position::new(0, 10),
+            },
+            context: CodeActionContext::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+
+        let actions = backend.code_action(params).await.unwrap().unwrap();
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected CodeAction");
+        };
+        assert_eq!(action.title, "Fix: 一文に二回以上利用… (no-doubled-joshi)");
+        assert!(!action.title.contains(long_message));
+    }
+
+    #[tokio::test]
+    async fn code_action_sorts_by_severity_then_position() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let fixable = |rule_id: &str, severity: u32, column: u32| TextlintMessage {
+            rule_id: rule_id.to_string(),
+            column,
+            severity,
+            fix: Some(FixCommand {
+                range: [(column - 1) as usize, column as usize],
+                text: "x".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "abcdefghij",
+                vec![
+                    fixable("warning-late", 1, 8),
+                    fixable("error-late", 2, 6),
+                    fixable("warning-early", 1, 2),
+                    fixable("error-early", 2, 4),
+                ],
+            ),
+        );
+
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let actions = backend
+            .code_action(code_action_params(uri, range))
+            .await
+            .unwrap()
+            .unwrap();
+        let titles: Vec<String> = actions
+            .into_iter()
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(a) => a.title,
+                CodeActionOrCommand::Command(c) => c.title,
+            })
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                "Fix: ふたつ => 2つ (error-early)",
+                "Fix: ふたつ => 2つ (error-late)",
+                "Fix: ふたつ => 2つ (warning-early)",
+                "Fix: ふたつ => 2つ (warning-late)",
+                "Disable error-early for this line",
+                "Disable error-late for this line",
+                "Disable warning-early for this line",
+                "Disable warning-late for this line",
+                "Copy rule ID: error-early",
+                "Copy rule ID: error-late",
+                "Copy rule ID: warning-early",
+                "Copy rule ID: warning-late",
+                FIX_ALL_TITLE,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn code_action_withholds_fix_when_edited_text_no_longer_matches() {
+        let capabilities = serde_json::json!({
+            "textDocument": { "codeAction": { "disabledSupport": true } }
+        });
+        let (service, _) =
+            initialized_with(MockRunner::new(vec![]), capabilities, Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let mut doc = document("ふたつの項目がある。", vec![futatsu_message()]);
+        doc.version = Some(1);
+        backend.state.insert(uri.clone(), doc);
+        // 編集後、まだ lint し直していない
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 2,
+                text: "これはふたつの項目がある。".to_string(),
+            },
+        );
+
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let actions = backend
+            .code_action(code_action_params(uri.clone(), range))
+            .await
+            .unwrap()
+            .unwrap();
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected CodeAction");
+        };
+        assert!(action.edit.is_none());
+        assert!(action.disabled.is_some());
+
+        // fix.range の部分が変わっていなければ、編集後でも適用できる
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 3,
+                text: "ふたつの項目がある。追記".to_string(),
+            },
+        );
+        let actions = backend
+            .code_action(code_action_params(uri, range))
+            .await
+            .unwrap()
+            .unwrap();
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected CodeAction");
+        };
+        assert!(action.edit.is_some());
+    }
+
+    #[tokio::test]
+    async fn confirm_fix_rules_guard_quickfix_until_invoked() {
+        let capabilities = serde_json::json!({
+            "textDocument": { "codeAction": { "disabledSupport": true } }
+        });
+        let (service, _) = initialized_with(
+            MockRunner::new(vec![]),
+            capabilities,
+            serde_json::json!({ "confirmFixRules": ["ja-no-redundant-expression"] }),
+        )
+        .await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let risky = TextlintMessage {
+            rule_id: "ja-no-redundant-expression".to_string(),
+            fix: Some(FixCommand {
+                range: [7, 9],
+                text: "あり".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message(), risky]),
+        );
+
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let actions_for = |trigger_kind| {
+            let mut params = code_action_params(uri.clone(), range);
+            params.context.trigger_kind = Some(trigger_kind);
+            async {
+                backend
+                    .code_action(params)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .into_iter()
+                    .filter_map(|action| match action {
+                        // ルール ID をコピーする action は確認の有無と関係ない
+                        CodeActionOrCommand::CodeAction(a) if a.command.is_none() => Some(a),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let automatic = actions_for(CodeActionTriggerKind::AUTOMATIC).await;
+        let guarded: Vec<(bool, bool)> = automatic
+            .iter()
+            .map(|a| (a.edit.is_some(), a.disabled.is_some()))
+            .collect();
+        // fix 2 つ (1 つは無効)、行を無効にする action 2 つ、fix-all の順
+        assert_eq!(
+            guarded,
+            [
+                (true, false),
+                (false, true),
+                (true, false),
+                (true, false),
+                (true, false)
+            ]
+        );
+        let fix_all = &automatic[4]
+            .edit
+            .as_ref()
+            .unwrap()
+            .changes
+            .as_ref()
+            .unwrap()[&uri];
+        assert_eq!(fix_all.len(), 1);
+
+        let invoked = actions_for(CodeActionTriggerKind::INVOKED).await;
+        assert!(invoked[1].title.contains("ja-no-redundant-expression"));
+        assert!(invoked[1].edit.is_some());
+        assert!(invoked[1].disabled.is_none());
+    }
+
+    #[tokio::test]
+    async fn open_document_is_linted_from_buffer_via_stdin() {
+        let results = vec![TextlintResult {
+            file_path: "/tmp/unsaved.md".to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let runner = MockRunner::new(results);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        // ディスクには存在しない、保存前のドキュメント
+        let uri = Url::from_file_path("/tmp/ichigyo-ls-unsaved/unsaved.md").unwrap();
+        backend
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    "markdown".to_string(),
+                    1,
+                    "ふたつの項目がある。".to_string(),
+                ),
+            })
+            .await;
+
+        assert_eq!(
+            *backend.runner.texts.lock().unwrap(),
+            ["ふたつの項目がある。"]
+        );
+        let doc = backend.state.get(&uri).expect("state should be created");
+        assert_eq!(doc.text, "ふたつの項目がある。");
+        assert_eq!(doc.version, Some(1));
+        assert_eq!(doc.messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn only_target_extensions_are_linted() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+        let open = |path: &str, language_id: &str| DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                Url::from_file_path(path).unwrap(),
+                language_id.to_string(),
+                1,
+                "ふたつの項目がある。".to_string(),
+            ),
+        };
+
+        backend
+            .did_open(open("/tmp/ichigyo-ls-ext/foo.rs", "rust"))
+            .await;
+        assert!(backend.runner.texts.lock().unwrap().is_empty());
+        assert!(backend.state.is_empty());
+        assert!(backend.buffers.is_empty());
+
+        // 拡張子の大文字小文字は区別しない
+        backend
+            .did_open(open("/tmp/ichigyo-ls-ext/foo.MD", "markdown"))
+            .await;
+        assert_eq!(backend.runner.texts.lock().unwrap().len(), 1);
+        let uri = Url::from_file_path("/tmp/ichigyo-ls-ext/foo.MD").unwrap();
+        assert!(backend.state.contains_key(&uri));
+    }
+
+    #[tokio::test]
+    async fn did_close_clears_state_and_diagnostics() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message()]),
+        );
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 1,
+                text: "ふたつの項目がある。".to_string(),
+            },
+        );
+
+        backend
+            .did_close(DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+            })
+            .await;
+
+        assert!(!backend.state.contains_key(&uri));
+        assert!(!backend.buffers.contains_key(&uri));
+        let notification = socket.next().await.unwrap();
+        assert_eq!(notification.method(), "textDocument/publishDiagnostics");
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+        assert_eq!(params.uri, uri);
+        assert!(params.diagnostics.is_empty());
+    }
+
+    #[tokio::test]
+    async fn code_action_offers_alternative_replacements() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let msg = TextlintMessage {
+            message: "ふたつ => 2つ / 二つ".to_string(),
+            ..futatsu_message()
+        };
+        backend
+            .state
+            .insert(uri.clone(), document("ふたつの項目がある。", vec![msg]));
+
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let actions = backend
+            .code_action(code_action_params(uri.clone(), range))
+            .await
+            .unwrap()
+            .unwrap();
+        let quickfixes: Vec<(String, Option<bool>, String)> = actions
+            .into_iter()
+            .filter_map(|action| match action {
+                CodeActionOrCommand::CodeAction(a)
+                    if a.kind == Some(CodeActionKind::QUICKFIX)
+                        && !a.title.starts_with("Disable ")
+                        && a.command.is_none() =>
+                {
+                    let edits = a.edit.unwrap().changes.unwrap().remove(&uri).unwrap();
+                    assert_eq!(
+                        edits[0].range,
+                        Range::new(Position::new(0, 0), Position::new(0, 3))
+                    );
+                    Some((a.title, a.is_preferred, edits[0].new_text.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            quickfixes,
+            [
+                (
+                    "Fix: ふたつ => 2つ / 二つ (prh)".to_string(),
+                    Some(true),
+                    "2つ".to_string()
+                ),
+                (
+                    "Replace with \"二つ\" (prh)".to_string(),
+                    None,
+                    "二つ".to_string()
+                ),
+            ]
+        );
+    }
+
+    fn ranged_change(
+        start: (u32, u32),
+        end: (u32, u32),
+        text: &str,
+    ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                Position::new(start.0, start.1),
+                Position::new(end.0, end.1),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn content_change_inserts_single_character() {
+        let mut text = "ふたつの項目\nがある。".to_string();
+        apply_content_change(
+            &mut text,
+            ranged_change((0, 3), (0, 3), "、"),
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(text, "ふたつ、の項目\nがある。");
+
+        // UTF-8 では同じ位置が 9 バイト目になる
+        let mut text = "ふたつの項目\nがある。".to_string();
+        apply_content_change(
+            &mut text,
+            ranged_change((0, 9), (0, 9), "、"),
+            PositionEncoding::Utf8,
+        );
+        assert_eq!(text, "ふたつ、の項目\nがある。");
+    }
+
+    #[test]
+    fn content_change_replaces_multiple_lines() {
+        let mut text = "一行目\n二行目\n三行目\n".to_string();
+        apply_content_change(
+            &mut text,
+            ranged_change((0, 2), (2, 1), "の途中から\n三"),
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(text, "一行の途中から\n三行目\n");
+
+        // range がなければ全文の置き換え
+        apply_content_change(
+            &mut text,
+            TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "全文".to_string(),
+            },
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(text, "全文");
+    }
+
+    #[tokio::test]
+    async fn did_change_applies_incremental_changes_in_order() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 1,
+                text: "ふたつの項目がある。\n".to_string(),
+            },
+        );
+        backend
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 2),
+                content_changes: vec![
+                    ranged_change((0, 0), (0, 3), "2つ"),
+                    ranged_change((1, 0), (1, 0), "追記\n"),
+                ],
+            })
+            .await;
+
+        let buffer = backend.buffers.get(&uri).unwrap();
+        assert_eq!(buffer.version, 2);
+        assert_eq!(buffer.text, "2つの項目がある。\n追記\n");
+    }
+
+    #[tokio::test]
+    async fn zero_debounce_lints_on_every_change() {
+        let (service, mut socket) = initialized(
+            MockRunner::new(vec![]),
+            serde_json::json!({ "lintDebounceMs": 0 }),
+        )
+        .await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let params = DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 1),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "ふたつ".to_string(),
+            }],
+        };
+        let change = tokio::time::timeout(Duration::from_millis(100), backend.did_change(params));
+        let (result, _) = tokio::join!(change, socket.next());
+
+        assert!(result.is_ok(), "lint should not wait for the debounce");
+        assert_eq!(backend.runner.files.lock().unwrap().len(), 1);
+        assert!(backend.pending_lints.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn did_change_burst_triggers_single_lint() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let change = |version: i32, text: &str| DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: text.to_string(),
+            }],
+        };
+
+        backend.did_change(change(1, "ふた")).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        backend.did_change(change(2, "ふたつ")).await;
+        assert!(backend.runner.files.lock().unwrap().is_empty());
+        assert!(backend.pending_lints.contains_key(&uri));
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(backend.runner.files.lock().unwrap().len(), 1);
+        assert!(backend.pending_lints.is_empty());
+        assert_eq!(backend.buffers.get(&uri).unwrap().version, 2);
+
+        // 待っている間の変更も含めた最新の内容を lint し、そのバージョンで publish する
+        assert_eq!(*backend.runner.texts.lock().unwrap(), ["ふたつ"]);
+        let notification = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("diagnostics should be published")
+            .unwrap();
+        assert_eq!(notification.method(), "textDocument/publishDiagnostics");
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+        assert_eq!(params.version, Some(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn empty_did_change_does_not_schedule_lint() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 1,
+                text: "ふたつ".to_string(),
+            },
+        );
+        backend
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 2),
+                content_changes: vec![],
+            })
+            .await;
+        assert!(backend.pending_lints.is_empty());
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(backend.runner.files.lock().unwrap().is_empty());
+        assert!(backend.runner.texts.lock().unwrap().is_empty());
+        assert_eq!(backend.buffers.get(&uri).unwrap().version, 1);
+    }
+
+    #[tokio::test]
+    async fn code_action_omits_stale_fix_without_disabled_support() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let mut doc = document("ふたつの項目がある。", vec![futatsu_message()]);
+        doc.version = Some(1);
+        backend.state.insert(uri.clone(), doc);
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 2,
+                text: "みっつの項目がある。".to_string(),
+            },
+        );
+
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let actions = backend
+            .code_action(code_action_params(uri, range))
+            .await
+            .unwrap()
+            .unwrap();
+        // 古い fix も、内容の変わった行を無効にする action も出さない
+        let titles: Vec<String> = actions
+            .into_iter()
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(a) => a.title,
+                CodeActionOrCommand::Command(c) => c.title,
+            })
+            .collect();
+        assert_eq!(titles, ["Copy rule ID: prh"]);
+    }
+
+    /// fix-all action の TextEdit を返す。
+    async fn fix_all_edits(messages: Vec<TextlintMessage>) -> Vec<TextEdit> {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend
+            .state
+            .insert(uri.clone(), document("ふたつの項目がある。", messages));
+        let mut params = code_action_params(
+            uri.clone(),
+            Range {
+                start: Position::new(0, 0),
+                end: Position::new(0, 10),
+            },
+        );
+        params.context.only = Some(vec![CodeActionKind::SOURCE_FIX_ALL]);
+
+        let actions = backend.code_action(params).await.unwrap().unwrap();
+        let fix_all = actions
+            .into_iter()
+            .find_map(|action| match action {
+                CodeActionOrCommand::CodeAction(a)
+                    if a.kind == Some(CodeActionKind::SOURCE_FIX_ALL) =>
+                {
+                    Some(a)
+                }
+                _ => None,
+            })
+            .expect("fix-all action");
+        assert_eq!(fix_all.title, FIX_ALL_TITLE);
+        fix_all.edit.unwrap().changes.unwrap().remove(&uri).unwrap()
+    }
+
+    #[tokio::test]
+    async fn fix_all_action_includes_every_non_overlapping_fix() {
+        let later = TextlintMessage {
+            rule_id: "ja-no-redundant-expression".to_string(),
+            fix: Some(FixCommand {
+                range: [7, 9],
+                text: "あり".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        let edits = fix_all_edits(vec![later, futatsu_message()]).await;
+
+        let ranges: Vec<Range> = edits.iter().map(|edit| edit.range).collect();
+        assert_eq!(
+            ranges,
+            [
+                Range::new(Position::new(0, 0), Position::new(0, 3)),
+                Range::new(Position::new(0, 7), Position::new(0, 9)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fix_all_action_drops_overlapping_fix() {
+        let overlapping = TextlintMessage {
+            rule_id: "ja-no-redundant-expression".to_string(),
+            fix: Some(FixCommand {
+                range: [2, 5],
+                text: "x".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        let edits = fix_all_edits(vec![overlapping, futatsu_message()]).await;
+
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].new_text, "2つ");
+    }
+
+    #[tokio::test]
+    async fn fix_all_action_logs_same_range_conflicts() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let conflicting = TextlintMessage {
+            rule_id: "ja-no-redundant-expression".to_string(),
+            fix: Some(FixCommand {
+                range: [0, 3],
+                text: "二つ".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message(), conflicting]),
+        );
+        let mut params = code_action_params(
+            uri.clone(),
+            Range::new(Position::new(0, 0), Position::new(0, 10)),
+        );
+        params.context.only = Some(vec![CodeActionKind::SOURCE_FIX_ALL]);
+
+        let (actions, notification) = tokio::join!(backend.code_action(params), socket.next());
+
+        assert_eq!(actions.unwrap().unwrap().len(), 1);
+        let notification = notification.unwrap();
+        assert_eq!(notification.method(), "window/logMessage");
+        let log: LogMessageParams =
+            serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+        assert_eq!(
+            log.message,
+            "conflicting fix for ja-no-redundant-expression at [0, 3]: kept fix from prh"
+        );
+    }
+
+    #[tokio::test]
+    async fn code_action_accepts_inverted_range() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let second_line = TextlintMessage {
+            line: 2,
+            fix: Some(FixCommand {
+                range: [5, 8],
+                text: "2つ".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document("項目がある\nふたつの項目", vec![second_line]),
+        );
+
+        let inverted = Range {
+            start: Position::new(2, 0),
+            end: Position::new(1, 0),
+        };
+        let mut params = code_action_params(uri, inverted);
+        params.context.only = Some(vec![CodeActionKind::QUICKFIX]);
+        let actions = backend.code_action(params).await.unwrap().unwrap();
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected CodeAction");
+        };
+        assert_eq!(action.kind, Some(CodeActionKind::QUICKFIX));
+        assert!(action.edit.is_some());
+    }
+
+    #[tokio::test]
+    async fn code_action_skips_message_beyond_document_lines() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let beyond = TextlintMessage {
+            line: 3,
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。\n\nふたつ", vec![beyond]),
+        );
+        // 編集で 1 行になった
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 2,
+                text: "ふたつの項目がある。".to_string(),
+            },
+        );
+
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(5, 0),
+        };
+        let result = backend
+            .code_action(code_action_params(uri.clone(), range))
+            .await
+            .unwrap();
+        assert!(result.is_none());
+
+        // 診断の範囲は本文内に収まる
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "ふたつ",
+                vec![TextlintMessage {
+                    line: 9,
+                    fix: None,
+                    ..futatsu_message()
+                }],
+            ),
+        );
+        let diagnostics = backend.document_diagnostics(&uri);
+        assert_eq!(diagnostics[0].range.start, Position::new(0, 3));
+    }
+
+    #[tokio::test]
+    async fn message_wrap_width_applies_to_hover_and_title_but_not_diagnostic() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = InitializeParams {
+            initialization_options: Some(serde_json::json!({ "messageWrapWidth": 8 })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        let long_message = "ふたつ => 2つ, 二つ, 2個, ２つ のいずれかに統一";
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "ふたつの項目がある。",
+                vec![TextlintMessage {
+                    message: long_message.to_string(),
+                    ..futatsu_message()
+                }],
+            ),
+        );
+
+        let hover = backend
+            .hover(HoverParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri.clone()),
+                    Position::new(0, 1),
+                ),
+                work_done_progress_params: Default::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let HoverContents::Markup(markup) = hover.contents else {
+            panic!("expected markup");
+        };
+        assert_eq!(
+            markup.value,
+            "**prh**  \nふたつ => 2  \nつ, 二つ, 2  \n個, ２つ のい  \nずれかに統一"
+        );
+
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let actions = backend
+            .code_action(code_action_params(uri.clone(), range))
+            .await
+            .unwrap()
+            .unwrap();
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected CodeAction");
+        };
+        assert_eq!(action.title, "Fix: ふたつ => … (prh)");
+
+        let diagnostics = backend.document_diagnostics(&uri);
+        assert_eq!(diagnostics[0].message, long_message);
+    }
+
+    #[tokio::test]
+    async fn category_severity_map_overrides_rule_severity() {
+        let (service, _) = initialized(
+            MockRunner::new(vec![]),
+            serde_json::json!({
+                "categorySeverityMap": { "terminology": "hint", "typo": "error" }
+            }),
+        )
+        .await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let typo = TextlintMessage {
+            rule_id: "preset-ja-technical-writing/ja-unnatural-alphabet".to_string(),
+            severity: 1,
+            ..futatsu_message()
+        };
+        let unknown = TextlintMessage {
+            rule_id: "my-rule".to_string(),
+            severity: 1,
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "ふたつの項目がある。",
+                vec![futatsu_message(), typo, unknown],
+            ),
+        );
+
+        let severities: Vec<_> = backend
+            .document_diagnostics(&uri)
+            .into_iter()
+            .map(|d| d.severity.unwrap())
+            .collect();
+        assert_eq!(
+            severities,
+            [
+                DiagnosticSeverity::HINT,
+                DiagnosticSeverity::ERROR,
+                DiagnosticSeverity::WARNING,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn diagnostic_spans_fix_range() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let msg = TextlintMessage {
+            rule_id: "no-doubled-joshi".to_string(),
+            line: 1,
+            column: 7,
+            fix: Some(FixCommand {
+                range: [6, 9],
+                text: "but".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        let diagnostic = backend.diagnostic("first, and then", &msg);
+        assert_eq!(diagnostic.range.start, Position::new(0, 6));
+        assert_eq!(
+            diagnostic.range.end.character,
+            diagnostic.range.start.character + 3
+        );
+    }
+
+    #[tokio::test]
+    async fn diagnostic_data_round_trips() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let unfixable = TextlintMessage {
+            rule_id: "my-rules/odd-rule".to_string(),
+            fix: None,
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message(), unfixable]),
+        );
+
+        let diagnostics = backend.document_diagnostics(&uri);
+        let data: Vec<DiagnosticData> = diagnostics
+            .iter()
+            .map(|d| serde_json::from_value(d.data.clone().unwrap()).unwrap())
+            .collect();
+        assert_eq!(
+            data,
+            [
+                DiagnosticData {
+                    rule_id: "prh".to_string(),
+                    fixable: true,
+                    category: Some("terminology".to_string()),
+                    doc_url: Some(
+                        Url::parse("https://www.npmjs.com/package/textlint-rule-prh").unwrap()
+                    ),
+                },
+                DiagnosticData {
+                    rule_id: "my-rules/odd-rule".to_string(),
+                    fixable: false,
+                    category: None,
+                    doc_url: Some(
+                        Url::parse("https://www.npmjs.com/package/textlint-rule-odd-rule").unwrap()
+                    ),
+                },
+            ]
+        );
+        assert_eq!(diagnostics[0].data.as_ref().unwrap()["ruleId"], "prh");
+        assert!(diagnostics[1]
+            .data
+            .as_ref()
+            .unwrap()
+            .get("category")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn code_action_returns_none_when_only_excludes_our_kinds() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message()]),
+        );
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+
+        let mut params = code_action_params(uri.clone(), range);
+        params.context.only = Some(vec![CodeActionKind::REFACTOR]);
+        assert!(backend.code_action(params).await.unwrap().is_none());
+
+        let mut params = code_action_params(uri, range);
+        params.context.only = Some(vec![CodeActionKind::QUICKFIX]);
+        assert!(backend.code_action(params).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn code_action_converts_fix_range_in_negotiated_encoding() {
+        let text = "𠮷野家\nふたつの項目がある。";
+        // 2 行目の「ふたつ」(UTF-16 オフセット 5..8)
+        let msg = TextlintMessage {
+            line: 2,
+            fix: Some(FixCommand {
+                range: [5, 8],
+                text: "2つ".to_string(),
+            }),
+            ..futatsu_message()
+        };
+
+        for (kind, end) in [
+            (PositionEncodingKind::UTF8, 9),
+            (PositionEncodingKind::UTF16, 3),
+            (PositionEncodingKind::UTF32, 3),
+        ] {
+            let runner = MockRunner::new(vec![]);
+            let (service, _) = LspService::new(|client| Backend::new(client, runner));
+            let backend = service.inner();
+            backend
+                .initialize(initialize_params_with_encodings(vec![kind.clone()]))
+                .await
+                .unwrap();
+
+            let uri = Url::from_file_path("/tmp/test.md").unwrap();
+            backend
+                .state
+                .insert(uri.clone(), document(text, vec![msg.clone()]));
+            let range = Range::new(Position::new(1, 0), Position::new(1, 0));
+            let actions = backend
+                .code_action(code_action_params(uri.clone(), range))
+                .await
+                .unwrap()
+                .unwrap();
+            let CodeActionOrCommand::CodeAction(fix) = &actions[0] else {
+                panic!("expected CodeAction");
+            };
+            let edits = &fix.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+            assert_eq!(
+                edits[0].range,
+                Range::new(Position::new(1, 0), Position::new(1, end)),
+                "{kind:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn code_action_returns_only_requested_kinds() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message()]),
+        );
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let kinds_for = |only: Vec<CodeActionKind>| {
+            let mut params = code_action_params(uri.clone(), range);
+            params.context.only = Some(only);
+            async {
+                backend
+                    .code_action(params)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .into_iter()
+                    .map(|action| match action {
+                        CodeActionOrCommand::CodeAction(a) => (a.kind.unwrap(), a.title),
+                        CodeActionOrCommand::Command(c) => panic!("unexpected command {c:?}"),
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            kinds_for(vec![CodeActionKind::QUICKFIX]).await,
+            [
+                (
+                    CodeActionKind::QUICKFIX,
+                    "Fix: ふたつ => 2つ (prh)".to_string()
+                ),
+                (
+                    CodeActionKind::QUICKFIX,
+                    "Disable prh for this line".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            kinds_for(vec![CodeActionKind::EMPTY]).await,
+            [(CodeActionKind::EMPTY, "Copy rule ID: prh".to_string())]
+        );
+        assert_eq!(
+            kinds_for(vec![CodeActionKind::SOURCE_FIX_ALL]).await,
+            [(CodeActionKind::SOURCE_FIX_ALL, FIX_ALL_TITLE.to_string())]
+        );
+        // 上位の kind を要求すれば下位の kind も返す
+        assert_eq!(
+            kinds_for(vec![CodeActionKind::SOURCE]).await,
+            [(CodeActionKind::SOURCE_FIX_ALL, FIX_ALL_TITLE.to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn code_action_offers_copy_rule_id_for_each_message_under_cursor() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let on_line = |rule_id: &str, line| TextlintMessage {
+            rule_id: rule_id.to_string(),
+            line,
+            fix: None,
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "ふたつの項目がある。\n二行目",
+                vec![
+                    on_line("prh", 1),
+                    on_line("max-ten", 1),
+                    on_line("prh", 1),
+                    on_line("no-todo", 2),
+                ],
+            ),
+        );
+
+        let range = Range::new(Position::new(0, 0), Position::new(0, 0));
+        let actions = backend
+            .code_action(code_action_params(uri, range))
+            .await
+            .unwrap()
+            .unwrap();
+        let commands: Vec<Command> = actions
+            .into_iter()
+            .filter_map(|action| match action {
+                CodeActionOrCommand::CodeAction(a) => a.command,
+                CodeActionOrCommand::Command(_) => None,
+            })
+            .collect();
+        // 同じルールは 1 つにまとめ、カーソルのない行の問題には出さない
+        let titles: Vec<&str> = commands.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, ["Copy rule ID: max-ten", "Copy rule ID: prh"]);
+        assert!(commands.iter().all(|c| c.command == COPY_RULE_ID_COMMAND));
+
+        let params = ExecuteCommandParams {
+            command: COPY_RULE_ID_COMMAND.to_string(),
+            arguments: commands[1].arguments.clone().unwrap(),
+            work_done_progress_params: Default::default(),
+        };
+        let (result, message) = tokio::join!(backend.execute_command(params), socket.next());
+        assert_eq!(result.unwrap(), Some(Value::String("prh".to_string())));
+        let message = message.unwrap();
+        assert_eq!(message.method(), "window/showMessage");
+        let shown: ShowMessageParams =
+            serde_json::from_value(message.params().unwrap().clone()).unwrap();
+        assert_eq!(shown.message, "textlint rule ID: prh");
+    }
+
+    #[tokio::test]
+    async fn code_action_puts_requested_diagnostics_first() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let warning = TextlintMessage {
+            rule_id: "ja-no-redundant-expression".to_string(),
+            severity: 1,
+            column: 5,
+            fix: Some(FixCommand {
+                range: [4, 6],
+                text: "項".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "ふたつの項目がある。",
+                vec![futatsu_message(), warning.clone()],
+            ),
+        );
+
+        let mut params =
+            code_action_params(uri, Range::new(Position::new(0, 0), Position::new(0, 10)));
+        params.context.only = Some(vec![CodeActionKind::QUICKFIX]);
+        params.context.diagnostics = vec![backend.diagnostic("ふたつの項目がある。", &warning)];
+        let actions = backend.code_action(params).await.unwrap().unwrap();
+        let CodeActionOrCommand::CodeAction(first) = &actions[0] else {
+            panic!("expected CodeAction");
+        };
+        // エラーより先に、クライアントが渡した警告の fix を出す
+        assert!(first.title.contains("ja-no-redundant-expression"));
+    }
+
+    #[tokio::test]
+    async fn diagnostic_severity_maps_each_textlint_severity() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let severities: Vec<Option<DiagnosticSeverity>> = [0, 1, 2, 3, 4]
+            .map(|severity| {
+                let msg = TextlintMessage {
+                    severity,
+                    ..futatsu_message()
+                };
+                backend.diagnostic("ふたつの項目がある。", &msg).severity
+            })
+            .to_vec();
+        assert_eq!(
+            severities,
+            [
+                Some(DiagnosticSeverity::HINT),
+                Some(DiagnosticSeverity::WARNING),
+                Some(DiagnosticSeverity::ERROR),
+                Some(DiagnosticSeverity::INFORMATION),
+                Some(DiagnosticSeverity::ERROR),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rule_severity_overrides_take_precedence() {
+        let (service, _socket) = initialized(
+            MockRunner::new(vec![]),
+            serde_json::json!({
+                "ruleSeverityOverrides": { "prh": "warning", "no-todo": "info" },
+                "categorySeverityMap": { "typo": "hint" },
+            }),
+        )
+        .await;
+        let backend = service.inner();
+
+        let severity = |rule_id: &str| {
+            let msg = TextlintMessage {
+                rule_id: rule_id.to_string(),
+                ..futatsu_message()
+            };
+            backend.diagnostic("ふたつの項目がある。", &msg).severity
+        };
+        assert_eq!(severity("prh"), Some(DiagnosticSeverity::WARNING));
+        assert_eq!(
+            severity("preset/no-todo"),
+            Some(DiagnosticSeverity::INFORMATION)
+        );
+        // 上書きしていないルールは textlint の severity (2 = error) のまま
+        assert_eq!(
+            severity("no-doubled-joshi"),
+            Some(DiagnosticSeverity::ERROR)
+        );
+    }
+
+    #[tokio::test]
+    async fn diagnostic_tags_follow_rule_tags() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let tags = |rule_id: &str| {
+            let msg = TextlintMessage {
+                rule_id: rule_id.to_string(),
+                ..futatsu_message()
+            };
+            backend.diagnostic("ふたつの項目がある。", &msg).tags
+        };
+        assert_eq!(
+            tags("ja-no-redundant-expression"),
+            Some(vec![DiagnosticTag::UNNECESSARY])
+        );
+        assert_eq!(
+            tags("ja-technical-writing/ja-no-redundant-expression"),
+            Some(vec![DiagnosticTag::UNNECESSARY])
+        );
+        assert_eq!(
+            tags("no-hankaku-kana"),
+            Some(vec![DiagnosticTag::DEPRECATED])
+        );
+        assert_eq!(tags("prh"), None);
+    }
+
+    #[tokio::test]
+    async fn diagnostic_links_rule_documentation() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let href = |rule_id: &str| {
+            let msg = TextlintMessage {
+                rule_id: rule_id.to_string(),
+                ..futatsu_message()
+            };
+            backend
+                .diagnostic("ふたつの項目がある。", &msg)
+                .code_description
+                .map(|description| description.href.to_string())
+        };
+        assert_eq!(
+            href("no-doubled-joshi").as_deref(),
+            Some("https://www.npmjs.com/package/textlint-rule-no-doubled-joshi")
+        );
+        assert_eq!(
+            href("ja-technical-writing/no-exclamation-question-mark").as_deref(),
+            Some("https://www.npmjs.com/package/textlint-rule-no-exclamation-question-mark")
+        );
+        assert_eq!(
+            href("@textlint-ja/no-synonyms").as_deref(),
+            Some("https://www.npmjs.com/package/@textlint-ja/textlint-rule-no-synonyms")
+        );
+        assert_eq!(
+            href("no-unmatched-pair").as_deref(),
+            Some("https://www.npmjs.com/package/@textlint-rule/textlint-rule-no-unmatched-pair")
+        );
+        for odd in ["", "a b", "ルール", "x?y#z", "@/", "preset/", "@scope/a%2F"] {
+            assert_eq!(href(odd), None, "{odd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn diagnostic_points_at_rule_key_in_json_config() {
+        let root = std::env::temp_dir().join("ichigyo-ls-rule-config-test");
+        std::fs::create_dir_all(&root).unwrap();
+        let config_path = root.join(".textlintrc.json");
+        std::fs::write(
+            &config_path,
+            "{\n  \"rules\": {\n    \"no-doubled-joshi\": true,\n    \"prh\": { \"rulePaths\": [\"dict.yml\"] }\n  }\n}\n",
+        )
+        .unwrap();
+
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+        let params = InitializeParams {
+            root_uri: Some(Url::from_file_path(&root).unwrap()),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        let uri = Url::from_file_path(root.join("doc.md")).unwrap();
+        let prh = TextlintMessage {
+            fix: None,
+            ..futatsu_message()
+        };
+        let unknown = TextlintMessage {
+            rule_id: "no-todo".to_string(),
+            ..prh.clone()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![prh, unknown]),
+        );
+
+        // 読み込む前はディスクを読まず、関連情報を付けない
+        assert_eq!(
+            backend.document_diagnostics(&uri)[0].related_information,
+            None
+        );
+        backend.load_rule_config(&uri).await;
+        let diagnostics = backend.document_diagnostics(&uri);
+        let related = diagnostics[0].related_information.as_ref().unwrap();
+        assert_eq!(related.len(), 1);
+        assert_eq!(
+            related[0].location.uri,
+            Url::from_file_path(&config_path).unwrap()
+        );
+        assert_eq!(related[0].location.range.start, Position::new(3, 4));
+        assert_eq!(related[0].location.range.end, Position::new(3, 9));
+        // 設定ファイルにないルールには付けない
+        assert_eq!(diagnostics[1].related_information, None);
+    }
+
+    #[tokio::test]
+    async fn diagnostic_points_at_fix_target() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let unfixable = TextlintMessage {
+            rule_id: "no-todo".to_string(),
+            fix: None,
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message(), unfixable]),
+        );
+
+        let diagnostics = backend.document_diagnostics(&uri);
+        assert_eq!(
+            diagnostics[0].related_information,
+            Some(vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: Range::new(Position::new(0, 0), Position::new(0, 3)),
+                },
+                message: "will be replaced with '2つ'".to_string(),
+            }])
+        );
+        assert_eq!(diagnostics[1].related_information, None);
+    }
+
+    #[test]
+    fn truncate_message_keeps_short_message() {
+        assert_eq!(truncate_message("短い", 10), "短い");
+        assert_eq!(truncate_message("あいうえお", 5), "あいうえお");
+        assert_eq!(truncate_message("あいうえおか", 5), "あいうえ…");
+    }
+
+    #[tokio::test]
+    async fn work_dir_prefers_root_then_parent() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        // ルートがなければファイルの親ディレクトリ。親のないパスでは決められない
+        assert_eq!(
+            backend.resolve_work_dir(Path::new("/docs/ja/a.md")),
+            Some(PathBuf::from("/docs/ja"))
+        );
+        assert_eq!(backend.resolve_work_dir(Path::new("/")), None);
+
+        let root = std::env::temp_dir().join("ichigyo-ls-work-dir-test");
+        std::fs::create_dir_all(&root).unwrap();
+        let params = InitializeParams {
+            root_uri: Some(Url::from_file_path(&root).unwrap()),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+        assert_eq!(
+            backend.resolve_work_dir(Path::new("/docs/ja/a.md")),
+            Some(root.clone())
+        );
+        assert_eq!(backend.resolve_work_dir(Path::new("/")), Some(root));
+    }
+
+    #[tokio::test]
+    async fn initialize_resolves_modules_dir_against_root() {
+        let root = std::env::temp_dir().join("ichigyo-ls-modules-dir-test");
+        std::fs::create_dir_all(root.join("tools/node_modules")).unwrap();
+
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = InitializeParams {
+            root_uri: Some(Url::from_file_path(&root).unwrap()),
+            initialization_options: Some(serde_json::json!({
+                "modulesDir": "tools/node_modules"
+            })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        let options = backend.run_options();
+        assert_eq!(
+            options.modules_dir.as_deref(),
+            Some(root.join("tools/node_modules").as_path())
+        );
+    }
+
+    /// `root` をワークスペースルートとして initialize し、RunOptions の textlint を返す。
+    async fn textlint_binary_for(root: &Path, options: Value) -> Option<PathBuf> {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = InitializeParams {
+            root_uri: Some(Url::from_file_path(root).unwrap()),
+            initialization_options: Some(options),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+        backend.run_options().binary
+    }
+
+    #[tokio::test]
+    async fn rules_dir_resolves_against_root() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let root = std::env::temp_dir();
+        let params = InitializeParams {
+            root_uri: Some(Url::from_file_path(&root).unwrap()),
+            initialization_options: Some(serde_json::json!({
+                "rulesDir": "textlint-rules",
+                "preset": "ja-technical-writing",
+            })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        let options = backend.run_options();
+        assert_eq!(options.rules_dir, Some(root.join("textlint-rules")));
+        assert_eq!(options.preset.as_deref(), Some("ja-technical-writing"));
+        assert_eq!(RunOptions::default().rules_dir, None);
+    }
+
+    #[tokio::test]
+    async fn textlint_path_overrides_binary() {
+        let root = std::env::temp_dir().join("ichigyo-ls-textlint-path-test");
+        std::fs::create_dir_all(root.join("node_modules/.bin")).unwrap();
+        std::fs::write(root.join("node_modules/.bin/textlint"), "").unwrap();
+
+        let binary = textlint_binary_for(
+            &root,
+            serde_json::json!({ "textlintPath": "tools/bin/textlint" }),
+        )
+        .await;
+        assert_eq!(binary, Some(root.join("tools/bin/textlint")));
+    }
+
+    #[tokio::test]
+    async fn textlint_binary_is_detected_in_node_modules() {
+        let root = std::env::temp_dir().join("ichigyo-ls-local-textlint-test");
+        std::fs::create_dir_all(root.join("node_modules/.bin")).unwrap();
+        std::fs::write(root.join("node_modules/.bin/textlint"), "").unwrap();
+
+        let binary = textlint_binary_for(&root, serde_json::json!({})).await;
+        assert_eq!(binary, Some(root.join("node_modules/.bin/textlint")));
+    }
+
+    #[tokio::test]
+    async fn textlint_binary_defaults_to_path_lookup() {
+        let root = std::env::temp_dir().join("ichigyo-ls-global-textlint-test");
+        std::fs::create_dir_all(&root).unwrap();
+
+        assert_eq!(
+            textlint_binary_for(&root, serde_json::json!({})).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn initialize_ignores_missing_modules_dir() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = InitializeParams {
+            initialization_options: Some(serde_json::json!({
+                "modulesDir": "/nonexistent/ichigyo-ls/node_modules"
+            })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        assert!(backend.run_options().modules_dir.is_none());
+    }
+
+    #[tokio::test]
+    async fn diagnostics_use_preset_source_for_mapped_rule() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = InitializeParams {
+            initialization_options: Some(serde_json::json!({
+                "rulePresetMap": { "no-doubled-joshi": "ja-technical-writing" }
+            })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        let messages = [
+            TextlintMessage {
+                rule_id: "no-doubled-joshi".to_string(),
+                message: "助詞の重複".to_string(),
+                line: 1,
+                column: 1,
+                index: None,
+                loc: None,
+                severity: 2,
+                fix: None,
+            },
+            TextlintMessage {
+                rule_id: "prh".to_string(),
+                message: "ふたつ => 2つ".to_string(),
+                line: 1,
+                column: 1,
+                index: None,
+                loc: None,
+                severity: 2,
+                fix: None,
+            },
+        ];
+        let diagnostics: Vec<Diagnostic> = messages
+            .iter()
+            .map(|msg| backend.diagnostic("text", msg))
+            .collect();
+
+        assert_eq!(
+            diagnostics[0].source.as_deref(),
+            Some("textlint:ja-technical-writing")
+        );
+        assert_eq!(diagnostics[1].source.as_deref(), Some("textlint"));
+    }
+
+    fn document(text: &str, messages: Vec<TextlintMessage>) -> DocumentState {
+        DocumentState {
+            text: text.to_string(),
+            messages,
+            language_id: "markdown".to_string(),
+            version: None,
+            published: None,
+            shifted: None,
+            linted_at: tokio::time::Instant::now(),
+        }
+    }
+
+    fn futatsu_message() -> TextlintMessage {
+        TextlintMessage {
+            rule_id: "prh".to_string(),
+            message: "ふたつ => 2つ".to_string(),
+            line: 1,
+            column: 1,
+            index: None,
+            loc: None,
+            severity: 2,
+            fix: Some(FixCommand {
+                range: [0, 3],
+                text: "2つ".to_string(),
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn apply_fix_at_command_issues_apply_edit() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message()]),
+        );
+
+        let params = ExecuteCommandParams {
+            command: APPLY_FIX_AT_COMMAND.to_string(),
+            arguments: vec![
+                serde_json::json!(uri),
+                serde_json::json!({ "line": 0, "character": 1 }),
+                serde_json::json!("prh"),
+            ],
+            work_done_progress_params: Default::default(),
+        };
+
+        let client = async {
+            let request = socket.next().await.unwrap();
+            assert_eq!(request.method(), "workspace/applyEdit");
+            let params: ApplyWorkspaceEditParams =
+                serde_json::from_value(request.params().unwrap().clone()).unwrap();
+            let response = Response::from_ok(
+                request.id().unwrap().clone(),
+                serde_json::json!({ "applied": true }),
+            );
+            socket.send(response).await.unwrap();
+            params.edit
+        };
+        let (result, edit) = tokio::join!(backend.execute_command(params), client);
+
+        assert_eq!(result.unwrap(), Some(Value::Bool(true)));
+        let edits = &edit.changes.unwrap()[&uri];
+        assert_eq!(
+            edits,
+            &vec![TextEdit {
+                range: Range {
+                    start: Position::new(0, 0),
+                    end: Position::new(0, 3),
+                },
+                new_text: "2つ".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_fix_at_command_returns_false_without_matching_rule() {
+        let (service, _socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message()]),
+        );
+
+        let params = ExecuteCommandParams {
+            command: APPLY_FIX_AT_COMMAND.to_string(),
+            arguments: vec![
+                serde_json::json!(uri),
+                serde_json::json!({ "line": 0, "character": 1 }),
+                serde_json::json!("no-doubled-joshi"),
+            ],
+            work_done_progress_params: Default::default(),
+        };
+
+        let result = backend.execute_command(params).await.unwrap();
+        assert_eq!(result, Some(Value::Bool(false)));
+    }
+
+    #[tokio::test]
+    async fn apply_fix_at_command_skips_stale_fix() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let mut doc = document("ふたつの項目がある。", vec![futatsu_message()]);
+        doc.version = Some(1);
+        backend.state.insert(uri.clone(), doc);
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 2,
+                text: "みっつの項目がある。".to_string(),
+            },
+        );
+
+        let params = ExecuteCommandParams {
+            command: APPLY_FIX_AT_COMMAND.to_string(),
+            arguments: vec![
+                serde_json::json!(uri),
+                serde_json::json!({ "line": 0, "character": 1 }),
+                serde_json::json!("prh"),
+            ],
+            work_done_progress_params: Default::default(),
+        };
+        let (result, message) = tokio::join!(backend.execute_command(params), socket.next());
+
+        assert_eq!(result.unwrap(), Some(Value::Bool(false)));
+        let message = message.unwrap();
+        assert_eq!(message.method(), "window/showMessage");
+        assert_eq!(
+            message.params().unwrap()["message"].as_str(),
+            Some(STALE_FIX_REASON)
+        );
+    }
+
+    fn code_action_params(uri: Url, range: Range) -> CodeActionParams {
+        CodeActionParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            range,
+            context: CodeActionContext::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn code_action_skips_reversed_and_oversized_fix_ranges() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let broken = |range: [usize; 2]| TextlintMessage {
+            index: None,
+            loc: None,
+            fix: Some(FixCommand {
+                range,
+                text: "2つ".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "ふたつの項目がある。",
+                vec![
+                    broken([3, 0]),
+                    broken([0, usize::MAX]),
+                    broken([usize::MAX - 1, usize::MAX]),
+                ],
+            ),
+        );
+
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        // fix は出さず、行を無効にする action だけが残る
+        let actions = backend
+            .code_action(code_action_params(uri, range))
+            .await
+            .unwrap()
+            .unwrap();
+        let titles: Vec<String> = actions
+            .into_iter()
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(a) => a.title,
+                CodeActionOrCommand::Command(c) => c.title,
+            })
+            .collect();
+        assert_eq!(titles, ["Disable prh for this line", "Copy rule ID: prh"]);
+    }
+
+    #[test]
+    fn kind_matches_hierarchically() {
+        let fix_all = CodeActionKind::SOURCE_FIX_ALL;
+        assert!(kind_matches(&fix_all, &fix_all));
+        assert!(kind_matches(
+            &fix_all,
+            &CodeActionKind::from("source.fixAll.ruleId")
+        ));
+        assert!(kind_matches(&CodeActionKind::SOURCE, &fix_all));
+        assert!(!kind_matches(&fix_all, &CodeActionKind::SOURCE));
+        assert!(!kind_matches(
+            &CodeActionKind::QUICKFIX,
+            &CodeActionKind::from("quickfixes")
+        ));
+    }
+
+    #[tokio::test]
+    async fn code_action_kinds_setting_filters_actions() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = InitializeParams {
+            initialization_options: Some(serde_json::json!({
+                "codeActionKinds": ["source.fixAll"]
+            })),
+            ..Default::default()
+        };
+        let result = backend.initialize(params).await.unwrap();
+        let Some(CodeActionProviderCapability::Options(options)) =
+            result.capabilities.code_action_provider
+        else {
+            panic!("expected code action options");
+        };
+        assert_eq!(
+            options.code_action_kinds,
+            Some(vec![CodeActionKind::SOURCE_FIX_ALL])
+        );
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message()]),
+        );
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let actions = backend
+            .code_action(code_action_params(uri, range))
+            .await
+            .unwrap()
+            .unwrap();
+        let kinds: Vec<Option<CodeActionKind>> = actions
+            .into_iter()
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(a) => a.kind,
+                CodeActionOrCommand::Command(_) => None,
+            })
+            .collect();
+        assert_eq!(kinds, [Some(CodeActionKind::SOURCE_FIX_ALL)]);
+    }
+
+    #[tokio::test]
+    async fn code_action_kinds_quickfix_only_offers_quickfix() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = InitializeParams {
+            initialization_options: Some(serde_json::json!({
+                "codeActionKinds": ["quickfix"]
+            })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message()]),
+        );
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let actions = backend
+            .code_action(code_action_params(uri, range))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(actions.iter().all(|action| matches!(
+            action,
+            CodeActionOrCommand::CodeAction(a) if a.kind == Some(CodeActionKind::QUICKFIX)
+        )));
+    }
+
+    #[tokio::test]
+    async fn initialized_warms_up_textlint_in_root() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = InitializeParams {
+            root_uri: Some(Url::from_file_path(std::env::temp_dir()).unwrap()),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+        backend.initialized(InitializedParams {}).await;
+
+        tokio::time::timeout(Duration::from_secs(1), backend.runner.warmed_up.notified())
+            .await
+            .expect("warmup should run after initialized");
+    }
+
+    #[tokio::test]
+    async fn initialize_ignores_root_uri_that_is_not_a_directory() {
+        let file = std::env::temp_dir().join("ichigyo-ls-root-file-test.md");
+        std::fs::write(&file, "").unwrap();
+
+        for root in [
+            std::env::temp_dir().join("ichigyo-ls-no-such-root"),
+            file.clone(),
+        ] {
+            let runner = MockRunner::new(vec![]);
+            let (service, _) = LspService::new(|client| Backend::new(client, runner));
+            let backend = service.inner();
+
+            let params = InitializeParams {
+                root_uri: Some(Url::from_file_path(&root).unwrap()),
+                ..Default::default()
+            };
+            backend.initialize(params).await.unwrap();
+            assert!(backend.root_dir.get().is_none(), "root={root:?}");
+        }
+    }
+
+    #[test]
+    fn language_id_is_inferred_from_extension() {
+        assert_eq!(language_id_from_path(Path::new("a.md")), "markdown");
+        assert_eq!(language_id_from_path(Path::new("a.MARKDOWN")), "markdown");
+        assert_eq!(language_id_from_path(Path::new("a.txt")), "plaintext");
+        assert_eq!(
+            language_id_from_path(Path::new("a.rst")),
+            "restructuredtext"
+        );
+        assert_eq!(language_id_from_path(Path::new("README")), "plaintext");
+    }
+
+    #[tokio::test]
+    async fn did_save_without_open_creates_state_and_publishes() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-cold-save-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cold.md");
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: path.display().to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let (service, mut socket) = initialized(MockRunner::new(results), Value::Null).await;
+        let backend = service.inner();
+
+        backend
+            .did_save(DidSaveTextDocumentParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+                text: None,
+            })
+            .await;
+
+        let doc = backend.state.get(&uri).expect("state should be created");
+        assert_eq!(doc.language_id, "markdown");
+        assert_eq!(doc.messages.len(), 1);
+        drop(doc);
+
+        let notification = socket.next().await.unwrap();
+        assert_eq!(notification.method(), "textDocument/publishDiagnostics");
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+        assert_eq!(params.uri, uri);
+        assert_eq!(params.diagnostics.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn did_change_drops_diagnostics_inside_edited_range() {
+        let (service, mut socket) = initialized(
+            MockRunner::new(vec![]),
+            serde_json::json!({ "lintDebounceMs": 60_000 }),
+        )
+        .await;
+        let backend = service.inner();
+        let uri = Url::from_file_path("/tmp/edited.md").unwrap();
+        let text = "ふたつの項目がある。";
+        let later = TextlintMessage {
+            rule_id: "sample".to_string(),
+            message: "ある".to_string(),
+            line: 1,
+            column: 8,
+            index: None,
+            loc: None,
+            severity: 2,
+            fix: None,
+        };
+        let mut doc = document(text, vec![futatsu_message(), later]);
+        doc.version = Some(1);
+        backend.state.insert(uri.clone(), doc);
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 1,
+                text: text.to_string(),
+            },
+        );
+
+        backend
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 2),
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: Some(Range::new(Position::new(0, 0), Position::new(0, 3))),
+                    range_length: None,
+                    text: "2つ".to_string(),
+                }],
+            })
+            .await;
+
+        // 書き換えた範囲の問題は lint を待たずに消え、後ろの問題は編集に合わせて動く
+        let notification = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(notification.method(), "textDocument/publishDiagnostics");
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+        assert_eq!(params.version, Some(2));
+        let ranges: Vec<Range> = params.diagnostics.iter().map(|d| d.range).collect();
+        assert_eq!(
+            ranges,
+            [Range::new(Position::new(0, 6), Position::new(0, 7))]
+        );
+        assert!(backend.runner.texts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn did_focus_notification_relints_tracked_document() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-did-focus-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("focus.md");
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+        let other = Url::from_file_path(dir.join("untracked.md")).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: path.display().to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let (mut service, mut socket) = initialized(MockRunner::new(results), Value::Null).await;
+        service
+            .inner()
+            .state
+            .insert(uri.clone(), document("ふたつの項目がある。", vec![]));
+
+        for target in [&other, &uri] {
+            let notification = Request::build("ichigyo/didFocus")
+                .params(serde_json::json!({ "textDocument": { "uri": target } }))
+                .finish();
+            let response = service.ready().await.unwrap().call(notification).await;
+            assert!(response.unwrap().is_none());
+        }
+
+        assert_eq!(*service.inner().runner.files.lock().unwrap(), vec![path]);
+        let notification = socket.next().await.unwrap();
+        assert_eq!(notification.method(), "textDocument/publishDiagnostics");
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+        assert_eq!(params.uri, uri);
+        assert_eq!(params.diagnostics.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn active_document_only_clears_previous_document_on_focus() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-active-document-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("first.md");
+        let second = dir.join("second.md");
+        std::fs::write(&first, "ふたつの項目がある。").unwrap();
+        std::fs::write(&second, "ふたつの項目がある。").unwrap();
+        let first_uri = Url::from_file_path(&first).unwrap();
+        let second_uri = Url::from_file_path(&second).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: second.display().to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let (service, mut socket) = initialized(
+            MockRunner::new(results),
+            serde_json::json!({ "activeDocumentOnly": true }),
+        )
+        .await;
+        let backend = service.inner();
+
+        let open = |uri: &Url| DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                "markdown".to_string(),
+                1,
+                "ふたつの項目がある。".to_string(),
+            ),
+        };
+        backend.did_open(open(&first_uri)).await;
+        backend.did_open(open(&second_uri)).await;
+        assert_eq!(*backend.runner.files.lock().unwrap(), vec![first.clone()]);
+        socket.next().await.unwrap();
+
+        let focus = DidFocusParams {
+            text_document: TextDocumentIdentifier::new(second_uri.clone()),
+        };
+        let client = async {
+            let mut published = Vec::new();
+            for _ in 0..2 {
+                let notification = socket.next().await.unwrap();
+                let params: PublishDiagnosticsParams =
+                    serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+                published.push((params.uri, params.diagnostics.len()));
+            }
+            published
+        };
+        let ((), published) = tokio::join!(backend.did_focus(focus), client);
+
+        assert_eq!(*backend.runner.files.lock().unwrap(), vec![first, second]);
+        assert!(!backend.state.contains_key(&first_uri));
+        assert_eq!(published, [(first_uri, 0), (second_uri, 1)]);
+    }
+
+    #[tokio::test]
+    async fn unchanged_content_is_not_linted_again() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-lint-cache-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.md");
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: path.display().to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let (service, _) = LspService::new(|client| Backend::new(client, MockRunner::new(results)));
+        let backend = service.inner();
+
+        backend.lint_and_publish(&uri, None).await;
+        backend.lint_and_publish(&uri, None).await;
+        assert_eq!(backend.runner.files.lock().unwrap().len(), 1);
+        assert_eq!(backend.document_diagnostics(&uri).len(), 1);
+
+        // 内容が変われば実行し直す
+        std::fs::write(&path, "ふたつの項目がある。\n").unwrap();
+        backend.lint_and_publish(&uri, None).await;
+        assert_eq!(backend.runner.files.lock().unwrap().len(), 2);
+
+        // relint は内容が同じでも実行する
+        backend.relint_and_publish(&uri).await;
+        assert_eq!(backend.runner.files.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cached_result_expires_after_cache_ttl() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-cache-ttl-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.md");
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let (service, _) = LspService::new(|client| Backend::new(client, MockRunner::new(vec![])));
+        let backend = service.inner();
+        backend.config.store(Arc::new(Config {
+            cache_ttl_ms: Some(1_000),
+            ..Config::default()
+        }));
+
+        backend.lint_and_publish(&uri, None).await;
+        // 期限内なら内容が同じ lint は実行しない
+        tokio::time::advance(Duration::from_millis(999)).await;
+        backend.lint_and_publish(&uri, None).await;
+        assert_eq!(backend.runner.files.lock().unwrap().len(), 1);
+
+        // 期限を過ぎれば内容が同じでも実行し直す
+        tokio::time::advance(Duration::from_millis(1)).await;
+        backend.lint_and_publish(&uri, None).await;
+        assert_eq!(backend.runner.files.lock().unwrap().len(), 2);
+    }
+
+    /// `messages` を返すランナーでディスク上の `name` を lint し、publish される診断を返す。
+    async fn published_diagnostics(name: &str, messages: Vec<TextlintMessage>) -> Vec<Diagnostic> {
+        let dir = std::env::temp_dir().join("ichigyo-ls-dedup-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: path.display().to_string(),
+            messages,
+        }];
+        let (service, mut socket) = initialized(MockRunner::new(results), Value::Null).await;
+        let (_, notification) =
+            tokio::join!(service.inner().lint_and_publish(&uri, None), socket.next());
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(notification.unwrap().params().unwrap().clone()).unwrap();
+        params.diagnostics
+    }
+
+    #[tokio::test]
+    async fn results_for_other_files_are_published_to_their_uris() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-multi-file-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.md"), "ふたつの項目がある。").unwrap();
+        std::fs::write(dir.join("b.md"), "ふたつの項目がある。").unwrap();
+        let a = Url::from_file_path(dir.join("a.md")).unwrap();
+        let b = Url::from_file_path(dir.join("b.md")).unwrap();
+
+        let results = vec![
+            TextlintResult {
+                file_path: "a.md".to_string(),
+                messages: vec![futatsu_message()],
+            },
+            TextlintResult {
+                file_path: dir.join("b.md").display().to_string(),
+                messages: vec![
+                    futatsu_message(),
+                    TextlintMessage {
+                        column: 4,
+                        ..futatsu_message()
+                    },
+                ],
+            },
+        ];
+        let (service, mut socket) = initialized(MockRunner::new(results), Value::Null).await;
+        let backend = service.inner();
+
+        let client = async {
+            let mut published = Vec::new();
+            while let Ok(Some(notification)) =
+                tokio::time::timeout(Duration::from_millis(200), socket.next()).await
+            {
+                let params: PublishDiagnosticsParams =
+                    serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+                published.push((params.uri, params.diagnostics.len()));
+            }
+            published
+        };
+        let ((), mut published) = tokio::join!(backend.lint_and_publish(&a, None), client);
+
+        published.sort();
+        assert_eq!(published, [(a.clone(), 1), (b.clone(), 2)]);
+        assert_eq!(backend.state.get(&b).unwrap().messages.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn identical_messages_are_published_once() {
+        let mut duplicate = futatsu_message();
+        duplicate.fix = None;
+        let diagnostics =
+            published_diagnostics("duplicate.md", vec![futatsu_message(), duplicate]).await;
+        assert_eq!(diagnostics.len(), 1);
+        // 最初のメッセージの fix を残す
+        let data: DiagnosticData =
+            serde_json::from_value(diagnostics[0].data.clone().unwrap()).unwrap();
+        assert!(data.fixable);
+    }
+
+    #[tokio::test]
+    async fn messages_in_different_columns_are_kept() {
+        let other_column = TextlintMessage {
+            column: 2,
+            ..futatsu_message()
+        };
+        let diagnostics =
+            published_diagnostics("columns.md", vec![futatsu_message(), other_column]).await;
+        assert_eq!(diagnostics.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn newer_lint_supersedes_running_one() {
+        let runner = MockRunner::with_delay(vec![], Duration::from_millis(50));
+        let (service, mut socket) = initialized(runner, Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/ichigyo-ls-supersede/doc.md").unwrap();
+        let edit = |version, text: &str| {
+            backend.buffers.insert(
+                uri.clone(),
+                Buffer {
+                    version,
+                    text: text.to_string(),
+                },
+            );
+        };
+        edit(1, "ふたつの項目");
+
+        let lints = async {
+            tokio::join!(backend.lint_and_publish(&uri, None), async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                edit(2, "ふたつの項目がある。");
+                backend.lint_and_publish(&uri, None).await;
+            });
+        };
+        let client = async {
+            let mut published = Vec::new();
+            while let Ok(Some(notification)) =
+                tokio::time::timeout(Duration::from_millis(300), socket.next()).await
+            {
+                published.push(notification.method().to_string());
+            }
+            published
+        };
+        let ((), published) = tokio::join!(lints, client);
+
+        // 古い lint の結果は publish せず、新しい内容の結果だけを publish する
+        assert_eq!(published, ["textDocument/publishDiagnostics"]);
+        assert_eq!(backend.runner.texts.lock().unwrap().len(), 2);
+        let doc = backend.state.get(&uri).unwrap();
+        assert_eq!(doc.text, "ふたつの項目がある。");
+        assert_eq!(doc.version, Some(2));
+    }
+
+    #[tokio::test]
+    async fn lints_for_same_uri_do_not_overlap() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-lint-lock-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("first.md");
+        let second = dir.join("second.md");
+        std::fs::write(&first, "ふたつの項目がある。").unwrap();
+        std::fs::write(&second, "ふたつの項目がある。").unwrap();
+        let first = Url::from_file_path(&first).unwrap();
+        let second = Url::from_file_path(&second).unwrap();
+
+        let runner = MockRunner::with_delay(vec![], Duration::from_millis(50));
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        tokio::join!(
+            backend.relint_and_publish(&first),
+            backend.relint_and_publish(&first),
+        );
+        assert_eq!(backend.runner.max_running.load(Ordering::SeqCst), 1);
+
+        // 別のドキュメントは並行して lint できる。
+        tokio::join!(
+            backend.relint_and_publish(&first),
+            backend.relint_and_publish(&second),
+        );
+        assert_eq!(backend.runner.max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn missing_install_diagnostic_shown_until_successful_run() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-missing-install-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.md");
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: path.display().to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let runner = MockRunner::new(results);
+        *runner.failure.lock().unwrap() = Some(std::io::ErrorKind::NotFound);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        backend.lint_and_publish(&uri, None).await;
+        let diagnostics = backend.document_diagnostics(&uri);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, MISSING_INSTALL_MESSAGE);
+        assert_eq!(diagnostics[0].range.start.line, 0);
+        assert_eq!(
+            diagnostics[0].severity,
+            Some(DiagnosticSeverity::INFORMATION)
+        );
+
+        *backend.runner.failure.lock().unwrap() = None;
+        backend.lint_and_publish(&uri, None).await;
+        let diagnostics = backend.document_diagnostics(&uri);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "ふたつ => 2つ");
+    }
+
+    #[tokio::test]
+    async fn lint_failure_is_reported_once_per_interval() {
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let runner = MockRunner::new(vec![]);
+        *runner.failure.lock().unwrap() = Some(std::io::ErrorKind::NotFound);
+        let (service, mut socket) =
+            initialized(runner, serde_json::json!({ "notifyMissingInstall": false })).await;
+        let backend = service.inner();
+
+        let client = async {
+            let mut messages = Vec::new();
+            while let Ok(Some(message)) =
+                tokio::time::timeout(Duration::from_millis(200), socket.next()).await
+            {
+                messages.push(message);
+            }
+            messages
+        };
+        let lints = async {
+            backend.lint_and_publish(&uri, None).await;
+            backend.lint_and_publish(&uri, None).await;
+        };
+        let ((), messages) = tokio::join!(lints, client);
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].method(), "window/showMessage");
+        let params: ShowMessageParams =
+            serde_json::from_value(messages[0].params().unwrap().clone()).unwrap();
+        assert_eq!(params.typ, MessageType::ERROR);
+        assert!(params.message.starts_with("textlint not found"));
+    }
+
+    #[tokio::test]
+    async fn missing_install_diagnostic_can_be_disabled() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-missing-install-off-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.md");
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let runner = MockRunner::new(vec![]);
+        *runner.failure.lock().unwrap() = Some(std::io::ErrorKind::NotFound);
+        let (service, _) =
+            initialized(runner, serde_json::json!({ "notifyMissingInstall": false })).await;
+        let backend = service.inner();
+
+        backend.lint_and_publish(&uri, None).await;
+        assert!(backend.document_diagnostics(&uri).is_empty());
+    }
+
+    #[tokio::test]
+    async fn lint_routes_to_runner_configured_for_language() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-runners-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut uris = Vec::new();
+        for name in ["doc.tex", "doc.md", "doc.txt"] {
+            let path = dir.join(name);
+            std::fs::write(&path, "text").unwrap();
+            uris.push(Url::from_file_path(&path).unwrap());
+        }
+
+        let options = serde_json::json!({
+            "runners": {
+                "latex": ["latex-linter", "{file}"],
+                "markdown": ["md-linter", "--json", "{file}"],
+            }
+        });
+        let (service, _) = initialized(MockRunner::new(vec![]), options).await;
+        let backend = service.inner();
+
+        for uri in &uris {
+            backend.lint_and_publish(uri, None).await;
+        }
+
+        let commands = backend.runner.commands.lock().unwrap().clone();
+        let argv = |args: &[&str]| Some(args.iter().map(|a| a.to_string()).collect::<Vec<_>>());
+        assert_eq!(
+            commands,
+            vec![
+                argv(&["latex-linter", "{file}"]),
+                argv(&["md-linter", "--json", "{file}"]),
+                None,
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_within_startup_grace_period_are_deduplicated_and_throttled() {
+        let runner = MockRunner::with_delay(vec![], Duration::from_millis(20));
+        let (service, _) =
+            initialized(runner, serde_json::json!({ "startupGracePeriodMs": 100 })).await;
+        let backend = service.inner();
+        backend.initialized(InitializedParams {}).await;
+
+        let open = |name: String| {
+            backend.did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    Url::from_file_path(format!("/tmp/ichigyo-startup/{name}.md")).unwrap(),
+                    "markdown".to_string(),
+                    1,
+                    String::new(),
+                ),
+            })
+        };
+        // 10 ドキュメントをそれぞれ 2 回ずつ開く
+        let opens = (0..20).map(|i| open(format!("doc{}", i % 10)));
+        futures::future::join_all(opens).await;
+        assert!(backend.runner.commands.lock().unwrap().is_empty());
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(backend.runner.commands.lock().unwrap().len(), 10);
+        let max_running = backend.runner.max_running.load(Ordering::SeqCst);
+        assert!(max_running <= LINT_CONCURRENCY, "{max_running}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn startup_open_queued_during_flush_is_linted() {
+        let runner = MockRunner::with_delay(vec![], Duration::from_millis(50));
+        let (service, _) =
+            initialized(runner, serde_json::json!({ "startupGracePeriodMs": 100 })).await;
+        let backend = service.inner();
+        backend.initialized(InitializedParams {}).await;
+
+        let uri = |name: &str| Url::from_file_path(format!("/tmp/ichigyo-startup/{name}")).unwrap();
+        backend.queue_startup_open(
+            uri("first.md"),
+            "markdown".to_string(),
+            Duration::from_millis(100),
+        );
+        // 猶予期間の終わりの lint が実行中に、次のドキュメントが入る
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert_eq!(backend.runner.commands.lock().unwrap().len(), 1);
+        backend.queue_startup_open(uri("second.md"), "markdown".to_string(), Duration::ZERO);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(backend.runner.commands.lock().unwrap().len(), 2);
+        assert!(backend.startup_opens.is_empty());
+        assert!(!backend.startup_flush_scheduled.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn opens_after_startup_grace_period_lint_immediately() {
+        let (service, _) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+        backend.initialized(InitializedParams {}).await;
+
+        backend
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    Url::from_file_path("/tmp/ichigyo-startup/now.md").unwrap(),
+                    "markdown".to_string(),
+                    1,
+                    String::new(),
+                ),
+            })
+            .await;
+        assert_eq!(backend.runner.commands.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn identical_lint_results_are_published_once() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-publish-once-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.md");
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: path.display().to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let (service, mut socket) = initialized(MockRunner::new(results), Value::Null).await;
+        let backend = service.inner();
+
+        let lints = async {
+            backend.relint_and_publish(&uri).await;
+            backend.relint_and_publish(&uri).await;
+        };
+        let client = async {
+            let mut methods = Vec::new();
+            while let Ok(Some(request)) =
+                tokio::time::timeout(Duration::from_millis(200), socket.next()).await
+            {
+                methods.push(request.method().to_string());
+            }
+            methods
+        };
+        let ((), methods) = tokio::join!(lints, client);
+
+        assert_eq!(backend.runner.files.lock().unwrap().len(), 2);
+        assert_eq!(methods, ["textDocument/publishDiagnostics"]);
+    }
+
+    #[tokio::test]
+    async fn notify_on_clean_fires_only_on_transition_to_zero() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-notify-on-clean-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("README.md");
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: path.display().to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let (service, mut socket) = initialized(
+            MockRunner::new(results),
+            serde_json::json!({ "notifyOnClean": true }),
+        )
+        .await;
+        let backend = service.inner();
+
+        let lints = async {
+            // 問題あり → 0 件: 通知する
+            backend.lint_and_publish(&uri, None).await;
+            backend.runner.results.lock().unwrap().clear();
+            backend.relint_and_publish(&uri).await;
+            // 0 件 → 0 件: 通知せず、同じ診断なので publish もしない
+            backend.relint_and_publish(&uri).await;
+            // pull の lint で 0 件になっても通知しない
+            backend.runner.results.lock().unwrap().push(TextlintResult {
+                file_path: path.display().to_string(),
+                messages: vec![futatsu_message()],
+            });
+            backend.relint_and_publish(&uri).await;
+            backend.runner.results.lock().unwrap().clear();
+            backend.lint_document(&uri, None, LintMode::Report).await;
+        };
+        let client = async {
+            let mut methods = Vec::new();
+            while let Ok(Some(request)) =
+                tokio::time::timeout(Duration::from_millis(200), socket.next()).await
+            {
+                methods.push(request.method().to_string());
+            }
+            methods
+        };
+        let ((), methods) = tokio::join!(lints, client);
+
+        assert_eq!(
+            methods,
+            [
+                "textDocument/publishDiagnostics",
+                "textDocument/publishDiagnostics",
+                "window/showMessage",
+                "textDocument/publishDiagnostics",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn textlint_config_is_resolved_against_root() {
+        let root = std::env::temp_dir().join("ichigyo-ls-textlint-config-test");
+        std::fs::create_dir_all(&root).unwrap();
+
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+        let params = InitializeParams {
+            root_uri: Some(Url::from_file_path(&root).unwrap()),
+            initialization_options: Some(serde_json::json!({
+                "textlintConfig": "config/textlint.json",
+                "scopedConfigs": [{ "glob": "docs/ja/**", "configPath": ".textlintrc.ja.json" }]
+            })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        let config_for = |relative: &str| {
+            backend
+                .run_options_for(&root.join(relative), "markdown")
+                .config_path
+        };
+        assert_eq!(
+            config_for("README.md"),
+            Some(root.join("config/textlint.json"))
+        );
+        assert_eq!(
+            config_for("docs/ja/intro.md"),
+            Some(root.join(".textlintrc.ja.json"))
+        );
+    }
+
+    #[tokio::test]
+    async fn scoped_configs_route_files_to_first_matching_config() {
+        let root = std::env::temp_dir().join("ichigyo-ls-scoped-configs-test");
+        std::fs::create_dir_all(&root).unwrap();
+
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+        let params = InitializeParams {
+            root_uri: Some(Url::from_file_path(&root).unwrap()),
+            initialization_options: Some(serde_json::json!({
+                "scopedConfigs": [
+                    { "glob": "docs/ja/**", "configPath": ".textlintrc.ja.json" },
+                    { "glob": "docs/**", "configPath": "/etc/textlint/en.json" },
+                ]
+            })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        let config_for = |relative: &str| {
+            backend
+                .run_options_for(&root.join(relative), "markdown")
+                .config_path
+        };
+        assert_eq!(
+            config_for("docs/ja/guide/intro.md"),
+            Some(root.join(".textlintrc.ja.json"))
+        );
+        assert_eq!(
+            config_for("docs/en/intro.md"),
+            Some(PathBuf::from("/etc/textlint/en.json"))
+        );
+        assert_eq!(config_for("README.md"), None);
+    }
+
+    #[tokio::test]
+    async fn baseline_hides_existing_problems_but_shows_new_ones() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![futatsu_message()]),
+        );
+
+        let params = ExecuteCommandParams {
+            command: SNAPSHOT_BASELINE_COMMAND.to_string(),
+            arguments: vec![serde_json::json!(uri)],
+            work_done_progress_params: Default::default(),
+        };
+        let result = backend.execute_command(params).await.unwrap();
+        assert_eq!(result, Some(Value::from(1)));
+        assert!(backend.document_diagnostics(&uri).is_empty());
+
+        // 既存の問題は行がずれてもベースライン扱い。新しい問題だけが出る
+        let mut moved = futatsu_message();
+        moved.line = 3;
+        let new_problem = TextlintMessage {
+            rule_id: "no-doubled-joshi".to_string(),
+            message: "助詞の重複".to_string(),
+            line: 5,
+            column: 1,
+            index: None,
+            loc: None,
+            severity: 2,
+            fix: None,
+        };
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "# 見出し\n\nふたつの項目がある。\n\nこれがあれがある。",
+                vec![moved, new_problem],
+            ),
+        );
+
+        let diagnostics = backend.document_diagnostics(&uri);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].code,
+            Some(NumberOrString::String("no-doubled-joshi".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn fix_and_format_applies_textlint_fixes_without_formatter() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let mut overlapping = futatsu_message();
+        overlapping.fix = Some(FixCommand {
+            range: [1, 4],
+            text: "x".to_string(),
+        });
+        let doubled = TextlintMessage {
+            rule_id: "no-doubled-joshi".to_string(),
+            message: "助詞の重複".to_string(),
+            line: 1,
+            column: 7,
+            index: None,
+            loc: None,
+            severity: 2,
+            fix: Some(FixCommand {
+                range: [6, 7],
+                text: "が".to_string(),
+            }),
+        };
+        backend.state.insert(
+            uri.clone(),
+            document(
+                "ふたつの項目ががある。",
+                vec![futatsu_message(), overlapping, doubled],
+            ),
+        );
+
+        let params = ExecuteCommandParams {
+            command: FIX_AND_FORMAT_COMMAND.to_string(),
+            arguments: vec![serde_json::json!(uri)],
+            work_done_progress_params: Default::default(),
+        };
+        let client = async {
+            let request = socket.next().await.unwrap();
+            assert_eq!(request.method(), "workspace/applyEdit");
+            let params: ApplyWorkspaceEditParams =
+                serde_json::from_value(request.params().unwrap().clone()).unwrap();
+            let response = Response::from_ok(
+                request.id().unwrap().clone(),
+                serde_json::json!({ "applied": true }),
+            );
+            socket.send(response).await.unwrap();
+            params.edit
+        };
+        let (result, edit) = tokio::join!(backend.execute_command(params), client);
+
+        assert_eq!(result.unwrap(), Some(Value::Bool(true)));
+        let new_texts: Vec<String> = edit.changes.unwrap()[&uri]
+            .iter()
+            .map(|e| e.new_text.clone())
+            .collect();
+        assert_eq!(new_texts, vec!["2つ".to_string(), "が".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn fix_and_format_leaves_document_edited_after_lint() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let mut doc = document("ふたつの項目がある。", vec![futatsu_message()]);
+        doc.version = Some(1);
+        backend.state.insert(uri.clone(), doc);
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 2,
+                text: "追記。ふたつの項目がある。".to_string(),
+            },
+        );
+
+        let params = ExecuteCommandParams {
+            command: FIX_AND_FORMAT_COMMAND.to_string(),
+            arguments: vec![serde_json::json!(uri)],
+            work_done_progress_params: Default::default(),
+        };
+        let (result, message) = tokio::join!(backend.execute_command(params), socket.next());
+
+        assert_eq!(result.unwrap(), Some(Value::Bool(false)));
+        let message = message.unwrap();
+        assert_eq!(message.method(), "window/showMessage");
+        assert_eq!(
+            message.params().unwrap()["message"].as_str(),
+            Some(STALE_FIX_REASON)
+        );
+        let next = tokio::time::timeout(Duration::from_millis(50), socket.next()).await;
+        assert!(next.is_err(), "unexpected request: {next:?}");
+    }
+
+    #[tokio::test]
+    async fn formatting_replaces_whole_document_with_fixed_text() {
+        let runner = MockRunner::new(vec![]);
+        *runner.fixed.lock().unwrap() = Some("2つの項目がある。\n2行目".to_string());
+        let (service, _) = initialized(runner, Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 1,
+                text: "ふたつの項目がある。\n2行目\n".to_string(),
+            },
+        );
+
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier { uri },
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        };
+        let edits = backend.formatting(params).await.unwrap().unwrap();
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range {
+                    start: Position::new(0, 0),
+                    end: Position::new(2, 0),
+                },
+                // 元のテキストに合わせて末尾の改行を残す
+                new_text: "2つの項目がある。\n2行目\n".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn formatting_returns_none_without_changes() {
+        let (service, _) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        backend
+            .state
+            .insert(uri.clone(), document("2つの項目がある。", vec![]));
+
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier { uri },
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        };
+        assert_eq!(backend.formatting(params).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn formatting_honors_final_newline_options() {
+        let (service, _) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let format = |text: &str, options: FormattingOptions| {
+            backend.buffers.insert(
+                uri.clone(),
+                Buffer {
+                    version: 1,
+                    text: text.to_string(),
+                },
+            );
+            let params = DocumentFormattingParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                options,
+                work_done_progress_params: Default::default(),
+            };
+            async move {
+                backend
+                    .formatting(params)
+                    .await
+                    .unwrap()
+                    .map(|edits| edits[0].new_text.clone())
+            }
+        };
+
+        let trim = FormattingOptions {
+            trim_final_newlines: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            format("2つ\r\n\r\n\r\n", trim).await.as_deref(),
+            Some("2つ\r\n")
+        );
+        let insert = FormattingOptions {
+            insert_final_newline: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            format("2つ", insert.clone()).await.as_deref(),
+            Some("2つ\n")
+        );
+        assert_eq!(format("2つ\n", insert).await, None);
+    }
+
+    #[test]
+    fn preserve_trailing_newline_follows_original() {
+        assert_eq!(preserve_trailing_newline("a\n", "b".to_string()), "b\n");
+        assert_eq!(preserve_trailing_newline("a", "b\r\n".to_string()), "b");
+        assert_eq!(preserve_trailing_newline("a\n", "b\n".to_string()), "b\n");
+    }
+
+    #[tokio::test]
+    async fn export_textlint_json_round_trips() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let without_fix = TextlintMessage {
+            rule_id: "max-ten".to_string(),
+            index: Some(4),
+            fix: None,
+            ..futatsu_message()
+        };
+        let messages = vec![futatsu_message(), without_fix];
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", messages.clone()),
+        );
+
+        let params = ExecuteCommandParams {
+            command: EXPORT_TEXTLINT_JSON_COMMAND.to_string(),
+            arguments: vec![serde_json::json!(uri)],
+            work_done_progress_params: Default::default(),
+        };
+        let exported = backend.execute_command(params).await.unwrap().unwrap();
+        assert_eq!(exported[0]["messages"][0]["ruleId"], "prh");
+        assert!(exported[0]["messages"][1].get("fix").is_none());
+
+        let results: Vec<TextlintResult> = serde_json::from_value(exported).unwrap();
+        assert_eq!(
+            results,
+            vec![TextlintResult {
+                file_path: uri.to_file_path().unwrap().display().to_string(),
+                messages,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn same_range_fixes_keep_one_in_fix_all_and_both_in_menu() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let alternative = TextlintMessage {
+            rule_id: "ja-numbers".to_string(),
+            severity: 1,
+            fix: Some(FixCommand {
+                range: [0, 3],
+                text: "二つ".to_string(),
+            }),
+            ..futatsu_message()
+        };
+        backend.state.insert(
+            uri.clone(),
+            document("ふたつの項目がある。", vec![alternative, futatsu_message()]),
+        );
+
+        let range = Range {
+            start: Position::new(0, 0),
+            end: Position::new(0, 10),
+        };
+        let actions = backend
+            .code_action(code_action_params(uri.clone(), range))
+            .await
+            .unwrap()
+            .unwrap();
+        // 両方の fix と、ルールごとの行を無効にする action・ルール ID をコピーする action、fix-all
+        assert_eq!(actions.len(), 7);
+
+        let params = ExecuteCommandParams {
+            command: FIX_AND_FORMAT_COMMAND.to_string(),
+            arguments: vec![serde_json::json!(uri)],
+            work_done_progress_params: Default::default(),
+        };
+        let client = async {
+            // fix-all の code action と fixAndFormat のどちらも捨てた fix をログに残す
+            for _ in 0..2 {
+                let log = socket.next().await.unwrap();
+                assert_eq!(log.method(), "window/logMessage");
+            }
+            let request = socket.next().await.unwrap();
+            assert_eq!(request.method(), "workspace/applyEdit");
+            let params: ApplyWorkspaceEditParams =
+                serde_json::from_value(request.params().unwrap().clone()).unwrap();
+            let response = Response::from_ok(
+                request.id().unwrap().clone(),
+                serde_json::json!({ "applied": true }),
+            );
+            socket.send(response).await.unwrap();
+            params.edit
+        };
+        let (result, edit) = tokio::join!(backend.execute_command(params), client);
+
+        assert_eq!(result.unwrap(), Some(Value::Bool(true)));
+        let new_texts: Vec<String> = edit.changes.unwrap()[&uri]
+            .iter()
+            .map(|e| e.new_text.clone())
+            .collect();
+        assert_eq!(new_texts, vec!["2つ".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn doctor_reports_cached_textlint_version() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let params = || ExecuteCommandParams {
+            command: DOCTOR_COMMAND.to_string(),
+            arguments: vec![],
+            work_done_progress_params: Default::default(),
+        };
+        let report = backend.execute_command(params()).await.unwrap().unwrap();
+        assert_eq!(report["textlintVersion"], "14.0.4");
+        assert_eq!(report["positionEncoding"], "utf-16");
+
+        backend.execute_command(params()).await.unwrap();
+        assert_eq!(backend.runner.version_probes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn lint_now_command_lints_immediately() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-lint-now-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.md");
+        std::fs::write(&path, "ふたつの項目がある。").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: path.display().to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let (service, mut socket) = initialized(MockRunner::new(results), Value::Null).await;
+        let backend = service.inner();
+        // 最後に lint した内容と同じでも、didChange の待ちがあっても、すぐに lint する
+        backend
+            .state
+            .insert(uri.clone(), document("ふたつの項目がある。", vec![]));
+        backend.pending_lints.insert(
+            uri.clone(),
+            tokio::time::Instant::now() + Duration::from_secs(60),
+        );
+
+        let params = ExecuteCommandParams {
+            command: LINT_NOW_COMMAND.to_string(),
+            arguments: vec![serde_json::json!(uri)],
+            work_done_progress_params: Default::default(),
+        };
+        let (result, notification) = tokio::join!(backend.execute_command(params), socket.next());
+
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(*backend.runner.files.lock().unwrap(), vec![path]);
+        assert!(!backend.pending_lints.contains_key(&uri));
+        let notification = notification.unwrap();
+        assert_eq!(notification.method(), "textDocument/publishDiagnostics");
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+        assert_eq!(params.uri, uri);
+        assert_eq!(params.diagnostics.len(), 1);
+    }
 
-        let runner = MockRunner::new(results);
-        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+    #[tokio::test]
+    async fn fix_all_command_applies_every_fix_and_reports_count() {
+        let (service, mut socket) = initialized(MockRunner::new(vec![]), Value::Null).await;
         let backend = service.inner();
 
-        // simulate lint state
         let uri = Url::from_file_path("/tmp/test.md").unwrap();
-        let text = "test がが error";
+        let later = TextlintMessage {
+            rule_id: "ja-no-redundant-expression".to_string(),
+            fix: Some(FixCommand {
+                range: [7, 9],
+                text: "あり".to_string(),
+            }),
+            ..futatsu_message()
+        };
         backend.state.insert(
             uri.clone(),
-            (
-                text.to_string(),
-                vec![TextlintMessage {
-                    rule_id: "no-doubled-joshi".to_string(),
-                    message: "助詞の重複".to_string(),
-                    line: 1,
-                    column: 5,
-                    severity: 2,
-                    fix: Some(FixCommand {
-                        range: [6, 7],
-                        text: "けれど".to_string(),
-                    }),
-                }],
-            ),
+            document("ふたつの項目がある。", vec![futatsu_message(), later]),
         );
 
-        let params = CodeActionParams {
-            text_document: TextDocumentIdentifier::new(uri),
-            range: Range {
-                start: Position::new(0, 0),
-                end: Position::new(0, 10),
+        let params = ExecuteCommandParams {
+            command: FIX_ALL_COMMAND.to_string(),
+            arguments: vec![serde_json::json!(uri)],
+            work_done_progress_params: Default::default(),
+        };
+        let client = async {
+            let request = socket.next().await.unwrap();
+            assert_eq!(request.method(), "workspace/applyEdit");
+            let params: ApplyWorkspaceEditParams =
+                serde_json::from_value(request.params().unwrap().clone()).unwrap();
+            let response = Response::from_ok(
+                request.id().unwrap().clone(),
+                serde_json::json!({ "applied": true }),
+            );
+            socket.send(response).await.unwrap();
+            let message = socket.next().await.unwrap();
+            assert_eq!(message.method(), "window/showMessage");
+            let shown: ShowMessageParams =
+                serde_json::from_value(message.params().unwrap().clone()).unwrap();
+            (params.edit, shown.message)
+        };
+        let (result, (edit, message)) = tokio::join!(backend.execute_command(params), client);
+
+        assert_eq!(result.unwrap(), Some(Value::from(2)));
+        assert_eq!(edit.changes.unwrap()[&uri].len(), 2);
+        assert_eq!(message, "applied 2 textlint fixes");
+    }
+
+    #[tokio::test]
+    async fn did_change_configuration_applies_settings_and_relints() {
+        let (service, _socket) = initialized(
+            MockRunner::new(vec![]),
+            serde_json::json!({ "textlintPath": "/opt/old/textlint" }),
+        )
+        .await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/ichigyo-ls-config-change/doc.md").unwrap();
+        backend
+            .state
+            .insert(uri.clone(), document("ふたつの項目がある。", vec![]));
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 1,
+                text: "ふたつの項目がある。".to_string(),
             },
-            context: CodeActionContext::default(),
+        );
+
+        backend
+            .did_change_configuration(DidChangeConfigurationParams {
+                settings: serde_json::json!({
+                    "ichigyo": {
+                        "textlintPath": "/opt/new/textlint",
+                        "lintDebounceMs": 0,
+                    }
+                }),
+            })
+            .await;
+
+        let config = backend.config();
+        assert_eq!(config.lint_debounce_ms, 0);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while backend.runner.binaries.lock().unwrap().is_empty() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("relint should run in the background");
+        assert_eq!(
+            *backend.runner.binaries.lock().unwrap(),
+            [Some(PathBuf::from("/opt/new/textlint"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_setting_is_logged_and_others_are_kept() {
+        let (service, mut socket) = initialized(
+            MockRunner::new(vec![]),
+            serde_json::json!({ "titleMaxLength": "long", "lintDebounceMs": 0 }),
+        )
+        .await;
+
+        let log = socket.next().await.unwrap();
+        assert_eq!(log.method(), "window/logMessage");
+        let message = log.params().unwrap()["message"].as_str().unwrap();
+        assert!(message.contains("titleMaxLength"), "{message}");
+        assert_eq!(service.inner().config().lint_debounce_ms, 0);
+    }
+
+    #[tokio::test]
+    async fn did_change_configuration_keeps_unsent_settings() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+        let params = InitializeParams {
+            initialization_options: Some(serde_json::json!({
+                "titleMaxLength": 10,
+                "lintDebounceMs": 100,
+            })),
+            ..Default::default()
+        };
+        backend.initialize(params).await.unwrap();
+
+        backend
+            .did_change_configuration(DidChangeConfigurationParams {
+                settings: serde_json::json!({ "ichigyo": { "titleMaxLength": 20 } }),
+            })
+            .await;
+
+        let config = backend.config();
+        assert_eq!(config.title_max_length, 20);
+        assert_eq!(config.lint_debounce_ms, 100);
+    }
+
+    #[tokio::test]
+    async fn did_change_configuration_ignores_settings_without_section() {
+        let (service, _socket) = initialized(
+            MockRunner::new(vec![]),
+            serde_json::json!({ "textlintPath": "/opt/old/textlint" }),
+        )
+        .await;
+        let backend = service.inner();
+
+        for settings in [
+            serde_json::json!({}),
+            serde_json::json!({ "otherExtension": { "enable": true } }),
+        ] {
+            backend
+                .did_change_configuration(DidChangeConfigurationParams { settings })
+                .await;
+        }
+
+        assert_eq!(
+            backend.config().textlint_path,
+            Some(PathBuf::from("/opt/old/textlint"))
+        );
+    }
+
+    #[tokio::test]
+    async fn relint_all_lints_every_tracked_document() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let backend = service.inner();
+
+        let paths: Vec<PathBuf> = ["a.md", "b.md", "c.txt"]
+            .iter()
+            .map(|name| PathBuf::from("/tmp/ichigyo-relint").join(name))
+            .collect();
+        for path in &paths {
+            backend.state.insert(
+                Url::from_file_path(path).unwrap(),
+                document("ふたつの項目がある。", vec![futatsu_message()]),
+            );
+        }
+
+        let params = ExecuteCommandParams {
+            command: RELINT_ALL_COMMAND.to_string(),
+            arguments: vec![],
+            work_done_progress_params: Default::default(),
+        };
+        let summary = backend.execute_command(params).await.unwrap().unwrap();
+        assert_eq!(summary["documents"], 3);
+
+        let mut linted = backend.runner.files.lock().unwrap().clone();
+        linted.sort();
+        assert_eq!(linted, paths);
+    }
+
+    #[tokio::test]
+    async fn workspace_diagnostic_reports_every_tracked_document() {
+        let runner = MockRunner::new(vec![TextlintResult {
+            file_path: String::new(),
+            messages: vec![futatsu_message()],
+        }]);
+        let (service, mut socket) =
+            initialized(runner, serde_json::json!({ "workspaceDiagnostics": true })).await;
+        let backend = service.inner();
+
+        let uris: Vec<Url> = ["a.md", "b.md"]
+            .iter()
+            .map(|name| Url::from_file_path(format!("/tmp/ichigyo-workspace/{name}")).unwrap())
+            .collect();
+        for uri in &uris {
+            backend
+                .state
+                .insert(uri.clone(), document("ふたつの項目がある。", vec![]));
+            backend.buffers.insert(
+                uri.clone(),
+                Buffer {
+                    version: 1,
+                    text: "ふたつの項目がある。".to_string(),
+                },
+            );
+        }
+
+        let params = WorkspaceDiagnosticParams {
+            identifier: None,
+            previous_result_ids: vec![],
             work_done_progress_params: Default::default(),
             partial_result_params: Default::default(),
         };
+        let WorkspaceDiagnosticReportResult::Report(report) =
+            backend.workspace_diagnostic(params).await.unwrap()
+        else {
+            panic!("expected a full report");
+        };
 
-        let result = backend.code_action(params).await.unwrap();
-        let actions = result.unwrap();
-        assert_eq!(actions.len(), 1);
+        let mut reported: Vec<(Url, usize)> = report
+            .items
+            .into_iter()
+            .map(|item| match item {
+                WorkspaceDocumentDiagnosticReport::Full(full) => {
+                    (full.uri, full.full_document_diagnostic_report.items.len())
+                }
+                WorkspaceDocumentDiagnosticReport::Unchanged(_) => panic!("unexpected unchanged"),
+            })
+            .collect();
+        reported.sort();
+        assert_eq!(reported, [(uris[0].clone(), 1), (uris[1].clone(), 1)]);
+        assert_eq!(backend.runner.texts.lock().unwrap().len(), 2);
+        // 結果はレスポンスだけで返し、publishDiagnostics は送らない
+        let pushed = tokio::time::timeout(Duration::from_millis(50), socket.next()).await;
+        assert!(pushed.is_err(), "unexpected notification: {pushed:?}");
+    }
 
-        if let CodeActionOrCommand::CodeAction(action) = &actions[0] {
-            assert_eq!(action.kind, Some(CodeActionKind::QUICKFIX));
-            assert!(action.title.contains("no-doubled-joshi"));
-        } else {
-            panic!("expected CodeAction");
+    #[tokio::test]
+    async fn concurrent_push_and_pull_publish_once() {
+        let runner = MockRunner::with_delay(
+            vec![TextlintResult {
+                file_path: String::new(),
+                messages: vec![futatsu_message()],
+            }],
+            Duration::from_millis(50),
+        );
+        let (service, mut socket) =
+            initialized(runner, serde_json::json!({ "workspaceDiagnostics": true })).await;
+        let backend = service.inner();
+        let uri = Url::from_file_path("/tmp/ichigyo-push-pull/doc.md").unwrap();
+        backend.state.insert(uri.clone(), document("", vec![]));
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 1,
+                text: "ふたつの項目がある。".to_string(),
+            },
+        );
+
+        let params = WorkspaceDiagnosticParams {
+            identifier: None,
+            previous_result_ids: vec![],
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let (_, report, notification) = tokio::join!(
+            backend.lint_and_publish(&uri, None),
+            backend.workspace_diagnostic(params),
+            tokio::time::timeout(Duration::from_secs(5), socket.next()),
+        );
+
+        // pull の lint が始まっても push の結果は捨てずに 1 回だけ送る
+        let notification = notification.expect("push should be published").unwrap();
+        assert_eq!(notification.method(), "textDocument/publishDiagnostics");
+        let pushed: PublishDiagnosticsParams =
+            serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+        let WorkspaceDiagnosticReportResult::Report(report) = report.unwrap() else {
+            panic!("expected a full report");
+        };
+        let [WorkspaceDocumentDiagnosticReport::Full(full)] = report.items.as_slice() else {
+            panic!("expected one full report");
+        };
+        assert_eq!(
+            full.full_document_diagnostic_report.items,
+            pushed.diagnostics
+        );
+        assert_eq!(pushed.diagnostics.len(), 1);
+        assert_eq!(backend.state.get(&uri).unwrap().messages.len(), 1);
+
+        // 同じ版の push は送り直さない
+        backend.lint_and_publish(&uri, None).await;
+        let pushed = tokio::time::timeout(Duration::from_millis(50), socket.next()).await;
+        assert!(pushed.is_err(), "unexpected notification: {pushed:?}");
+    }
+
+    #[tokio::test]
+    async fn diagnostic_provider_requires_workspace_diagnostics() {
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let result = service
+            .inner()
+            .initialize(InitializeParams::default())
+            .await
+            .unwrap();
+        assert!(result.capabilities.diagnostic_provider.is_none());
+
+        let runner = MockRunner::new(vec![]);
+        let (service, _) = LspService::new(|client| Backend::new(client, runner));
+        let params = InitializeParams {
+            initialization_options: Some(serde_json::json!({ "workspaceDiagnostics": true })),
+            ..Default::default()
+        };
+        let result = service.inner().initialize(params).await.unwrap();
+        assert!(result.capabilities.diagnostic_provider.is_some());
+    }
+
+    #[tokio::test]
+    async fn slow_lint_reports_progress() {
+        let capabilities = serde_json::json!({ "window": { "workDoneProgress": true } });
+        let runner = MockRunner::with_delay(vec![], Duration::from_millis(300));
+        let (service, mut socket) = initialized_with(runner, capabilities, Value::Null).await;
+        let backend = service.inner();
+
+        let uri = Url::from_file_path("/tmp/ichigyo-ls-progress/slow.md").unwrap();
+        backend.buffers.insert(
+            uri.clone(),
+            Buffer {
+                version: 1,
+                text: "ふたつの項目がある。".to_string(),
+            },
+        );
+
+        let client = async {
+            let mut messages = Vec::new();
+            while let Ok(Some(request)) =
+                tokio::time::timeout(Duration::from_millis(500), socket.next()).await
+            {
+                let kind = request
+                    .params()
+                    .and_then(|params| params["value"]["kind"].as_str())
+                    .map(str::to_string);
+                messages.push((request.method().to_string(), kind));
+                if let Some(id) = request.id() {
+                    let response = Response::from_ok(id.clone(), Value::Null);
+                    socket.send(response).await.unwrap();
+                }
+            }
+            messages
+        };
+        let ((), messages) = tokio::join!(backend.lint_and_publish(&uri, None), client);
+
+        let progress = |kind: &str| ("$/progress".to_string(), Some(kind.to_string()));
+        assert_eq!(
+            messages,
+            [
+                ("window/workDoneProgress/create".to_string(), None),
+                progress("begin"),
+                progress("end"),
+                ("textDocument/publishDiagnostics".to_string(), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn relint_all_reports_progress_when_supported() {
+        let capabilities = serde_json::json!({ "window": { "workDoneProgress": true } });
+        let (service, mut socket) =
+            initialized_with(MockRunner::new(vec![]), capabilities, Value::Null).await;
+        let backend = service.inner();
+        backend.state.insert(
+            Url::from_file_path("/tmp/ichigyo-relint/a.md").unwrap(),
+            document("text", vec![]),
+        );
+
+        let client = async {
+            let mut methods = Vec::new();
+            while let Ok(Some(request)) =
+                tokio::time::timeout(Duration::from_millis(200), socket.next()).await
+            {
+                methods.push(request.method().to_string());
+                if let Some(id) = request.id() {
+                    let response = Response::from_ok(id.clone(), Value::Null);
+                    socket.send(response).await.unwrap();
+                }
+            }
+            methods
+        };
+        let (summary, methods) = tokio::join!(backend.relint_all(), client);
+
+        assert_eq!(summary["documents"], 1);
+        assert_eq!(
+            methods,
+            [
+                "window/workDoneProgress/create",
+                "$/progress",
+                "$/progress",
+                "$/progress",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn initialized_pulls_configuration_and_registers_watchers() {
+        let capabilities = serde_json::json!({
+            "workspace": {
+                "configuration": true,
+                "didChangeWatchedFiles": { "dynamicRegistration": true },
+            }
+        });
+        let (service, mut socket) = initialized_with(
+            MockRunner::new(vec![]),
+            capabilities,
+            serde_json::json!({ "titleMaxLength": 10 }),
+        )
+        .await;
+        let backend = service.inner();
+
+        let client = async {
+            let request = socket.next().await.unwrap();
+            assert_eq!(request.method(), "workspace/configuration");
+            let params: ConfigurationParams =
+                serde_json::from_value(request.params().unwrap().clone()).unwrap();
+            assert_eq!(params.items[0].section.as_deref(), Some("ichigyo"));
+            let response = Response::from_ok(
+                request.id().unwrap().clone(),
+                serde_json::json!([{ "titleMaxLength": 5 }]),
+            );
+            socket.send(response).await.unwrap();
+
+            let request = socket.next().await.unwrap();
+            assert_eq!(request.method(), "client/registerCapability");
+            let params: RegistrationParams =
+                serde_json::from_value(request.params().unwrap().clone()).unwrap();
+            assert_eq!(
+                params.registrations[0].method,
+                "workspace/didChangeWatchedFiles"
+            );
+            let response = Response::from_ok(request.id().unwrap().clone(), Value::Null);
+            socket.send(response).await.unwrap();
+        };
+        tokio::join!(backend.initialized(InitializedParams {}), client);
+
+        assert_eq!(backend.config().title_max_length, 5);
+    }
+
+    #[tokio::test]
+    async fn empty_pulled_configuration_keeps_initialization_options() {
+        let capabilities = serde_json::json!({ "workspace": { "configuration": true } });
+        let (service, mut socket) = initialized_with(
+            MockRunner::new(vec![]),
+            capabilities,
+            serde_json::json!({ "titleMaxLength": 10 }),
+        )
+        .await;
+        let backend = service.inner();
+
+        for reply in [serde_json::json!([{}]), serde_json::json!([null])] {
+            let client = async {
+                let request = socket.next().await.unwrap();
+                assert_eq!(request.method(), "workspace/configuration");
+                let response = Response::from_ok(request.id().unwrap().clone(), reply);
+                socket.send(response).await.unwrap();
+            };
+            tokio::join!(backend.pull_configuration(), client);
+            assert_eq!(backend.config().title_max_length, 10);
+        }
+    }
+
+    #[tokio::test]
+    async fn configuration_change_reregisters_watchers() {
+        let capabilities = serde_json::json!({
+            "workspace": { "didChangeWatchedFiles": { "dynamicRegistration": true } }
+        });
+        let (service, mut socket) =
+            initialized_with(MockRunner::new(vec![]), capabilities, Value::Null).await;
+        let backend = service.inner();
+
+        let settings = serde_json::json!({
+            "ichigyo": { "relintOnExternalChange": true, "targetExtensions": ["md", "mdx"] }
+        });
+        backend
+            .did_change_configuration(DidChangeConfigurationParams { settings })
+            .await;
+
+        let request = socket.next().await.unwrap();
+        assert_eq!(request.method(), "client/unregisterCapability");
+        let response = Response::from_ok(request.id().unwrap().clone(), Value::Null);
+        socket.send(response).await.unwrap();
+        let request = socket.next().await.unwrap();
+        assert_eq!(request.method(), "client/registerCapability");
+        let params: RegistrationParams =
+            serde_json::from_value(request.params().unwrap().clone()).unwrap();
+        let options: DidChangeWatchedFilesRegistrationOptions =
+            serde_json::from_value(params.registrations[0].register_options.clone().unwrap())
+                .unwrap();
+        let globs: Vec<GlobPattern> = options
+            .watchers
+            .into_iter()
+            .map(|watcher| watcher.glob_pattern)
+            .collect();
+        assert_eq!(
+            globs,
+            [
+                GlobPattern::String(TEXTLINT_CONFIG_GLOB.to_string()),
+                GlobPattern::String("**/*.{md,mdx}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn shift_position_follows_inserted_and_removed_lines() {
+        let enc = PositionEncoding::Utf16;
+        let edited = Range::new(Position::new(1, 2), Position::new(2, 4));
+        // 編集の終わりと同じ行の位置は、挿入した最後の行の後ろに続く
+        assert_eq!(
+            shift_position(Position::new(2, 6), edited, "あ\nいう", enc),
+            Position::new(2, 4)
+        );
+        assert_eq!(
+            shift_position(Position::new(2, 6), edited, "あ", enc),
+            Position::new(1, 5)
+        );
+        // 後ろの行は増減した行数だけずれる
+        assert_eq!(
+            shift_position(Position::new(5, 1), edited, "", enc),
+            Position::new(4, 1)
+        );
+        assert_eq!(
+            shift_position(Position::new(5, 1), edited, "\n\n", enc),
+            Position::new(6, 1)
+        );
+    }
+
+    #[test]
+    fn extensions_glob_matches_target_extensions() {
+        assert_eq!(extensions_glob(&[]), None);
+        assert_eq!(
+            extensions_glob(&[".md".to_string()]).as_deref(),
+            Some("**/*.md")
+        );
+        assert_eq!(
+            extensions_glob(&["md".to_string(), "txt".to_string()]).as_deref(),
+            Some("**/*.{md,txt}")
+        );
+    }
+
+    #[tokio::test]
+    async fn external_change_relints_open_document() {
+        let dir = std::env::temp_dir().join("ichigyo-ls-external-change-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let changed = dir.join("changed.md");
+        let unchanged = dir.join("unchanged.md");
+        std::fs::write(&changed, "ふたつの項目がある。").unwrap();
+        std::fs::write(&unchanged, "変更なし").unwrap();
+        let changed_uri = Url::from_file_path(&changed).unwrap();
+        let unchanged_uri = Url::from_file_path(&unchanged).unwrap();
+
+        let results = vec![TextlintResult {
+            file_path: changed.display().to_string(),
+            messages: vec![futatsu_message()],
+        }];
+        let (service, mut socket) = initialized(
+            MockRunner::new(results),
+            serde_json::json!({ "relintOnExternalChange": true }),
+        )
+        .await;
+        let backend = service.inner();
+
+        // どちらもエディタ上の内容で lint 済み。changed.md だけディスク上の内容が違う
+        for (uri, buffer) in [(&changed_uri, "未保存の編集"), (&unchanged_uri, "変更なし")]
+        {
+            let mut doc = document(buffer, vec![]);
+            doc.version = Some(1);
+            backend.state.insert(uri.clone(), doc);
+            let buffer = Buffer {
+                version: 1,
+                text: buffer.to_string(),
+            };
+            backend.buffers.insert(uri.clone(), buffer);
         }
+
+        let events = [&changed_uri, &unchanged_uri]
+            .map(|uri| FileEvent::new(uri.clone(), FileChangeType::CHANGED));
+        backend
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: events.to_vec(),
+            })
+            .await;
+
+        assert_eq!(*backend.runner.files.lock().unwrap(), vec![changed]);
+        assert_eq!(
+            *backend.runner.texts.lock().unwrap(),
+            vec!["未保存の編集".to_string()]
+        );
+        assert_eq!(
+            backend.buffers.get(&changed_uri).unwrap().text,
+            "未保存の編集"
+        );
+        let notification = socket.next().await.unwrap();
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(notification.params().unwrap().clone()).unwrap();
+        assert_eq!(params.uri, changed_uri);
+        assert_eq!(params.diagnostics.len(), 1);
     }
 
     #[tokio::test]
-    async fn code_action_returns_none_for_no_fix() {
+    async fn textlintrc_change_relints_every_open_document() {
         let runner = MockRunner::new(vec![]);
         let (service, _) = LspService::new(|client| Backend::new(client, runner));
         let backend = service.inner();
 
+        let dir = PathBuf::from("/tmp/ichigyo-ls-config-watch");
+        let paths = [dir.join("a.md"), dir.join("b.md")];
+        for path in &paths {
+            let uri = Url::from_file_path(path).unwrap();
+            backend
+                .state
+                .insert(uri.clone(), document("ふたつの項目がある。", vec![]));
+            let buffer = Buffer {
+                version: 1,
+                text: "ふたつの項目がある。".to_string(),
+            };
+            backend.buffers.insert(uri, buffer);
+        }
+
+        // 設定ファイル以外の変更は relintOnExternalChange がなければ無視する
+        let change = |path: PathBuf| DidChangeWatchedFilesParams {
+            changes: vec![FileEvent::new(
+                Url::from_file_path(path).unwrap(),
+                FileChangeType::CHANGED,
+            )],
+        };
+        backend
+            .did_change_watched_files(change(dir.join("c.md")))
+            .await;
+        assert!(backend.runner.files.lock().unwrap().is_empty());
+
+        backend
+            .did_change_watched_files(change(dir.join(".textlintrc.json")))
+            .await;
+        let mut files = backend.runner.files.lock().unwrap().clone();
+        files.sort();
+        assert_eq!(files, paths);
+    }
+
+    #[tokio::test]
+    async fn status_notification_counts_published_diagnostics() {
+        let (service, mut socket) = initialized(
+            MockRunner::new(vec![]),
+            serde_json::json!({ "statusNotifications": true }),
+        )
+        .await;
+        let backend = service.inner();
+
         let uri = Url::from_file_path("/tmp/test.md").unwrap();
+        let with_severity = |rule_id: &str, severity| TextlintMessage {
+            rule_id: rule_id.to_string(),
+            severity,
+            ..futatsu_message()
+        };
         backend.state.insert(
             uri.clone(),
-            (
-                "text".to_string(),
-                vec![TextlintMessage {
-                    rule_id: "max-ten".to_string(),
-                    message: "読点が多い".to_string(),
-                    line: 1,
-                    column: 1,
-                    severity: 1,
-                    fix: None,
-                }],
+            document(
+                "ふたつの項目がある。",
+                vec![
+                    with_severity("a", 2),
+                    with_severity("b", 2),
+                    with_severity("c", 1),
+                    with_severity("d", 0),
+                ],
             ),
         );
 
-        let params = CodeActionParams {
-            text_document: TextDocumentIdentifier::new(uri),
-            range: Range {
-                start: Position::new(0, 0),
-                end: Position::new(0, 5),
-            },
-            context: CodeActionContext::default(),
-            work_done_progress_params: Default::default(),
-            partial_result_params: Default::default(),
+        let client = async {
+            let published = socket.next().await.unwrap();
+            let status = socket.next().await.unwrap();
+            (published, status)
         };
+        let (_, (published, status)) = tokio::join!(backend.publish(&uri), client);
 
-        let result = backend.code_action(params).await.unwrap();
-        assert!(result.is_none());
+        let published: PublishDiagnosticsParams =
+            serde_json::from_value(published.params().unwrap().clone()).unwrap();
+        assert_eq!(status.method(), STATUS_METHOD);
+        let status: StatusParams =
+            serde_json::from_value(status.params().unwrap().clone()).unwrap();
+        assert_eq!(
+            status,
+            StatusParams::new(uri.clone(), &published.diagnostics)
+        );
+        assert_eq!(
+            status,
+            StatusParams {
+                uri,
+                errors: 2,
+                warnings: 1,
+                information: 0,
+                hints: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn initialized_skips_requests_without_client_support() {
+        let (service, mut socket) = initialized(
+            MockRunner::new(vec![]),
+            serde_json::json!({ "warmup": false }),
+        )
+        .await;
+        let backend = service.inner();
+
+        backend.initialized(InitializedParams {}).await;
+        drop(service);
+
+        assert!(socket.next().await.is_none());
     }
 }
@@ -1,11 +1,88 @@
-use std::path::Path;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::formatter::FILE_PLACEHOLDER;
 
 /// textlint を実行して結果を返すトレイト。テスト時にモック可能。
 #[async_trait::async_trait]
 pub trait TextlintRunner: Send + Sync + 'static {
-    async fn run(&self, file_path: &Path, work_dir: &Path) -> anyhow::Result<Vec<TextlintResult>>;
+    async fn run(
+        &self,
+        file_path: &Path,
+        work_dir: &Path,
+        options: &RunOptions,
+    ) -> anyhow::Result<Vec<TextlintResult>>;
+
+    /// 保存されていない内容 `text` を stdin で渡して lint する。`file_path` は
+    /// `--stdin-filename` として設定やプラグインの選択にだけ使い、ディスク上の内容は読まない。
+    async fn run_stdin(
+        &self,
+        text: &str,
+        file_path: &Path,
+        work_dir: &Path,
+        options: &RunOptions,
+    ) -> anyhow::Result<Vec<TextlintResult>>;
+
+    /// `text` に textlint の fix をすべて適用した結果を返す。ファイルは書き換えない。
+    async fn fix_stdin(
+        &self,
+        text: &str,
+        file_path: &Path,
+        work_dir: &Path,
+        options: &RunOptions,
+    ) -> anyhow::Result<String>;
+
+    /// 空の入力を lint して Node やルールのモジュールキャッシュを温める。
+    /// 設定ファイルの誤りもここで検出できる。
+    async fn warmup(&self, work_dir: &Path, options: &RunOptions) -> anyhow::Result<()>;
+
+    /// `textlint --version` で textlint のバージョンを調べる。
+    async fn version(&self, work_dir: &Path, options: &RunOptions) -> anyhow::Result<String>;
+}
+
+/// textlint プロセスをどこで起動・待機するか。
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dispatch {
+    /// tokio の非同期プロセス API を使う。
+    #[default]
+    Async,
+    /// `spawn_blocking` のスレッドプール上で起動する。
+    /// 大量の lint が同時に走る場合に非同期ランタイムのワーカーを塞がない。
+    Blocking,
+}
+
+/// textlint 実行時のオプション。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunOptions {
+    pub dispatch: Dispatch,
+    /// ルールやプラグインを解決する `node_modules` ディレクトリ。`NODE_PATH` 経由で渡す。
+    pub modules_dir: Option<PathBuf>,
+    /// textlint の代わりに実行するコマンドの argv。`{file}` はファイルパスに置き換える。
+    /// 出力は textlint の JSON 形式でなければならない。`None` なら textlint を使う。
+    pub command: Option<Vec<String>>,
+    /// `--config` で渡す設定ファイル。`None` なら textlint の探索に任せる。
+    pub config_path: Option<PathBuf>,
+    /// プロジェクトの `.textlintrc` を読まずに `rules` だけで lint する。
+    pub no_textlintrc: bool,
+    /// `--rule` で有効にするルール名。
+    pub rules: Vec<String>,
+    /// `--rulesdir` でルールを読み込むディレクトリ。
+    pub rules_dir: Option<PathBuf>,
+    /// `--preset` で有効にするプリセット。
+    pub preset: Option<String>,
+    /// `.txt` のファイルを lint するとき `--plugin @textlint/textlint-plugin-text` を付ける。
+    pub force_plaintext_plugin: bool,
+    /// コマンドの前に付けるランチャーの argv。引数はそのまま後ろに続ける。
+    pub wrapper: Vec<String>,
+    /// コマンドラインをクォートして 1 つの引数として渡すシェルの argv (例: `["bash", "-lc"]`)。
+    pub shell: Vec<String>,
+    /// 起動する textlint の実行ファイル。`None` なら `PATH` から `textlint` を探す。
+    pub binary: Option<PathBuf>,
 }
 
 /// 実際に textlint コマンドを呼び出す実装。
@@ -13,22 +90,386 @@ pub struct CommandRunner;
 
 #[async_trait::async_trait]
 impl TextlintRunner for CommandRunner {
-    async fn run(&self, file_path: &Path, work_dir: &Path) -> anyhow::Result<Vec<TextlintResult>> {
-        let output = tokio::process::Command::new("textlint")
-            .args(["--format", "json"])
-            .arg(file_path)
-            .current_dir(work_dir)
-            .output()
-            .await?;
+    async fn run(
+        &self,
+        file_path: &Path,
+        work_dir: &Path,
+        options: &RunOptions,
+    ) -> anyhow::Result<Vec<TextlintResult>> {
+        let command = self.command(file_path, work_dir, options);
+        let output = execute(command, options.dispatch).await?;
+        parse_output(output)
+    }
+
+    async fn run_stdin(
+        &self,
+        text: &str,
+        file_path: &Path,
+        work_dir: &Path,
+        options: &RunOptions,
+    ) -> anyhow::Result<Vec<TextlintResult>> {
+        let mut argv = vec![textlint_program(options)];
+        argv.extend(textlint_config_args(options, file_path));
+        argv.extend([
+            OsString::from("--stdin"),
+            OsString::from("--stdin-filename"),
+            file_path.as_os_str().to_owned(),
+            OsString::from("--format"),
+            OsString::from("json"),
+        ]);
+        let command = self.base_command(argv, work_dir, options);
+        let output = execute_with_stdin(command, options.dispatch, text.to_string()).await?;
+        parse_output(output)
+    }
+
+    async fn fix_stdin(
+        &self,
+        text: &str,
+        file_path: &Path,
+        work_dir: &Path,
+        options: &RunOptions,
+    ) -> anyhow::Result<String> {
+        let mut argv = vec![textlint_program(options)];
+        argv.extend(textlint_config_args(options, file_path));
+        argv.extend([
+            OsString::from("--fix"),
+            OsString::from("--dry-run"),
+            OsString::from("--stdin"),
+            OsString::from("--stdin-filename"),
+            file_path.as_os_str().to_owned(),
+            OsString::from("--format"),
+            OsString::from("fixed-result"),
+        ]);
+        let command = self.base_command(argv, work_dir, options);
+        let output = execute_with_stdin(command, options.dispatch, text.to_string()).await?;
+        // 直せない問題が残ると exit code 1 になるが、stdout には修正後のテキストが出る
+        if output.stdout.is_empty() && !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("textlint --fix failed: {}", stderr.trim());
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    async fn warmup(&self, work_dir: &Path, options: &RunOptions) -> anyhow::Result<()> {
+        // stdin は空なので、設定とルールを読み込むだけで終わる
+        let mut argv = vec![textlint_program(options)];
+        argv.extend(textlint_config_args(options, Path::new("warmup.md")));
+        argv.extend(
+            [
+                "--stdin",
+                "--stdin-filename",
+                "warmup.md",
+                "--format",
+                "json",
+            ]
+            .map(OsString::from),
+        );
+        let command = self.base_command(argv, work_dir, options);
+
+        let output = execute(command, options.dispatch).await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("textlint warmup failed: {}", stderr.trim());
+        }
+        Ok(())
+    }
+
+    async fn version(&self, work_dir: &Path, options: &RunOptions) -> anyhow::Result<String> {
+        let argv = vec![textlint_program(options), OsString::from("--version")];
+        let command = self.base_command(argv, work_dir, options);
+
+        let output = execute(command, options.dispatch).await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("textlint --version failed: {}", stderr.trim());
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.trim().trim_start_matches('v').to_string())
+    }
+}
+
+impl CommandRunner {
+    /// `argv` を実行するコマンドに作業ディレクトリ・環境変数を設定して返す。
+    /// `wrapper` / `shell` が設定されていれば、それを経由して起動する。
+    fn base_command(&self, argv: Vec<OsString>, work_dir: &Path, options: &RunOptions) -> Command {
+        let mut argv: Vec<OsString> = options
+            .wrapper
+            .iter()
+            .map(OsString::from)
+            .chain(argv)
+            .collect();
+        if !options.shell.is_empty() {
+            // シェルにはコマンドライン全体を 1 つの引数として渡すので、各引数をクォートする
+            let line = shell_words::join(argv.iter().map(|arg| arg.to_string_lossy()));
+            argv = options
+                .shell
+                .iter()
+                .map(OsString::from)
+                .chain([OsString::from(line)])
+                .collect();
+        }
+
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        command.current_dir(work_dir);
+        if let Some(dir) = &options.modules_dir {
+            command.env("NODE_PATH", node_path(dir));
+        }
+        command
+    }
+
+    /// lint の起動コマンドを組み立てる。`options.command` がなければ textlint を使う。
+    fn command(&self, file_path: &Path, work_dir: &Path, options: &RunOptions) -> Command {
+        let argv: Vec<OsString> = match options.command.as_deref() {
+            Some(command) if !command.is_empty() => {
+                let file = file_path.to_string_lossy();
+                command
+                    .iter()
+                    .map(|arg| OsString::from(arg.replace(FILE_PLACEHOLDER, &file)))
+                    .collect()
+            }
+            _ => {
+                let mut argv = vec![textlint_program(options)];
+                argv.extend(textlint_config_args(options, file_path));
+                argv.extend([
+                    OsString::from("--format"),
+                    OsString::from("json"),
+                    file_path.as_os_str().to_owned(),
+                ]);
+                argv
+            }
+        };
+        self.base_command(argv, work_dir, options)
+    }
+}
+
+/// `text` に textlint の fix をすべて適用したテキストを返す。LSP とは独立に使える。
+///
+/// `textlint --fix --dry-run` を実行するので、結果は `textlint --fix` でファイルを
+/// 書き換えた場合とまったく同じになる。`ext` は `md` などの拡張子で、`work_dir` の
+/// 設定ファイルとともにプラグインやルールの選択に使う。
+pub async fn fix_all_text<R: TextlintRunner + ?Sized>(
+    runner: &R,
+    text: &str,
+    ext: &str,
+    work_dir: &Path,
+) -> anyhow::Result<String> {
+    let file_path = work_dir.join(format!("stdin.{ext}"));
+    runner
+        .fix_stdin(text, &file_path, work_dir, &RunOptions::default())
+        .await
+}
+
+/// 起動する textlint の実行ファイル。
+fn textlint_program(options: &RunOptions) -> OsString {
+    match &options.binary {
+        Some(binary) => binary.as_os_str().to_owned(),
+        None => OsString::from("textlint"),
+    }
+}
+
+/// プレーンテキストを lint するための textlint のプラグイン。
+const PLAINTEXT_PLUGIN: &str = "@textlint/textlint-plugin-text";
+
+/// 設定ファイルやルールの指定に関する textlint の引数。
+fn textlint_config_args(options: &RunOptions, file_path: &Path) -> Vec<OsString> {
+    let mut args = Vec::new();
+    if let Some(path) = &options.config_path {
+        args.push(OsString::from("--config"));
+        args.push(path.as_os_str().to_owned());
+    }
+    if options.no_textlintrc {
+        args.push(OsString::from("--no-textlintrc"));
+    }
+    for rule in &options.rules {
+        args.push(OsString::from("--rule"));
+        args.push(OsString::from(rule));
+    }
+    if let Some(dir) = &options.rules_dir {
+        args.push(OsString::from("--rulesdir"));
+        args.push(dir.as_os_str().to_owned());
+    }
+    if let Some(preset) = &options.preset {
+        args.push(OsString::from("--preset"));
+        args.push(OsString::from(preset));
+    }
+    let is_txt = file_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("txt"));
+    if options.force_plaintext_plugin && is_txt {
+        args.push(OsString::from("--plugin"));
+        args.push(OsString::from(PLAINTEXT_PLUGIN));
+    }
+    args
+}
+
+/// textlint の起動に失敗した原因が、実行ファイルが見つからないことか。
+pub fn is_missing_binary(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+/// `work_dir` の `node_modules` にインストールされた textlint の実行ファイル。
+pub fn local_binary(work_dir: &Path) -> Option<PathBuf> {
+    Some(work_dir.join("node_modules/.bin/textlint")).filter(|path| path.exists())
+}
+
+/// `work_dir` の `node_modules` に textlint がインストールされているか。
+pub fn has_local_install(work_dir: &Path) -> bool {
+    local_binary(work_dir).is_some()
+}
+
+/// textlint が探す設定ファイルのうち、JSON として読める可能性のあるもの。探す順に並べる。
+const JSON_CONFIG_FILES: [&str; 2] = [".textlintrc.json", ".textlintrc"];
+
+/// `work_dir` にある JSON 形式 (かもしれない) の textlint の設定ファイル。
+pub fn find_json_config(work_dir: &Path) -> Option<PathBuf> {
+    JSON_CONFIG_FILES
+        .iter()
+        .map(|name| work_dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// JSON の textlint 設定 `config` で `rule_id` を設定しているキーの範囲 (UTF-16 オフセット、
+/// 引用符を含む)。プリセットのルール (`<preset>/<rule>`) は `preset-<preset>` のキーを探す。
+/// JSON として読めない場合や `rules` にキーがない場合は `None`。
+pub fn rule_config_key_range(config: &str, rule_id: &str) -> Option<[usize; 2]> {
+    let config = strip_bom(config);
+    let value: serde_json::Value = serde_json::from_str(config).ok()?;
+    let rules = value.get("rules")?.as_object()?;
+    let key = if rules.contains_key(rule_id) {
+        rule_id.to_string()
+    } else {
+        let (preset, _) = rule_id.split_once('/')?;
+        Some(format!("preset-{preset}")).filter(|key| rules.contains_key(key))?
+    };
+
+    let rules_start = find_json_key(config, "rules", 0)?;
+    let start = find_json_key(config, &key, rules_start)?;
+    let end = start + key.len() + 2;
+    Some([
+        config[..start].encode_utf16().count(),
+        config[..end].encode_utf16().count(),
+    ])
+}
 
-        // textlint は lint エラーがあると exit code 1 を返すが、stdout に JSON が出る
-        let stdout = String::from_utf8(output.stdout)?;
-        if stdout.is_empty() {
-            return Ok(vec![]);
+/// `from` バイト目以降で、オブジェクトのキーとして現れる `"key"` のバイト位置。
+fn find_json_key(text: &str, key: &str, from: usize) -> Option<usize> {
+    let quoted = format!("\"{key}\"");
+    text[from..]
+        .match_indices(&quoted)
+        .map(|(i, _)| from + i)
+        .find(|&i| text[i + quoted.len()..].trim_start().starts_with(':'))
+}
+
+/// `dir` を先頭に加えた `NODE_PATH` の値を返す。既存の `NODE_PATH` は後ろに残す。
+fn node_path(dir: &Path) -> OsString {
+    let existing = std::env::var_os("NODE_PATH").unwrap_or_default();
+    let paths = std::iter::once(dir.to_path_buf()).chain(std::env::split_paths(&existing));
+    std::env::join_paths(paths).unwrap_or_else(|_| dir.as_os_str().to_owned())
+}
+
+/// `dispatch` に従ってコマンドを実行し、`input` を stdin に書き込んで終了まで待つ。
+async fn execute_with_stdin(
+    mut command: Command,
+    dispatch: Dispatch,
+    input: String,
+) -> std::io::Result<Output> {
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    match dispatch {
+        Dispatch::Async => {
+            let mut child = tokio::process::Command::from(command)
+                .kill_on_drop(true)
+                .spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                ignore_broken_pipe(stdin.write_all(input.as_bytes()).await)?;
+            }
+            child.wait_with_output().await
+        }
+        Dispatch::Blocking => tokio::task::spawn_blocking(move || {
+            let mut child = command.spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                ignore_broken_pipe(std::io::Write::write_all(&mut stdin, input.as_bytes()))?;
+            }
+            child.wait_with_output()
+        })
+        .await
+        .map_err(std::io::Error::other)?,
+    }
+}
+
+/// stdin を読まずに終了するコマンドもあるので、書き込みの BrokenPipe は無視する。
+fn ignore_broken_pipe(result: std::io::Result<()>) -> std::io::Result<()> {
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// `dispatch` に従ってコマンドを実行し、終了まで待つ。
+async fn execute(command: Command, dispatch: Dispatch) -> std::io::Result<Output> {
+    match dispatch {
+        // リクエストがキャンセルされて future が破棄されたら、textlint も止める
+        Dispatch::Async => {
+            tokio::process::Command::from(command)
+                .kill_on_drop(true)
+                .output()
+                .await
+        }
+        Dispatch::Blocking => {
+            let mut command = command;
+            tokio::task::spawn_blocking(move || command.output())
+                .await
+                .map_err(std::io::Error::other)?
+        }
+    }
+}
+
+/// textlint の stdout を JSON としてパースする。
+fn parse_output(output: Output) -> anyhow::Result<Vec<TextlintResult>> {
+    // textlint は lint エラーがあると exit code 1 を返すが、stdout に JSON が出る。
+    // 設定の誤りなどで異常終了したときの手がかりは stderr にしか出ない
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = stderr.trim();
+    let stdout = String::from_utf8(output.stdout)?;
+    if stdout.trim().is_empty() {
+        if !output.status.success() {
+            anyhow::bail!("textlint exited with {}: {stderr}", output.status);
         }
-        let results: Vec<TextlintResult> = serde_json::from_str(&stdout)?;
-        Ok(results)
+        return Ok(vec![]);
     }
+    parse_results(&stdout).map_err(|e| {
+        anyhow::Error::new(e).context(format!("failed to parse textlint output: {stderr}"))
+    })
+}
+
+/// textlint の JSON 出力をパースする。
+///
+/// プラグインやシェルのラッパーが JSON の前後に警告を出すことがあるので、全体をパースできなければ
+/// `[` で始まる行から順に、そこから始まる最初の JSON 値として読めるものを探す。後ろの出力は無視する。
+/// どれも読めなければ全体をパースしたときのエラーを返す。
+fn parse_results(stdout: &str) -> serde_json::Result<Vec<TextlintResult>> {
+    let err = match serde_json::from_str(stdout) {
+        Ok(results) => return Ok(results),
+        Err(e) => e,
+    };
+    let mut offset = 0;
+    for line in stdout.split_inclusive('\n') {
+        let start = offset + (line.len() - line.trim_start().len());
+        offset += line.len();
+        if !stdout[start..].starts_with('[') {
+            continue;
+        }
+        let mut values =
+            serde_json::Deserializer::from_str(&stdout[start..]).into_iter::<Vec<TextlintResult>>();
+        if let Some(Ok(results)) = values.next() {
+            return Ok(results);
+        }
+    }
+    Err(err)
 }
 
 /// LSP の Position.character で使うエンコーディング。
@@ -42,15 +483,196 @@ pub enum PositionEncoding {
 }
 
 /// LSP の Position 相当。line / character ともに 0-based。
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Position {
     pub line: u32,
     pub character: u32,
 }
 
+/// LSP の Range 相当。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// textlint が報告するオフセット (fix.range) の単位。
+///
+/// textlint 本体は UTF-16 コードユニットで報告するが、一部のプラグイン構成では
+/// バイト数やコードポイント数で報告されることがある。
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OffsetBasis {
+    #[default]
+    Utf16,
+    /// UTF-8 のバイト数。
+    Bytes,
+    /// Unicode コードポイント数。
+    Codepoints,
+}
+
+/// `basis` 単位のオフセットを UTF-16 コードユニットのオフセットに変換する。
+///
+/// オフセットが文字の途中 (マルチバイト文字のバイト途中) を指す場合は `None`。
+/// 本文の末尾を超える分はそのまま加算し、範囲外であることを呼び出し側で検出できるようにする。
+pub fn offset_to_utf16(text: &str, offset: usize, basis: OffsetBasis) -> Option<usize> {
+    if basis == OffsetBasis::Utf16 {
+        return Some(offset);
+    }
+
+    let mut consumed = 0usize;
+    let mut utf16_count = 0usize;
+    for ch in strip_bom(text).chars() {
+        if consumed >= offset {
+            break;
+        }
+        consumed += match basis {
+            OffsetBasis::Bytes => ch.len_utf8(),
+            OffsetBasis::Codepoints => 1,
+            OffsetBasis::Utf16 => unreachable!(),
+        };
+        utf16_count += ch.len_utf16();
+    }
+
+    if consumed > offset {
+        return None;
+    }
+    Some(utf16_count + (offset - consumed))
+}
+
+/// ルール・位置・メッセージが同じ問題を取り除き、最初のもの (と、その fix) だけを残す。
+/// 複数のプリセットが同じルールを有効にしていると、同じ問題が重複して報告される。
+pub fn dedup_messages(messages: &mut Vec<TextlintMessage>) {
+    let mut seen = std::collections::HashSet::new();
+    messages.retain(|msg| {
+        seen.insert((
+            msg.rule_id.clone(),
+            msg.line,
+            msg.column,
+            msg.message.clone(),
+        ))
+    });
+}
+
+/// メッセージの fix.range と index を `basis` から UTF-16 オフセットに正規化する。
+/// 文字の途中を指していて変換できない fix は適用できないので取り除く。
+pub fn normalize_fix_offsets(text: &str, messages: &mut [TextlintMessage], basis: OffsetBasis) {
+    if basis == OffsetBasis::Utf16 {
+        return;
+    }
+    for msg in messages {
+        msg.index = msg.index.and_then(|i| offset_to_utf16(text, i, basis));
+        let Some(fix) = msg.fix.as_mut() else {
+            continue;
+        };
+        let start = offset_to_utf16(text, fix.range[0], basis);
+        let end = offset_to_utf16(text, fix.range[1], basis);
+        match (start, end) {
+            (Some(start), Some(end)) => fix.range = [start, end],
+            _ => msg.fix = None,
+        }
+    }
+}
+
+/// 先頭の BOM を除いた本文。textlint もエディタも BOM を文字として数えない。
+fn strip_bom(text: &str) -> &str {
+    text.strip_prefix('\u{feff}').unwrap_or(text)
+}
+
+/// 本文の長さ (UTF-16 コードユニット)。先頭の BOM は数えない。
+pub fn utf16_len(text: &str) -> usize {
+    strip_bom(text).encode_utf16().count()
+}
+
+/// 本文の末尾の Position。
+pub fn end_position(text: &str, encoding: PositionEncoding) -> Position {
+    offset_to_position(text, utf16_len(text), encoding)
+}
+
 /// textlint の文字オフセット（UTF-16 コードユニット単位）を
 /// 指定されたエンコーディングの Position に変換する。
+///
+/// 本文の長さを超えるオフセット (編集後の古い fix.range など) は本文の末尾に飽和させる。
+/// 範囲外を検出したい場合は [`checked_offset_to_position`] を使う。
+///
+/// 先頭の BOM はオフセットにも Position にも数えない。CRLF の `\r` と `\n` の間を指す
+/// オフセットは、`\r` の直前 (行末) とみなす。
 pub fn offset_to_position(text: &str, offset: usize, encoding: PositionEncoding) -> Position {
+    walk_to_offset(text, offset, encoding).0
+}
+
+/// [`offset_to_position`] と同じだが、本文の長さを超えるオフセットなら `None` を返す。
+/// 本文の末尾ちょうどは範囲内とする。
+pub fn checked_offset_to_position(
+    text: &str,
+    offset: usize,
+    encoding: PositionEncoding,
+) -> Option<Position> {
+    match walk_to_offset(text, offset, encoding) {
+        (position, true) => Some(position),
+        (_, false) => None,
+    }
+}
+
+/// [`offset_to_position`] の逆変換。`pos` (character は `encoding` の単位) を
+/// textlint の文字オフセット (UTF-16 コードユニット単位) に変換する。
+///
+/// 行末を超える character は行末 (改行の直前。CRLF なら `\r` の直前) に、本文の行数を
+/// 超える line は本文の末尾にクランプする。文字の途中を指す character はその文字の後ろと
+/// みなす。先頭の BOM は数えない。
+pub fn position_to_offset(text: &str, pos: Position, encoding: PositionEncoding) -> usize {
+    walk_to_position(text, pos, encoding).1
+}
+
+/// [`position_to_offset`] と同じ規則で `pos` を本文のバイトオフセットに変換する。
+/// 返す値は常に文字の境界で、先頭に BOM があればその後ろになる。
+pub fn position_to_byte_offset(text: &str, pos: Position, encoding: PositionEncoding) -> usize {
+    walk_to_position(text, pos, encoding).0
+}
+
+/// 本文を先頭から `pos` まで歩いたバイトオフセットと UTF-16 オフセット。
+fn walk_to_position(text: &str, pos: Position, encoding: PositionEncoding) -> (usize, usize) {
+    let bom = text.len() - strip_bom(text).len();
+    let end = || (text.len(), utf16_len(text));
+    let text = strip_bom(text);
+    let mut bytes = bom;
+    let mut utf16 = 0usize;
+    let mut lines = text.split('\n');
+    for _ in 0..pos.line {
+        match lines.next() {
+            Some(line) => {
+                bytes += line.len() + 1;
+                utf16 += line.encode_utf16().count() + 1;
+            }
+            None => return end(),
+        }
+    }
+    let Some(line) = lines.next() else {
+        return end();
+    };
+    let line = line.strip_suffix('\r').unwrap_or(line);
+
+    let target = pos.character as usize;
+    let mut walked = 0usize;
+    for ch in line.chars() {
+        if walked >= target {
+            break;
+        }
+        walked += match encoding {
+            PositionEncoding::Utf8 => ch.len_utf8(),
+            PositionEncoding::Utf16 => ch.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        };
+        bytes += ch.len_utf8();
+        utf16 += ch.len_utf16();
+    }
+    (bytes, utf16)
+}
+
+/// 本文を先頭から `offset` まで歩いた位置と、`offset` に届いたか。
+/// 届かなければ本文の末尾の位置を返す。
+fn walk_to_offset(text: &str, offset: usize, encoding: PositionEncoding) -> (Position, bool) {
+    let text = strip_bom(text);
     let mut line = 0u32;
     let mut utf16_count = 0usize;
     let mut line_start_utf16 = 0usize;
@@ -58,11 +680,11 @@ pub fn offset_to_position(text: &str, offset: usize, encoding: PositionEncoding)
     let mut line_start_chars = 0usize;
     let mut byte_count = 0usize;
     let mut char_count = 0usize;
+    let mut after_cr = false;
 
-    for ch in text.chars() {
-        if utf16_count == offset {
-            break;
-        }
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next_if(|_| utf16_count != offset) {
+        after_cr = ch == '\r';
         let utf16_len = ch.len_utf16();
         let utf8_len = ch.len_utf8();
         if ch == '\n' {
@@ -76,85 +698,372 @@ pub fn offset_to_position(text: &str, offset: usize, encoding: PositionEncoding)
         char_count += 1;
     }
 
-    let character = match encoding {
+    let mut character = match encoding {
         PositionEncoding::Utf8 => (byte_count - line_start_byte) as u32,
         PositionEncoding::Utf16 => (utf16_count - line_start_utf16) as u32,
         PositionEncoding::Utf32 => (char_count - line_start_chars) as u32,
     };
+    // CRLF の `\r` と `\n` の間は、エディタ上では行末 (`\r` の直前) と同じ
+    if after_cr && chars.peek() == Some(&'\n') {
+        character -= 1;
+    }
 
-    Position { line, character }
+    (Position { line, character }, utf16_count >= offset)
 }
 
 /// textlint の column (1-based, UTF-16 コードユニット) を
 /// 指定されたエンコーディングの character offset (0-based) に変換する。
+///
+/// `column_1based` が 0 (仕様上ありえない値) の場合は行頭 (0) とみなす。
+/// 行末を指すルールなどで column が行の長さを超える場合は、行末 (改行の直前。CRLF なら
+/// `\r` の直前) にクランプする。本文の行数を超える line は 0 を返す。先頭の BOM は数えない。
 pub fn textlint_column_to_character(
     text: &str,
     line_0based: u32,
     column_1based: u32,
     encoding: PositionEncoding,
 ) -> u32 {
-    if encoding == PositionEncoding::Utf16 {
-        return column_1based.saturating_sub(1);
+    if column_1based == 0 {
+        return 0;
     }
 
-    // 対象行の先頭バイトインデックスを探す
-    let line_start_byte = if line_0based == 0 {
-        0
-    } else {
-        let mut remaining = line_0based;
-        text.char_indices()
-            .find_map(|(i, ch)| {
-                if ch == '\n' {
-                    remaining -= 1;
-                    if remaining == 0 {
-                        return Some(i + ch.len_utf8());
-                    }
-                }
-                None
-            })
-            .unwrap_or(text.len())
-    };
+    let line_text = strip_bom(text)
+        .split('\n')
+        .nth(line_0based as usize)
+        .unwrap_or("");
+    let line_text = line_text.strip_suffix('\r').unwrap_or(line_text);
 
     // 行先頭から column_1based - 1 個の UTF-16 code units を歩いて
     // 指定エンコーディングでのオフセットを計算する
-    let target_utf16 = column_1based.saturating_sub(1) as usize;
+    let target_utf16 = (column_1based - 1) as usize;
     let mut utf16_walked = 0usize;
     let mut result = 0u32;
 
-    for ch in text[line_start_byte..].chars() {
-        if utf16_walked >= target_utf16 || ch == '\n' {
+    for ch in line_text.chars() {
+        if utf16_walked >= target_utf16 {
             break;
         }
-        match encoding {
-            PositionEncoding::Utf8 => result += ch.len_utf8() as u32,
-            PositionEncoding::Utf32 => result += 1,
-            PositionEncoding::Utf16 => unreachable!(),
-        }
+        result += match encoding {
+            PositionEncoding::Utf8 => ch.len_utf8() as u32,
+            PositionEncoding::Utf16 => ch.len_utf16() as u32,
+            PositionEncoding::Utf32 => 1,
+        };
         utf16_walked += ch.len_utf16();
     }
 
     result
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+/// 本文の行数。末尾が改行なら、その後ろの空行も 1 行と数える (LSP の Position と同じ)。
+pub fn line_count(text: &str) -> u32 {
+    text.split('\n').count() as u32
+}
+
+/// textlint のメッセージが指す位置 (line / column) を Position に変換する。
+///
+/// line / column は 1-based だが、プラグインによっては 0 を報告することがある。
+/// その場合はそれぞれ 0 行目・行頭にクランプする。
+/// 本文の行数を超える line は本文の末尾にクランプする。
+pub fn message_position(text: &str, msg: &TextlintMessage, encoding: PositionEncoding) -> Position {
+    line_column_to_position(text, msg.line, msg.column, encoding)
+}
+
+/// 1-based の line / column (UTF-16 コードユニット) を Position に変換する。
+/// 本文の行数を超える line は本文の末尾にクランプする。
+fn line_column_to_position(
+    text: &str,
+    line_1based: u32,
+    column_1based: u32,
+    encoding: PositionEncoding,
+) -> Position {
+    let line = line_1based.saturating_sub(1);
+    if line >= line_count(text) {
+        return end_position(text, encoding);
+    }
+    let character = textlint_column_to_character(text, line, column_1based, encoding);
+    Position { line, character }
+}
+
+/// fix.range が本文に対して不正である理由。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvalidFixRange {
+    /// start が end より後ろにある。
+    Reversed,
+    /// 本文の末尾を超えている。
+    OutOfBounds,
+    /// サロゲートペアの途中を指している。
+    SplitsSurrogate,
+}
+
+impl std::fmt::Display for InvalidFixRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reversed => f.write_str("range start is after range end"),
+            Self::OutOfBounds => f.write_str("range exceeds the document length"),
+            Self::SplitsSurrogate => f.write_str("range splits a surrogate pair"),
+        }
+    }
+}
+
+/// fix.range (UTF-16 オフセット) が `text` に対して有効かを検証する。
+///
+/// 末尾を超える range は切り詰めずに不正とみなす。バグのあるルールが
+/// `[0, usize::MAX]` のような値を返した場合に、意図しない範囲まで置換しないため。
+pub fn check_fix_range(text: &str, range: [usize; 2]) -> Result<(), InvalidFixRange> {
+    let [start, end] = range;
+    if start > end {
+        return Err(InvalidFixRange::Reversed);
+    }
+    if end > utf16_len(text) {
+        return Err(InvalidFixRange::OutOfBounds);
+    }
+    let text = strip_bom(text);
+    if !is_utf16_boundary(text, start) || !is_utf16_boundary(text, end) {
+        return Err(InvalidFixRange::SplitsSurrogate);
+    }
+    Ok(())
+}
+
+/// `msg` の fix が `text` に対して適用可能かを判定する。
+///
+/// textlint は置換前の文字列を報告しないため、range が本文の範囲内に収まり、
+/// サロゲートペアを分断しないことをもって適用可能とみなす。fix がなければ `false`。
+pub fn fix_applies(text: &str, msg: &TextlintMessage) -> bool {
+    msg.fix
+        .as_ref()
+        .is_some_and(|fix| check_fix_range(text, fix.range).is_ok())
+}
+
+/// 一括適用できる fix を持つメッセージを選ぶ。
+///
+/// 適用可能な fix を開始位置順に並べ、直前に採用した fix と範囲が重なるものは捨てる
+/// (先に始まるものを優先)。範囲がまったく同じ fix は severity の高いもの、同じなら
+/// 先に報告されたものだけを採用する。結果の fix はそのまま LSP の TextEdit 列として使える。
+pub fn non_overlapping_fixes<'a>(
+    text: &str,
+    messages: &'a [TextlintMessage],
+) -> Vec<&'a TextlintMessage> {
+    let mut fixable: Vec<(&TextlintMessage, [usize; 2])> = messages
+        .iter()
+        .filter_map(|msg| msg.fix.as_ref().map(|fix| (msg, fix.range)))
+        .filter(|(_, range)| check_fix_range(text, *range).is_ok())
+        .collect();
+    fixable.sort_by_key(|(msg, range)| (*range, std::cmp::Reverse(msg.severity)));
+
+    let mut selected: Vec<&TextlintMessage> = Vec::new();
+    let mut prev: Option<[usize; 2]> = None;
+    for (msg, range) in fixable {
+        if prev.is_some_and(|prev| range == prev || range[0] < prev[1]) {
+            continue;
+        }
+        selected.push(msg);
+        prev = Some(range);
+    }
+    selected
+}
+
+/// `selected` の fix と同じ範囲を異なるテキストで置き換えようとして捨てられた fix を、
+/// (採用した fix, 捨てた fix) の組で返す。
+pub fn same_range_conflicts<'a>(
+    selected: &[&'a TextlintMessage],
+    messages: &'a [TextlintMessage],
+) -> Vec<(&'a TextlintMessage, &'a TextlintMessage)> {
+    let mut conflicts = Vec::new();
+    for kept in selected {
+        let Some(kept_fix) = kept.fix.as_ref() else {
+            continue;
+        };
+        for msg in messages {
+            if msg
+                .fix
+                .as_ref()
+                .is_some_and(|fix| fix.range == kept_fix.range && fix.text != kept_fix.text)
+            {
+                conflicts.push((*kept, msg));
+            }
+        }
+    }
+    conflicts
+}
+
+/// [`non_overlapping_fixes`] で選んだメッセージの fix を `text` に適用したテキストを返す。
+/// 先頭の BOM は fix.range に数えず、結果にもそのまま残す。
+pub fn apply_fixes(text: &str, fixes: &[&TextlintMessage]) -> String {
+    let body = strip_bom(text);
+    let bom = &text[..text.len() - body.len()];
+    let units: Vec<u16> = body.encode_utf16().collect();
+    let mut result: Vec<u16> = Vec::with_capacity(units.len());
+    let mut cursor = 0;
+    for fix in fixes.iter().filter_map(|msg| msg.fix.as_ref()) {
+        let [start, end] = fix.range;
+        result.extend_from_slice(&units[cursor..start]);
+        result.extend(fix.text.encode_utf16());
+        cursor = end;
+    }
+    result.extend_from_slice(&units[cursor..]);
+    format!("{bom}{}", String::from_utf16_lossy(&result))
+}
+
+/// メッセージに挙がっている、fix 以外の置換候補。
+///
+/// `prh` などの `<対象> => <候補1> / <候補2>` 形式のメッセージだけを読む。誤読を避けるため、
+/// 候補に fix のテキストが含まれない場合は何も返さない。
+pub fn alternative_replacements(msg: &TextlintMessage) -> Vec<String> {
+    let Some(fix) = msg.fix.as_ref() else {
+        return Vec::new();
+    };
+    let Some((_, candidates)) = msg.message.split_once(" => ") else {
+        return Vec::new();
+    };
+    let candidates: Vec<&str> = candidates
+        .split(['/', '|'])
+        .map(str::trim)
+        .filter(|candidate| !candidate.is_empty())
+        .collect();
+    if candidates.len() < 2 || !candidates.contains(&fix.text.as_str()) {
+        return Vec::new();
+    }
+
+    let mut alternatives: Vec<String> = Vec::new();
+    for candidate in candidates {
+        if candidate != fix.text && !alternatives.iter().any(|a| a == candidate) {
+            alternatives.push(candidate.to_string());
+        }
+    }
+    alternatives
+}
+
+/// fix.range が指す本文の部分文字列。範囲が本文に対して不正なら `None`。
+pub fn fix_range_text(text: &str, range: [usize; 2]) -> Option<String> {
+    check_fix_range(text, range).ok()?;
+    let units: Vec<u16> = strip_bom(text).encode_utf16().collect();
+    Some(String::from_utf16_lossy(&units[range[0]..range[1]]))
+}
+
+/// メッセージが指す範囲を Range に変換する。
+///
+/// 範囲の情報源は次の順に使う。
+/// 1. `loc` (start / end の line / column)
+/// 2. `fix.range` (本文に対して正しい場合のみ)
+/// 3. `index`
+/// 4. `line` / `column` ([`message_position`] と同じ)
+///
+/// 3 と 4 は終わりが分からないので、エディタで下線が見えるよう開始位置の 1 文字を範囲とする。
+/// 開始位置が行末なら幅 0 のまま返す。
+pub fn message_range(text: &str, msg: &TextlintMessage, encoding: PositionEncoding) -> Range {
+    if let Some(loc) = &msg.loc {
+        let to_position =
+            |p: &LocPosition| line_column_to_position(text, p.line, p.column, encoding);
+        return Range {
+            start: to_position(&loc.start),
+            end: to_position(&loc.end),
+        };
+    }
+
+    if let Some(fix) = msg
+        .fix
+        .as_ref()
+        .filter(|f| check_fix_range(text, f.range).is_ok())
+    {
+        return Range {
+            start: offset_to_position(text, fix.range[0], encoding),
+            end: offset_to_position(text, fix.range[1], encoding),
+        };
+    }
+
+    let start = match msg.index {
+        // 本文より後ろを指す index は古いので、line / column を使う
+        Some(index) => checked_offset_to_position(text, index, encoding)
+            .unwrap_or_else(|| message_position(text, msg, encoding)),
+        None => message_position(text, msg, encoding),
+    };
+    Range {
+        start,
+        end: one_char_after(text, start, encoding),
+    }
+}
+
+/// `start` にある 1 文字の直後の位置を返す。行末 (改行や本文の末尾) ならそのまま返す。
+fn one_char_after(text: &str, start: Position, encoding: PositionEncoding) -> Position {
+    let Some(line) = strip_bom(text).split('\n').nth(start.line as usize) else {
+        return start;
+    };
+    let mut character = 0u32;
+    for ch in line.chars() {
+        let width = match encoding {
+            PositionEncoding::Utf8 => ch.len_utf8() as u32,
+            PositionEncoding::Utf16 => ch.len_utf16() as u32,
+            PositionEncoding::Utf32 => 1,
+        };
+        if character == start.character {
+            if ch == '\r' {
+                break;
+            }
+            return Position {
+                line: start.line,
+                character: character + width,
+            };
+        }
+        if character > start.character {
+            break;
+        }
+        character += width;
+    }
+    start
+}
+
+/// `offset` (UTF-16 コードユニット) が本文中の文字境界 (末尾を含む) にあるか。
+fn is_utf16_boundary(text: &str, offset: usize) -> bool {
+    let mut utf16_count = 0usize;
+    for ch in text.chars() {
+        if utf16_count >= offset {
+            break;
+        }
+        utf16_count += ch.len_utf16();
+    }
+    utf16_count == offset
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TextlintResult {
     #[serde(rename = "filePath")]
     pub file_path: String,
     pub messages: Vec<TextlintMessage>,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TextlintMessage {
     #[serde(rename = "ruleId")]
     pub rule_id: String,
     pub message: String,
     pub line: u32,
     pub column: u32,
+    /// 本文先頭からのオフセット (0-based, UTF-16 コードユニット)。古い textlint は報告しない。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    /// 問題の範囲。古い textlint は報告しない。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loc: Option<MessageLoc>,
     pub severity: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub fix: Option<FixCommand>,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+/// textlint のメッセージの `loc`。
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MessageLoc {
+    pub start: LocPosition,
+    pub end: LocPosition,
+}
+
+/// `loc` の位置。line / column ともに 1-based (column は UTF-16 コードユニット)。
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LocPosition {
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FixCommand {
     pub range: [usize; 2],
     pub text: String,
@@ -164,6 +1073,70 @@ pub struct FixCommand {
 mod tests {
     use super::*;
 
+    /// fix_stdin で決まったテキストを返すランナー。
+    struct FixedTextRunner {
+        fixed: String,
+        file_path: std::sync::Mutex<Option<PathBuf>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TextlintRunner for FixedTextRunner {
+        async fn run(
+            &self,
+            _file_path: &Path,
+            _work_dir: &Path,
+            _options: &RunOptions,
+        ) -> anyhow::Result<Vec<TextlintResult>> {
+            unreachable!("fix_all_text should not lint")
+        }
+
+        async fn run_stdin(
+            &self,
+            _text: &str,
+            _file_path: &Path,
+            _work_dir: &Path,
+            _options: &RunOptions,
+        ) -> anyhow::Result<Vec<TextlintResult>> {
+            unreachable!("fix_all_text should not lint")
+        }
+
+        async fn fix_stdin(
+            &self,
+            _text: &str,
+            file_path: &Path,
+            _work_dir: &Path,
+            _options: &RunOptions,
+        ) -> anyhow::Result<String> {
+            *self.file_path.lock().unwrap() = Some(file_path.to_path_buf());
+            Ok(self.fixed.clone())
+        }
+
+        async fn warmup(&self, _work_dir: &Path, _options: &RunOptions) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn version(&self, _work_dir: &Path, _options: &RunOptions) -> anyhow::Result<String> {
+            Ok("14.0.0".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn fix_all_text_returns_fixed_text() {
+        let runner = FixedTextRunner {
+            fixed: "2つの項目がある。".to_string(),
+            file_path: std::sync::Mutex::new(None),
+        };
+        let fixed = fix_all_text(&runner, "ふたつの項目がある。", "md", Path::new("/work"))
+            .await
+            .unwrap();
+
+        assert_eq!(fixed, "2つの項目がある。");
+        assert_eq!(
+            *runner.file_path.lock().unwrap(),
+            Some(PathBuf::from("/work/stdin.md"))
+        );
+    }
+
     #[test]
     fn deserialize_single_message_with_fix() {
         let json = r#"[
@@ -393,26 +1366,382 @@ mod tests {
     }
 
     #[test]
-    fn textlint_column_to_character_utf16() {
-        let text = "あいう";
-        // column 2 (1-based) → character 1 (0-based) in UTF-16
-        assert_eq!(
-            textlint_column_to_character(text, 0, 2, PositionEncoding::Utf16),
-            1
-        );
+    fn position_to_offset_round_trips() {
+        for text in ["abc\n\ndef", "あいう\n\nかき\r\nく", "a𠮷b\n𠮷\n"] {
+            let len = text.encode_utf16().count();
+            for encoding in [
+                PositionEncoding::Utf8,
+                PositionEncoding::Utf16,
+                PositionEncoding::Utf32,
+            ] {
+                for offset in 0..=len {
+                    // サロゲートペアの途中は Position で表せないので除く。
+                    // CRLF の `\r` と `\n` の間は `\r` の直前と同じ Position になる
+                    let between_crlf = offset > 0
+                        && text.encode_utf16().nth(offset - 1) == Some(u16::from(b'\r'))
+                        && text.encode_utf16().nth(offset) == Some(u16::from(b'\n'));
+                    if !is_utf16_boundary(text, offset) || between_crlf {
+                        continue;
+                    }
+                    let pos = offset_to_position(text, offset, encoding);
+                    assert_eq!(
+                        position_to_offset(text, pos, encoding),
+                        offset,
+                        "text={text:?} offset={offset} encoding={encoding:?}"
+                    );
+                }
+            }
+        }
     }
 
     #[test]
-    fn textlint_column_to_character_utf8() {
-        let text = "あいう";
-        // column 2 (1-based) → 'い' is at byte offset 3
+    fn position_to_offset_clamps_out_of_range_positions() {
+        let text = "あいう\nab";
+        let at = |line, character| Position { line, character };
+        // 行末を超える character は行末
         assert_eq!(
-            textlint_column_to_character(text, 0, 2, PositionEncoding::Utf8),
+            position_to_offset(text, at(0, 100), PositionEncoding::Utf8),
             3
         );
-    }
-
-    #[test]
+        // 空行と、本文より後ろの行
+        assert_eq!(
+            position_to_offset("a\n\nb", at(1, 5), PositionEncoding::Utf16),
+            2
+        );
+        assert_eq!(
+            position_to_offset(text, at(9, 0), PositionEncoding::Utf16),
+            6
+        );
+        // 文字の途中 (UTF-8 で 'あ' の 2 バイト目) は文字の後ろ
+        assert_eq!(
+            position_to_offset(text, at(0, 2), PositionEncoding::Utf8),
+            1
+        );
+    }
+
+    #[test]
+    fn position_to_byte_offset_lands_on_char_boundaries() {
+        let text = "あ𠮷\nab";
+        let at = |line, character| Position { line, character };
+        assert_eq!(
+            position_to_byte_offset(text, at(0, 1), PositionEncoding::Utf16),
+            3
+        );
+        assert_eq!(
+            position_to_byte_offset(text, at(0, 3), PositionEncoding::Utf16),
+            7
+        );
+        // 文字の途中 (UTF-8 で 'あ' の 2 バイト目) は文字の後ろ
+        assert_eq!(
+            position_to_byte_offset(text, at(0, 2), PositionEncoding::Utf8),
+            3
+        );
+        assert_eq!(
+            position_to_byte_offset(text, at(1, 1), PositionEncoding::Utf32),
+            9
+        );
+        assert_eq!(
+            position_to_byte_offset(text, at(5, 0), PositionEncoding::Utf32),
+            text.len()
+        );
+    }
+
+    #[test]
+    fn crlf_is_not_counted_as_a_visible_column() {
+        let text = "ab\r\ncd";
+        let at = |line, character| Position { line, character };
+        // `\r` の直前と、`\r` と `\n` の間はどちらも 0 行目の行末
+        assert_eq!(
+            offset_to_position(text, 2, PositionEncoding::Utf16),
+            at(0, 2)
+        );
+        assert_eq!(
+            offset_to_position(text, 3, PositionEncoding::Utf16),
+            at(0, 2)
+        );
+        assert_eq!(
+            offset_to_position(text, 4, PositionEncoding::Utf8),
+            at(1, 0)
+        );
+        assert_eq!(
+            position_to_offset(text, at(0, 9), PositionEncoding::Utf16),
+            2
+        );
+        assert_eq!(
+            position_to_offset(text, at(1, 1), PositionEncoding::Utf16),
+            5
+        );
+        assert_eq!(
+            textlint_column_to_character(text, 0, 9, PositionEncoding::Utf16),
+            2
+        );
+    }
+
+    #[test]
+    fn leading_bom_is_not_counted() {
+        let text = "\u{feff}あい\r\nう";
+        let at = |line, character| Position { line, character };
+        // textlint のオフセットもエディタの Position も BOM を数えない
+        assert_eq!(
+            offset_to_position(text, 1, PositionEncoding::Utf16),
+            at(0, 1)
+        );
+        assert_eq!(
+            offset_to_position(text, 1, PositionEncoding::Utf8),
+            at(0, 3)
+        );
+        assert_eq!(
+            offset_to_position(text, 4, PositionEncoding::Utf16),
+            at(1, 0)
+        );
+        assert_eq!(
+            position_to_offset(text, at(0, 1), PositionEncoding::Utf16),
+            1
+        );
+        assert_eq!(
+            textlint_column_to_character(text, 0, 2, PositionEncoding::Utf16),
+            1
+        );
+        // バイトオフセットは BOM (3 バイト) の後ろから数える
+        assert_eq!(
+            position_to_byte_offset(text, at(0, 0), PositionEncoding::Utf16),
+            3
+        );
+        assert_eq!(
+            position_to_byte_offset(text, at(0, 1), PositionEncoding::Utf16),
+            6
+        );
+        assert_eq!(check_fix_range(text, [0, 5]), Ok(()));
+        assert_eq!(
+            check_fix_range(text, [0, 6]),
+            Err(InvalidFixRange::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn offset_to_position_at_and_beyond_text_end() {
+        for (text, end_utf8, end_utf16) in [("ab\ncd", 2, 2), ("あい\nう𠮷", 7, 3)] {
+            let len = text.encode_utf16().count();
+            for (encoding, character) in [
+                (PositionEncoding::Utf8, end_utf8),
+                (PositionEncoding::Utf16, end_utf16),
+            ] {
+                let end = Position { line: 1, character };
+                // 末尾ちょうどは範囲内
+                assert_eq!(offset_to_position(text, len, encoding), end);
+                assert_eq!(checked_offset_to_position(text, len, encoding), Some(end));
+                // 末尾より後ろは末尾に飽和し、checked では検出できる
+                assert_eq!(offset_to_position(text, len + 5, encoding), end);
+                assert_eq!(checked_offset_to_position(text, len + 1, encoding), None);
+            }
+        }
+    }
+
+    #[test]
+    fn message_range_ignores_index_beyond_text() {
+        let mut msg = message_with_fix_range([0, 3]);
+        msg.fix = None;
+        msg.index = Some(100);
+        msg.column = 2;
+        let range = message_range("ふたつ", &msg, PositionEncoding::Utf16);
+        assert_eq!(
+            range.start,
+            Position {
+                line: 0,
+                character: 1
+            }
+        );
+    }
+
+    #[test]
+    fn is_missing_binary_detects_not_found() {
+        let err = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert!(is_missing_binary(&err));
+        let err = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert!(!is_missing_binary(&err));
+        assert!(!is_missing_binary(&anyhow::anyhow!("textlint failed")));
+    }
+
+    #[test]
+    fn offset_to_utf16_converts_bytes_and_codepoints() {
+        // 'あ' = 3 bytes / 1 code unit, '𠮷' = 4 bytes / 2 code units
+        let text = "あ𠮷い";
+        assert_eq!(offset_to_utf16(text, 3, OffsetBasis::Bytes), Some(1));
+        assert_eq!(offset_to_utf16(text, 7, OffsetBasis::Bytes), Some(3));
+        assert_eq!(offset_to_utf16(text, 2, OffsetBasis::Codepoints), Some(3));
+        assert_eq!(offset_to_utf16(text, 3, OffsetBasis::Utf16), Some(3));
+        // バイト途中は変換できない
+        assert_eq!(offset_to_utf16(text, 1, OffsetBasis::Bytes), None);
+        // 末尾を超える分はそのまま残る
+        assert_eq!(offset_to_utf16(text, 12, OffsetBasis::Bytes), Some(6));
+    }
+
+    #[test]
+    fn byte_based_fix_offsets_map_to_correct_positions() {
+        let text = "あいう\nふたつの項目";
+        // "ふたつ" はバイト単位で [10, 19)
+        let mut messages = vec![message_with_fix_range([10, 19])];
+        normalize_fix_offsets(text, &mut messages, OffsetBasis::Bytes);
+
+        let fix = messages[0].fix.as_ref().unwrap();
+        assert_eq!(fix.range, [4, 7]);
+        let start = offset_to_position(text, fix.range[0], PositionEncoding::Utf16);
+        let end = offset_to_position(text, fix.range[1], PositionEncoding::Utf16);
+        assert_eq!(
+            start,
+            Position {
+                line: 1,
+                character: 0
+            }
+        );
+        assert_eq!(
+            end,
+            Position {
+                line: 1,
+                character: 3
+            }
+        );
+        let end = offset_to_position(text, fix.range[1], PositionEncoding::Utf8);
+        assert_eq!(end.character, 9);
+    }
+
+    #[test]
+    fn normalize_fix_offsets_drops_unmappable_fix() {
+        let text = "ふたつ";
+        let mut messages = vec![message_with_fix_range([1, 3])];
+        normalize_fix_offsets(text, &mut messages, OffsetBasis::Bytes);
+        assert!(messages[0].fix.is_none());
+    }
+
+    fn pos(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    #[test]
+    fn deserialize_message_with_loc_and_index() {
+        let json = r#"{
+            "ruleId": "prh",
+            "message": "ふたつ => 2つ",
+            "line": 2,
+            "column": 1,
+            "index": 4,
+            "loc": {
+                "start": { "line": 2, "column": 1 },
+                "end": { "line": 2, "column": 4 }
+            },
+            "severity": 2
+        }"#;
+        let msg: TextlintMessage = serde_json::from_str(json).unwrap();
+        assert_eq!(msg.index, Some(4));
+        let loc = msg.loc.unwrap();
+        assert_eq!(loc.start, LocPosition { line: 2, column: 1 });
+        assert_eq!(loc.end, LocPosition { line: 2, column: 4 });
+    }
+
+    #[test]
+    fn line_beyond_document_is_clamped_to_end() {
+        let text = "あいう\nかき";
+        assert_eq!(line_count(text), 2);
+        assert_eq!(line_count("a\n"), 2);
+
+        let mut msg = message_with_fix_range([0, 0]);
+        msg.fix = None;
+        msg.line = 5;
+        msg.column = 3;
+        for (encoding, character) in [
+            (PositionEncoding::Utf8, 6),
+            (PositionEncoding::Utf16, 2),
+            (PositionEncoding::Utf32, 2),
+        ] {
+            let range = message_range(text, &msg, encoding);
+            assert_eq!(range.start, pos(1, character), "enc={encoding:?}");
+            assert_eq!(range.end, pos(1, character), "enc={encoding:?}");
+        }
+    }
+
+    #[test]
+    fn message_range_prefers_loc() {
+        let text = "あいう\nふたつの項目";
+        let mut msg = message_with_fix_range([0, 1]);
+        msg.index = Some(0);
+        msg.loc = Some(MessageLoc {
+            start: LocPosition { line: 2, column: 1 },
+            end: LocPosition { line: 2, column: 4 },
+        });
+
+        let range = message_range(text, &msg, PositionEncoding::Utf8);
+        assert_eq!(range.start, pos(1, 0));
+        assert_eq!(range.end, pos(1, 9));
+    }
+
+    #[test]
+    fn message_range_falls_back_to_fix_range() {
+        let text = "あいう\nふたつの項目";
+        let mut msg = message_with_fix_range([4, 7]);
+        msg.index = Some(0);
+
+        let range = message_range(text, &msg, PositionEncoding::Utf16);
+        assert_eq!(range.start, pos(1, 0));
+        assert_eq!(range.end, pos(1, 3));
+    }
+
+    #[test]
+    fn message_range_falls_back_to_index_when_fix_is_invalid() {
+        let text = "あいう\nふたつの項目";
+        let mut msg = message_with_fix_range([7, 4]);
+        msg.index = Some(5);
+
+        let range = message_range(text, &msg, PositionEncoding::Utf16);
+        assert_eq!(range.start, pos(1, 1));
+        assert_eq!(range.end, pos(1, 2));
+    }
+
+    #[test]
+    fn message_range_falls_back_to_line_and_column() {
+        let text = "あいう\nふたつの項目";
+        let mut msg = message_with_fix_range([0, 0]);
+        msg.fix = None;
+        msg.line = 2;
+        msg.column = 2;
+
+        let range = message_range(text, &msg, PositionEncoding::Utf8);
+        assert_eq!(range.start, pos(1, 3));
+        assert_eq!(range.end, pos(1, 6));
+    }
+
+    #[test]
+    fn message_range_without_fix_stays_empty_at_line_end() {
+        let text = "ab\r\ncd";
+        let mut msg = message_with_fix_range([0, 0]);
+        msg.fix = None;
+        msg.column = 3;
+
+        let range = message_range(text, &msg, PositionEncoding::Utf16);
+        assert_eq!(range.start, pos(0, 2));
+        assert_eq!(range.end, pos(0, 2));
+    }
+
+    #[test]
+    fn textlint_column_to_character_utf16() {
+        let text = "あいう";
+        // column 2 (1-based) → character 1 (0-based) in UTF-16
+        assert_eq!(
+            textlint_column_to_character(text, 0, 2, PositionEncoding::Utf16),
+            1
+        );
+    }
+
+    #[test]
+    fn textlint_column_to_character_utf8() {
+        let text = "あいう";
+        // column 2 (1-based) → 'い' is at byte offset 3
+        assert_eq!(
+            textlint_column_to_character(text, 0, 2, PositionEncoding::Utf8),
+            3
+        );
+    }
+
+    #[test]
     fn textlint_column_to_character_utf32() {
         let text = "あいう";
         // column 2 (1-based) → code point 1
@@ -439,4 +1768,606 @@ mod tests {
             1
         );
     }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dispatch_modes_produce_same_results() {
+        let json = r#"[{"filePath": "./a.md", "messages": [{"ruleId": "prh", "message": "ふたつ => 2つ", "line": 3, "column": 1, "severity": 2, "fix": {"range": [6, 9], "text": "2つ"}}]}]"#;
+
+        let mut results = Vec::new();
+        for dispatch in [Dispatch::Async, Dispatch::Blocking] {
+            // textlint の代わりに JSON を出力するだけのコマンドを使う
+            let mut command = Command::new("echo");
+            command.arg(json);
+            let output = execute(command, dispatch).await.unwrap();
+            results.push(parse_output(output).unwrap());
+        }
+
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0][0].messages[0].rule_id, "prh");
+    }
+
+    #[test]
+    fn textlint_program_uses_configured_binary() {
+        let mut options = RunOptions::default();
+        assert_eq!(textlint_program(&options), "textlint");
+
+        options.binary = Some(PathBuf::from("/work/node_modules/.bin/textlint"));
+        let command = CommandRunner.command(Path::new("a.md"), Path::new("/work"), &options);
+        assert_eq!(command.get_program(), "/work/node_modules/.bin/textlint");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn parse_error_includes_stderr() {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "echo 'not json'; echo 'Error: .textlintrc is broken' >&2; exit 2",
+        ]);
+        let output = execute(command, Dispatch::Async).await.unwrap();
+
+        let err = parse_output(output).unwrap_err();
+        assert!(format!("{err:#}").contains(".textlintrc is broken"));
+        assert!(err.downcast_ref::<serde_json::Error>().is_some());
+    }
+
+    #[test]
+    fn parse_results_reads_clean_json() {
+        let json = r#"[{"filePath": "./a.md", "messages": [{"ruleId": "prh", "message": "[a] => b", "line": 1, "column": 1, "severity": 2}]}]"#;
+        let results = parse_results(json).unwrap();
+        assert_eq!(results[0].messages[0].message, "[a] => b");
+    }
+
+    #[test]
+    fn parse_results_skips_surrounding_noise() {
+        let output = concat!(
+            "[WARN] plugin \"foo\" is deprecated\n",
+            r#"[{"filePath": "./a.md", "messages": [{"ruleId": "prh", "message": "[a] => b", "line": 1, "column": 1, "severity": 2}]}]"#,
+            "\nDone in 0.3s [ok]\n",
+        );
+        let results = parse_results(output).unwrap();
+        assert_eq!(results[0].file_path, "./a.md");
+        assert_eq!(results[0].messages[0].message, "[a] => b");
+    }
+
+    #[test]
+    fn parse_results_rejects_invalid_output() {
+        assert!(parse_results("not json").is_err());
+        assert!(parse_results("[WARN] something\n[{\"filePath\": ").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn empty_output_with_failure_status_includes_stderr() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo 'Cannot find module' >&2; exit 2"]);
+        let output = execute(command, Dispatch::Async).await.unwrap();
+
+        let err = parse_output(output).unwrap_err();
+        assert!(err.to_string().contains("Cannot find module"));
+    }
+
+    fn message_with_fix_range(range: [usize; 2]) -> TextlintMessage {
+        TextlintMessage {
+            rule_id: "prh".to_string(),
+            message: "ふたつ => 2つ".to_string(),
+            line: 1,
+            column: 1,
+            index: None,
+            loc: None,
+            severity: 2,
+            fix: Some(FixCommand {
+                range,
+                text: "2つ".to_string(),
+            }),
+        }
+    }
+
+    #[test]
+    fn alternative_replacements_reads_listed_candidates() {
+        let mut msg = message_with_fix_range([0, 3]);
+        msg.message = "ふたつ => 2つ / 二つ | 二つ".to_string();
+        assert_eq!(alternative_replacements(&msg), ["二つ"]);
+    }
+
+    #[test]
+    fn alternative_replacements_ignores_unrelated_messages() {
+        // 候補が 1 つだけ
+        let msg = message_with_fix_range([0, 3]);
+        assert!(alternative_replacements(&msg).is_empty());
+
+        // fix のテキストが候補にない
+        let mut msg = message_with_fix_range([0, 3]);
+        msg.message = "ふたつ => A/B テスト".to_string();
+        assert!(alternative_replacements(&msg).is_empty());
+
+        // fix がない
+        let mut msg = message_with_fix_range([0, 3]);
+        msg.message = "ふたつ => 2つ / 二つ".to_string();
+        msg.fix = None;
+        assert!(alternative_replacements(&msg).is_empty());
+    }
+
+    #[test]
+    fn rule_config_key_range_finds_rule_and_preset_keys() {
+        let config = r#"{
+  "filters": { "prh": true },
+  "rules": {
+    "prh": { "rulePaths": ["dict.yml"] },
+    "preset-ja-technical-writing": true
+  }
+}"#;
+        let range = rule_config_key_range(config, "prh").unwrap();
+        let units: Vec<u16> = config.encode_utf16().collect();
+        assert_eq!(
+            String::from_utf16_lossy(&units[range[0]..range[1]]),
+            "\"prh\""
+        );
+        assert_eq!(
+            offset_to_position(config, range[0], PositionEncoding::Utf16).line,
+            3
+        );
+
+        let range =
+            rule_config_key_range(config, "ja-technical-writing/no-exclamation-question-mark")
+                .unwrap();
+        assert_eq!(
+            offset_to_position(config, range[0], PositionEncoding::Utf16).line,
+            4
+        );
+    }
+
+    #[test]
+    fn rule_config_key_range_skips_unknown_rules_and_non_json() {
+        let config = r#"{ "rules": { "prh": true } }"#;
+        assert_eq!(rule_config_key_range(config, "no-doubled-joshi"), None);
+        assert_eq!(rule_config_key_range("rules:\n  prh: true\n", "prh"), None);
+    }
+
+    #[test]
+    fn fix_applies_in_bounds() {
+        let text = "ふたつの項目";
+        assert!(fix_applies(text, &message_with_fix_range([0, 3])));
+        // 末尾ちょうどまでの range も有効
+        assert!(fix_applies(text, &message_with_fix_range([3, 6])));
+        assert!(fix_applies(text, &message_with_fix_range([6, 6])));
+    }
+
+    #[test]
+    fn fix_applies_out_of_bounds() {
+        let text = "ふたつの項目";
+        assert!(!fix_applies(text, &message_with_fix_range([3, 7])));
+        assert!(!fix_applies(text, &message_with_fix_range([10, 12])));
+        assert!(!fix_applies(text, &message_with_fix_range([3, 1])));
+    }
+
+    #[test]
+    fn fix_applies_rejects_surrogate_split() {
+        // '𠮷' は UTF-16 で 2 コードユニット (offset 1..3)
+        let text = "a𠮷b";
+        assert!(fix_applies(text, &message_with_fix_range([1, 3])));
+        assert!(!fix_applies(text, &message_with_fix_range([2, 3])));
+        assert!(!fix_applies(text, &message_with_fix_range([1, 2])));
+    }
+
+    #[test]
+    fn check_fix_range_reports_reason() {
+        let text = "a𠮷b";
+        assert_eq!(check_fix_range(text, [0, 4]), Ok(()));
+        assert_eq!(
+            check_fix_range(text, [3, 1]),
+            Err(InvalidFixRange::Reversed)
+        );
+        assert_eq!(
+            check_fix_range(text, [0, usize::MAX]),
+            Err(InvalidFixRange::OutOfBounds)
+        );
+        assert_eq!(
+            check_fix_range(text, [usize::MAX, usize::MAX]),
+            Err(InvalidFixRange::OutOfBounds)
+        );
+        assert_eq!(
+            check_fix_range(text, [2, 3]),
+            Err(InvalidFixRange::SplitsSurrogate)
+        );
+    }
+
+    #[test]
+    fn non_overlapping_fixes_drops_overlaps() {
+        let text = "ふたつの項目がある。";
+        let messages = vec![
+            message_with_fix_range([4, 6]),
+            message_with_fix_range([0, 3]),
+            // [0, 3] と重なるので捨てる
+            message_with_fix_range([2, 5]),
+            // 範囲外なので捨てる
+            message_with_fix_range([8, 20]),
+        ];
+        let ranges: Vec<[usize; 2]> = non_overlapping_fixes(text, &messages)
+            .iter()
+            .map(|msg| msg.fix.as_ref().unwrap().range)
+            .collect();
+        assert_eq!(ranges, vec![[0, 3], [4, 6]]);
+    }
+
+    #[test]
+    fn non_overlapping_fixes_keeps_one_of_same_range_fixes() {
+        let text = "ふたつの項目がある。";
+        let with_text = |range: [usize; 2], severity: u32, replacement: &str| {
+            let mut msg = message_with_fix_range(range);
+            msg.severity = severity;
+            msg.fix.as_mut().unwrap().text = replacement.to_string();
+            msg
+        };
+        let messages = vec![
+            with_text([0, 3], 1, "二つ"),
+            with_text([0, 3], 2, "2つ"),
+            with_text([0, 3], 2, "2個"),
+            // 幅 0 の挿入も同じ位置なら 1 つだけ
+            with_text([4, 4], 2, "A"),
+            with_text([4, 4], 2, "B"),
+        ];
+
+        let selected = non_overlapping_fixes(text, &messages);
+        let texts: Vec<&str> = selected
+            .iter()
+            .map(|msg| msg.fix.as_ref().unwrap().text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["2つ", "A"]);
+
+        let conflicts: Vec<(&str, &str)> = same_range_conflicts(&selected, &messages)
+            .into_iter()
+            .map(|(kept, dropped)| {
+                (
+                    kept.fix.as_ref().unwrap().text.as_str(),
+                    dropped.fix.as_ref().unwrap().text.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(conflicts, vec![("2つ", "二つ"), ("2つ", "2個"), ("A", "B")]);
+    }
+
+    #[test]
+    fn apply_fixes_replaces_ranges() {
+        let text = "ふたつの𠮷がある";
+        let first = message_with_fix_range([0, 3]);
+        let mut second = message_with_fix_range([4, 6]);
+        second.fix.as_mut().unwrap().text = "吉".to_string();
+        assert_eq!(apply_fixes(text, &[&first, &second]), "2つの吉がある");
+        assert_eq!(apply_fixes(text, &[]), text);
+        let with_bom = "\u{feff}ふたつの𠮷がある";
+        assert_eq!(
+            apply_fixes(with_bom, &[&first, &second]),
+            "\u{feff}2つの吉がある"
+        );
+    }
+
+    #[test]
+    fn fix_range_text_slices_utf16_range() {
+        let text = "ふたつの𠮷がある";
+        assert_eq!(fix_range_text(text, [0, 3]).as_deref(), Some("ふたつ"));
+        assert_eq!(fix_range_text(text, [4, 6]).as_deref(), Some("𠮷"));
+        assert_eq!(fix_range_text(text, [4, 5]), None);
+        assert_eq!(fix_range_text(text, [3, 100]), None);
+        let with_bom = "\u{feff}ふたつの𠮷がある";
+        assert_eq!(fix_range_text(with_bom, [0, 3]).as_deref(), Some("ふたつ"));
+        assert_eq!(fix_range_text(with_bom, [4, 6]).as_deref(), Some("𠮷"));
+        assert_eq!(fix_range_text(with_bom, [5, 7]), None);
+    }
+
+    #[test]
+    fn end_position_skips_bom() {
+        let text = "\u{feff}ふたつ\n𠮷";
+        assert_eq!(utf16_len(text), 6);
+        assert_eq!(
+            end_position(text, PositionEncoding::Utf16),
+            Position {
+                line: 1,
+                character: 2
+            }
+        );
+    }
+
+    #[test]
+    fn fix_applies_without_fix() {
+        let mut msg = message_with_fix_range([0, 1]);
+        msg.fix = None;
+        assert!(!fix_applies("text", &msg));
+    }
+
+    #[test]
+    fn command_sets_node_path_for_modules_dir() {
+        let options = RunOptions {
+            modules_dir: Some(PathBuf::from("/opt/textlint/node_modules")),
+            ..Default::default()
+        };
+        let command = CommandRunner.command(Path::new("a.md"), Path::new("/work"), &options);
+
+        let node_path = command
+            .get_envs()
+            .find(|(key, _)| *key == "NODE_PATH")
+            .and_then(|(_, value)| value)
+            .expect("NODE_PATH should be set");
+        let first = std::env::split_paths(node_path).next().unwrap();
+        assert_eq!(first, PathBuf::from("/opt/textlint/node_modules"));
+    }
+
+    #[test]
+    fn command_uses_configured_command_with_file_placeholder() {
+        let options = RunOptions {
+            command: Some(vec![
+                "my-linter".to_string(),
+                "--json".to_string(),
+                "{file}".to_string(),
+            ]),
+            ..Default::default()
+        };
+        let command = CommandRunner.command(Path::new("a.tex"), Path::new("/work"), &options);
+
+        assert_eq!(command.get_program(), "my-linter");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args, ["--json", "a.tex"]);
+        assert_eq!(command.get_current_dir(), Some(Path::new("/work")));
+    }
+
+    #[test]
+    fn command_omits_config_without_config_path() {
+        let command = CommandRunner.command(
+            Path::new("a.md"),
+            Path::new("/work"),
+            &RunOptions::default(),
+        );
+
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args, ["--format", "json", "a.md"]);
+    }
+
+    #[test]
+    fn command_passes_config_path() {
+        let options = RunOptions {
+            config_path: Some(PathBuf::from("/work/.textlintrc.ja.json")),
+            ..Default::default()
+        };
+        let command = CommandRunner.command(Path::new("a.md"), Path::new("/work"), &options);
+
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--config",
+                "/work/.textlintrc.ja.json",
+                "--format",
+                "json",
+                "a.md"
+            ]
+        );
+    }
+
+    #[test]
+    fn command_bypasses_textlintrc_with_explicit_rules() {
+        let options = RunOptions {
+            no_textlintrc: true,
+            rules: vec!["preset-ja-technical-writing".to_string(), "prh".to_string()],
+            ..Default::default()
+        };
+        let command = CommandRunner.command(Path::new("a.md"), Path::new("/work"), &options);
+
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--no-textlintrc",
+                "--rule",
+                "preset-ja-technical-writing",
+                "--rule",
+                "prh",
+                "--format",
+                "json",
+                "a.md"
+            ]
+        );
+
+        let command = CommandRunner.command(
+            Path::new("a.md"),
+            Path::new("/work"),
+            &RunOptions::default(),
+        );
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args, ["--format", "json", "a.md"]);
+    }
+
+    #[test]
+    fn command_passes_rules_dir_and_preset() {
+        let options = RunOptions {
+            rules_dir: Some(PathBuf::from("/work/rules")),
+            preset: Some("ja-technical-writing".to_string()),
+            ..Default::default()
+        };
+        let command = CommandRunner.command(Path::new("a.md"), Path::new("/work"), &options);
+
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--rulesdir",
+                "/work/rules",
+                "--preset",
+                "ja-technical-writing",
+                "--format",
+                "json",
+                "a.md"
+            ]
+        );
+    }
+
+    #[test]
+    fn plaintext_plugin_is_forced_only_for_txt() {
+        let options = RunOptions {
+            force_plaintext_plugin: true,
+            ..Default::default()
+        };
+        let args = |file: &str, options: &RunOptions| {
+            let command = CommandRunner.command(Path::new(file), Path::new("/work"), options);
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            args("a.TXT", &options),
+            [
+                "--plugin",
+                "@textlint/textlint-plugin-text",
+                "--format",
+                "json",
+                "a.TXT"
+            ]
+        );
+        assert_eq!(args("a.md", &options), ["--format", "json", "a.md"]);
+        assert_eq!(
+            args("a.txt", &RunOptions::default()),
+            ["--format", "json", "a.txt"]
+        );
+    }
+
+    #[test]
+    fn command_is_prefixed_with_wrapper() {
+        let options = RunOptions {
+            wrapper: vec!["with-env".to_string(), "--profile=ci".to_string()],
+            ..Default::default()
+        };
+        let command = CommandRunner.command(Path::new("a b.md"), Path::new("/work"), &options);
+
+        assert_eq!(command.get_program(), "with-env");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            ["--profile=ci", "textlint", "--format", "json", "a b.md"]
+        );
+    }
+
+    #[test]
+    fn command_runs_through_shell_with_quoted_arguments() {
+        let options = RunOptions {
+            wrapper: vec!["with-env".to_string()],
+            shell: vec!["bash".to_string(), "-lc".to_string()],
+            ..Default::default()
+        };
+        let command =
+            CommandRunner.command(Path::new("my docs/it's.md"), Path::new("/work"), &options);
+
+        assert_eq!(command.get_program(), "bash");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "-lc",
+                r#"with-env textlint --format json 'my docs/it'\''s.md'"#
+            ]
+        );
+        assert_eq!(command.get_current_dir(), Some(Path::new("/work")));
+    }
+
+    #[test]
+    fn command_leaves_env_untouched_without_modules_dir() {
+        let command = CommandRunner.command(
+            Path::new("a.md"),
+            Path::new("/work"),
+            &RunOptions::default(),
+        );
+        assert_eq!(command.get_envs().count(), 0);
+    }
+
+    #[test]
+    fn textlint_column_to_character_clamps_to_line_end() {
+        let text = "あいう\r\nabc";
+        // 1 行目は 3 文字 (UTF-8 で 9 バイト)。CRLF の \r は含めない
+        assert_eq!(
+            textlint_column_to_character(text, 0, 10, PositionEncoding::Utf8),
+            9
+        );
+        assert_eq!(
+            textlint_column_to_character(text, 0, 10, PositionEncoding::Utf32),
+            3
+        );
+        assert_eq!(
+            textlint_column_to_character(text, 0, 10, PositionEncoding::Utf16),
+            3
+        );
+        // 最終行
+        assert_eq!(
+            textlint_column_to_character(text, 1, 100, PositionEncoding::Utf8),
+            3
+        );
+        assert_eq!(
+            textlint_column_to_character(text, 1, 100, PositionEncoding::Utf32),
+            3
+        );
+        // 本文にない行
+        assert_eq!(
+            textlint_column_to_character(text, 5, 2, PositionEncoding::Utf8),
+            0
+        );
+    }
+
+    #[test]
+    fn textlint_column_to_character_column_zero() {
+        let text = "あいう\nかきく";
+        for enc in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            assert_eq!(
+                textlint_column_to_character(text, 0, 0, enc),
+                0,
+                "enc={enc:?}"
+            );
+            assert_eq!(
+                textlint_column_to_character(text, 1, 0, enc),
+                0,
+                "enc={enc:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn message_position_clamps_degenerate_line_and_column() {
+        let text = "あいう\nかきく";
+        let mut msg = message_with_fix_range([0, 1]);
+
+        for enc in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            msg.line = 0;
+            msg.column = 0;
+            assert_eq!(
+                message_position(text, &msg, enc),
+                Position {
+                    line: 0,
+                    character: 0
+                },
+                "enc={enc:?}"
+            );
+
+            // line 0 は 1 行目として扱う
+            msg.line = 0;
+            msg.column = 2;
+            assert_eq!(message_position(text, &msg, enc).line, 0, "enc={enc:?}");
+
+            msg.line = 2;
+            msg.column = 0;
+            assert_eq!(
+                message_position(text, &msg, enc),
+                Position {
+                    line: 1,
+                    character: 0
+                },
+                "enc={enc:?}"
+            );
+        }
+    }
 }
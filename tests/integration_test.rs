@@ -1,6 +1,11 @@
 use std::path::Path;
 
-use ichigyo_ls::textlint::{self, CommandRunner, PositionEncoding, TextlintRunner};
+use futures::StreamExt;
+use ichigyo_ls::server::Backend;
+use ichigyo_ls::textlint::{self, CommandRunner, PositionEncoding, RunOptions, TextlintRunner};
+use serde_json::{json, Value};
+use tower::{Service, ServiceExt};
+use tower_lsp::jsonrpc::Request;
 
 const FIXTURE: &str = include_str!("fixtures/sample.md");
 
@@ -18,7 +23,10 @@ fn work_dir() -> &'static Path {
 #[tokio::test]
 async fn textlint_parses_fixture() {
     let runner = CommandRunner;
-    let results = runner.run(fixture_path(), work_dir()).await.unwrap();
+    let results = runner
+        .run(fixture_path(), work_dir(), &RunOptions::default())
+        .await
+        .unwrap();
 
     assert_eq!(results.len(), 1);
     assert!(
@@ -46,10 +54,31 @@ async fn textlint_parses_fixture() {
     assert_eq!(fix.text, "2つ");
 }
 
+#[tokio::test]
+async fn textlint_lints_stdin_like_the_file() {
+    let runner = CommandRunner;
+    let results = runner
+        .run_stdin(FIXTURE, fixture_path(), work_dir(), &RunOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+    let futatsu = results[0]
+        .messages
+        .iter()
+        .find(|m| m.message.contains("ふたつ"))
+        .expect("should find 'ふたつ' error");
+    assert_eq!(futatsu.rule_id, "prh");
+    assert_eq!(futatsu.fix.as_ref().expect("should have fix").text, "2つ");
+}
+
 #[tokio::test]
 async fn fix_range_converts_to_correct_position() {
     let runner = CommandRunner;
-    let results = runner.run(fixture_path(), work_dir()).await.unwrap();
+    let results = runner
+        .run(fixture_path(), work_dir(), &RunOptions::default())
+        .await
+        .unwrap();
 
     let futatsu = results[0]
         .messages
@@ -83,7 +112,10 @@ async fn fix_range_converts_to_correct_position() {
 #[tokio::test]
 async fn applying_text_edit_produces_correct_result() {
     let runner = CommandRunner;
-    let results = runner.run(fixture_path(), work_dir()).await.unwrap();
+    let results = runner
+        .run(fixture_path(), work_dir(), &RunOptions::default())
+        .await
+        .unwrap();
 
     let futatsu = results[0]
         .messages
@@ -109,3 +141,166 @@ async fn applying_text_edit_produces_correct_result() {
         "should not contain ふたつ anymore"
     );
 }
+
+/// fix.range (オフセット) から求めた開始位置と、line / column から求めた
+/// 診断の開始位置がどのエンコーディングでも一致することを確認する。
+/// どちらかの単位の扱いが変わるとずれるので、その検出用。
+#[test]
+fn fix_start_matches_diagnostic_start_for_each_encoding() {
+    // textlint が fixture に対して報告する "ふたつ" のメッセージ
+    let futatsu: textlint::TextlintMessage = serde_json::from_value(serde_json::json!({
+        "ruleId": "prh",
+        "message": "ふたつ => 2つ",
+        "line": 3,
+        "column": 1,
+        "severity": 2,
+        "fix": { "range": [7, 10], "text": "2つ" }
+    }))
+    .unwrap();
+    let fix = futatsu.fix.as_ref().unwrap();
+
+    for encoding in [
+        PositionEncoding::Utf8,
+        PositionEncoding::Utf16,
+        PositionEncoding::Utf32,
+    ] {
+        let fix_start = textlint::offset_to_position(FIXTURE, fix.range[0], encoding);
+        let diagnostic_start = textlint::message_position(FIXTURE, &futatsu, encoding);
+        assert_eq!(fix_start, diagnostic_start, "encoding={encoding:?}");
+
+        let range = textlint::message_range(FIXTURE, &futatsu, encoding);
+        assert_eq!(range.start, diagnostic_start, "encoding={encoding:?}");
+    }
+}
+
+/// fixture に対して textlint が実際に出力する JSON を返すランナー。
+struct FixtureRunner;
+
+const FIXTURE_OUTPUT: &str = r#"[{"filePath": "sample.md", "messages": [{"ruleId": "prh", "message": "ふたつ => 2つ", "line": 3, "column": 1, "index": 7, "severity": 2, "fix": {"range": [7, 10], "text": "2つ"}}]}]"#;
+
+#[async_trait::async_trait]
+impl TextlintRunner for FixtureRunner {
+    async fn run(
+        &self,
+        _file_path: &Path,
+        _work_dir: &Path,
+        _options: &RunOptions,
+    ) -> anyhow::Result<Vec<textlint::TextlintResult>> {
+        Ok(serde_json::from_str(FIXTURE_OUTPUT)?)
+    }
+
+    async fn run_stdin(
+        &self,
+        text: &str,
+        file_path: &Path,
+        work_dir: &Path,
+        options: &RunOptions,
+    ) -> anyhow::Result<Vec<textlint::TextlintResult>> {
+        assert_eq!(text, FIXTURE);
+        self.run(file_path, work_dir, options).await
+    }
+
+    async fn fix_stdin(
+        &self,
+        text: &str,
+        _file_path: &Path,
+        _work_dir: &Path,
+        _options: &RunOptions,
+    ) -> anyhow::Result<String> {
+        Ok(text.to_string())
+    }
+
+    async fn warmup(&self, _work_dir: &Path, _options: &RunOptions) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn version(&self, _work_dir: &Path, _options: &RunOptions) -> anyhow::Result<String> {
+        Ok("14.0.0".to_string())
+    }
+}
+
+/// LSP の Position を `encoding` の単位で解釈し、バイトオフセットに変換する。
+fn byte_offset(text: &str, position: &Value, encoding: &str) -> usize {
+    let line = position["line"].as_u64().unwrap() as usize;
+    let character = position["character"].as_u64().unwrap() as usize;
+    let line_start: usize = text.split('\n').take(line).map(|l| l.len() + 1).sum();
+    let mut units = 0;
+    for (offset, ch) in text[line_start..].char_indices() {
+        if units >= character {
+            return line_start + offset;
+        }
+        units += match encoding {
+            "utf-8" => ch.len_utf8(),
+            "utf-16" => ch.len_utf16(),
+            _ => 1,
+        };
+    }
+    text.len()
+}
+
+/// in-memory の LSP サーバーに fixture を開かせ、code action の WorkspaceEdit を
+/// 手元で適用して "ふたつ" が "2つ" に置き換わることを確認する。
+#[tokio::test]
+async fn code_action_edit_fixes_fixture_end_to_end() {
+    let uri = tower_lsp::lsp_types::Url::from_file_path(fixture_path()).unwrap();
+
+    for encoding in ["utf-8", "utf-16", "utf-32"] {
+        let (mut service, mut socket) = Backend::service(FixtureRunner);
+        // publishDiagnostics などのサーバーからの通知を読み捨てる
+        tokio::spawn(async move { while socket.next().await.is_some() {} });
+
+        let initialize = Request::build("initialize")
+            .params(json!({
+                "capabilities": { "general": { "positionEncodings": [encoding] } },
+            }))
+            .id(1)
+            .finish();
+        let response = service.ready().await.unwrap().call(initialize).await;
+        let (_, result) = response.unwrap().unwrap().into_parts();
+        assert_eq!(
+            result.unwrap()["capabilities"]["positionEncoding"],
+            encoding
+        );
+
+        let did_open = Request::build("textDocument/didOpen")
+            .params(json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "markdown",
+                    "version": 1,
+                    "text": FIXTURE,
+                },
+            }))
+            .finish();
+        service.ready().await.unwrap().call(did_open).await.unwrap();
+
+        let code_action = Request::build("textDocument/codeAction")
+            .params(json!({
+                "textDocument": { "uri": uri },
+                "range": {
+                    "start": { "line": 2, "character": 0 },
+                    "end": { "line": 2, "character": 0 },
+                },
+                "context": { "diagnostics": [], "only": ["quickfix"] },
+            }))
+            .id(2)
+            .finish();
+        let response = service.ready().await.unwrap().call(code_action).await;
+        let (_, result) = response.unwrap().unwrap().into_parts();
+        let actions = result.unwrap();
+        let edits = &actions[0]["edit"]["changes"][uri.as_str()];
+        assert_eq!(edits.as_array().unwrap().len(), 1, "encoding={encoding}");
+
+        let edit = &edits[0];
+        let start = byte_offset(FIXTURE, &edit["range"]["start"], encoding);
+        let end = byte_offset(FIXTURE, &edit["range"]["end"], encoding);
+        let fixed = format!(
+            "{}{}{}",
+            &FIXTURE[..start],
+            edit["newText"].as_str().unwrap(),
+            &FIXTURE[end..]
+        );
+        assert!(fixed.contains("2つの項目がある"), "encoding={encoding}");
+        assert!(!fixed.contains("ふたつ"), "encoding={encoding}");
+    }
+}